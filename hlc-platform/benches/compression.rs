@@ -44,9 +44,9 @@ fn generate_test_data() -> Vec<(&'static str, Vec<u8>)> {
         }),
         
         // Text-like data
-        ("text_1kb", "The quick brown fox jumps over the lazy dog. ".repeat(21)[..1024].as_bytes().to_vec()),
-        ("text_10kb", "The quick brown fox jumps over the lazy dog. ".repeat(227)[..10 * 1024].as_bytes().to_vec()),
-        ("text_100kb", "The quick brown fox jumps over the lazy dog. ".repeat(2275)[..100 * 1024].as_bytes().to_vec()),
+        ("text_1kb", "The quick brown fox jumps over the lazy dog. ".repeat(21).as_bytes()[..1024].to_vec()),
+        ("text_10kb", "The quick brown fox jumps over the lazy dog. ".repeat(227).as_bytes()[..10 * 1024].to_vec()),
+        ("text_100kb", "The quick brown fox jumps over the lazy dog. ".repeat(2275).as_bytes()[..100 * 1024].to_vec()),
         
         // Random data (difficult to compress)
         ("random_1kb", (0..1024).map(|i| ((i * 17 + 42) % 256) as u8).collect()),
@@ -378,8 +378,8 @@ fn bench_realistic_data(c: &mut Criterion) {
         ("log_data", {
             let mut data = Vec::new();
             let timestamp_base = 1234567890u32;
-            let log_levels = [b"INFO", b"WARN", b"ERROR", b"DEBUG"];
-            let messages = [
+            let log_levels: [&[u8]; 4] = [b"INFO", b"WARN", b"ERROR", b"DEBUG"];
+            let messages: [&[u8]; 5] = [
                 b"User login successful",
                 b"Database connection established",
                 b"Cache miss for key",
@@ -393,11 +393,11 @@ fn bench_realistic_data(c: &mut Criterion) {
                 data.push(b' ');
                 
                 // Log level (repeated patterns)
-                data.extend(log_levels[i % log_levels.len()]);
+                data.extend(log_levels[i as usize % log_levels.len()]);
                 data.push(b' ');
                 
                 // Message (some repetition)
-                data.extend(messages[i % messages.len()]);
+                data.extend(messages[i as usize % messages.len()]);
                 data.push(b'\n');
             }
             data
@@ -344,6 +344,7 @@ fn advanced_features_example() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 /// Helper function to demonstrate error handling patterns
+#[allow(dead_code)]
 fn demonstrate_error_handling() -> Result<(), hlc::HlcError> {
     use hlc::HlcError;
     
@@ -374,6 +375,7 @@ fn demonstrate_error_handling() -> Result<(), hlc::HlcError> {
 }
 
 /// Helper function to show performance measurement
+#[allow(dead_code)]
 fn measure_performance(data: &[u8], config: &HlcConfig) -> Result<(), Box<dyn std::error::Error>> {
     let iterations = 5;
     let mut total_compression_time = std::time::Duration::from_secs(0);
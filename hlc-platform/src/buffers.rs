@@ -0,0 +1,66 @@
+//! Per-thread pools of reusable byte buffers for the chunk hot path
+//! Trial encodings that lose, and intermediate transform outputs, are handed
+//! back here and become the output buffers of later zstd calls on the same
+//! rayon thread, so steady-state compression allocates little
+
+use std::cell::RefCell;
+
+/// Buffers kept per thread; enough for the candidates of one chunk
+const MAX_POOLED: usize = 8;
+
+/// Larger buffers are freed instead of pooled
+const MAX_POOLED_CAPACITY: usize = 16 << 20;
+
+thread_local! {
+    static POOL: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An empty buffer with at least `capacity` bytes reserved, reused from this
+/// thread's pool when one is available
+pub(crate) fn take(capacity: usize) -> Vec<u8> {
+    let pooled = POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        // Prefer a buffer that is already big enough
+        let index = pool.iter().position(|b| b.capacity() >= capacity).or(pool.len().checked_sub(1))?;
+        Some(pool.swap_remove(index))
+    });
+
+    let mut buffer = pooled.unwrap_or_default();
+    buffer.clear();
+    buffer.reserve(capacity);
+    buffer
+}
+
+/// Returns a buffer that is no longer needed to this thread's pool
+pub(crate) fn give(buffer: Vec<u8>) {
+    if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_CAPACITY {
+        return;
+    }
+    POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.len() < MAX_POOLED {
+            pool.push(buffer);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let mut buffer = take(1000);
+        buffer.extend_from_slice(b"old contents");
+        let address = buffer.as_ptr();
+        give(buffer);
+
+        let reused = take(500);
+        assert!(reused.is_empty());
+        assert!(reused.capacity() >= 1000);
+        assert_eq!(reused.as_ptr(), address);
+
+        give(Vec::new());
+        assert!(take(10).capacity() >= 10);
+    }
+}
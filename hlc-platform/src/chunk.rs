@@ -1,17 +1,37 @@
-use crate::config::HlcConfig;
+use crate::buffers;
+use crate::config::{HlcConfig, HlcMode};
 use crate::container::{CompressedChunk, PipelineFlags, calculate_checksum};
 use crate::error::HlcError;
-use crate::transforms::{analyzer, delta, entropy, rle, dictionary};
+use crate::recipe::{Recipe, RecipeStep};
+use crate::search;
+use crate::strategy::{ChunkContext, Strategy};
+use crate::transforms::{analyzer, entropy, precomp, registry};
+use crate::transforms::analyzer::{Analysis, DataKind};
+use crate::transforms::entropy::Codec;
+use fastcdc::v2020 as cdc;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct RawChunk {
     pub id: usize,
+    /// Byte offset of the chunk in the input it was split from
+    pub offset: u64,
     pub data: Vec<u8>,
 }
 
 impl RawChunk {
     pub fn new(id: usize, data: Vec<u8>) -> Self {
-        Self { id, data }
+        Self { id, offset: 0, data }
+    }
+
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
     }
 
     pub fn size(&self) -> usize {
@@ -23,53 +43,245 @@ impl RawChunk {
     }
 }
 
+/// Longest tail of the preceding chunk used as a zstd prefix
+pub const MAX_PREFIX_LEN: usize = 1 << 20;
+
+/// Which path chose a chunk's encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decision {
+    /// The analyzer's shortlist
+    #[default]
+    Auto,
+    /// `HlcConfig::pipeline`, without analysis
+    Pinned,
+    /// The strategy callback
+    Strategy,
+    /// The exhaustive search, which beat the shortlist
+    Search,
+    /// Detected or hinted as incompressible and stored without any trials
+    Incompressible,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Decision::Auto => "auto",
+            Decision::Pinned => "pinned",
+            Decision::Strategy => "strategy",
+            Decision::Search => "search",
+            Decision::Incompressible => "incompressible",
+        })
+    }
+}
+
+/// A chain encoded in full while choosing a chunk's encoding
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriedChain {
+    /// Steps as applied, the entropy stage included when it paid off
+    pub steps: Vec<RecipeStep>,
+    /// The analyzer's prediction, for shortlisted candidates
+    pub predicted_size: Option<usize>,
+    pub size: usize,
+}
+
+/// How a chunk's encoding was chosen
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Trace {
+    pub decision: Decision,
+    /// The analysis and the time it took, when one ran
+    pub analysis: Option<(Analysis, Duration)>,
+    /// Chains encoded in full, in the order tried
+    pub tried: Vec<TriedChain>,
+}
+
 /// Process a single chunk through the HLC compression pipeline
 pub fn process_chunk(chunk: RawChunk, config: &HlcConfig) -> Result<CompressedChunk, HlcError> {
-    let original_size = chunk.data.len();
+    process_chunk_with_prefix(chunk, &[], config)
+}
+
+/// Like `process_chunk`, priming the zstd entropy stage with the tail of
+/// `previous`, the raw data of the chunk before this one
+///
+/// The result records the prefix length only when the final encoding used
+/// it; pinned pipelines and search results are always encoded without one
+pub fn process_chunk_with_prefix(
+    chunk: RawChunk,
+    previous: &[u8],
+    config: &HlcConfig,
+) -> Result<CompressedChunk, HlcError> {
+    encode_chunk(chunk.id, chunk.offset, Cow::Owned(chunk.data), previous, config, &mut Trace::default())
+}
+
+/// Like `process_chunk_with_prefix` for a borrowed chunk; its data is only
+/// copied if the chunk ends up stored
+pub(crate) fn process_chunk_ref(
+    chunk: &RawChunk,
+    previous: &[u8],
+    config: &HlcConfig,
+) -> Result<CompressedChunk, HlcError> {
+    encode_chunk(chunk.id, chunk.offset, Cow::Borrowed(&chunk.data), previous, config, &mut Trace::default())
+}
+
+/// Like `process_chunk_ref` without a prefix, also reporting how the encoding
+/// was chosen
+pub(crate) fn process_chunk_traced(chunk: &RawChunk, config: &HlcConfig) -> Result<(CompressedChunk, Trace), HlcError> {
+    let mut trace = Trace::default();
+    let compressed = encode_chunk(chunk.id, chunk.offset, Cow::Borrowed(&chunk.data), &[], config, &mut trace)?;
+    Ok((compressed, trace))
+}
+
+fn encode_chunk(
+    id: usize,
+    offset: u64,
+    chunk_data: Cow<'_, [u8]>,
+    previous: &[u8],
+    config: &HlcConfig,
+    trace: &mut Trace,
+) -> Result<CompressedChunk, HlcError> {
+    let prefix = &previous[previous.len().saturating_sub(MAX_PREFIX_LEN)..];
+    let original_size = chunk_data.len();
     if original_size == 0 {
-        let checksum = calculate_checksum(&chunk.data, config.checksum);
-        return Ok(CompressedChunk::new(chunk.id, chunk.data, original_size, checksum));
+        let checksum = calculate_checksum(&chunk_data, config.checksum);
+        return Ok(CompressedChunk::new(id, chunk_data.into_owned(), original_size, checksum));
     }
 
-    let checksum = calculate_checksum(&chunk.data, config.checksum);
+    let checksum = calculate_checksum(&chunk_data, config.checksum);
+
+    // A pinned pipeline bypasses analysis and user transform estimates entirely
+    if let Some(pipeline) = &config.pipeline {
+        let mut recipe = pipeline.resolve(&config.registry, config.entropy_level)?;
+        let mut data = recipe.encode_with_config(&chunk_data, config)?;
+        trace.decision = Decision::Pinned;
+        trace.tried.push(TriedChain { steps: recipe.steps.clone(), predicted_size: None, size: data.len() });
+        if recipe.is_stored() || data.len() >= original_size {
+            recipe = Recipe::new();
+            buffers::give(std::mem::replace(&mut data, chunk_data.into_owned()));
+        }
+
+        return Ok(CompressedChunk {
+            id,
+            recipe,
+            reference: None,
+            prefix_len: 0,
+            original_checksum: checksum,
+            original_size: original_size as u32,
+            compressed_size: data.len() as u32,
+            data,
+        });
+    }
+
+    // Embedded deflate streams are inflated first, so their content is no
+    // longer mistaken for incompressible data
+    let expanded = if config.precompression { precomp::expand(&chunk_data) } else { None };
+
+    // Already-compressed and encrypted content is stored without any trials;
+    // a hint settles the question without looking
+    let incompressible = expanded.is_none()
+        && match config.hint {
+            Some(hint) => hint == DataKind::Random,
+            None => analyzer::detect_compressed(&chunk_data, &config.thresholds).is_some(),
+        };
+    if incompressible {
+        trace.decision = Decision::Incompressible;
+        return Ok(CompressedChunk::new(id, chunk_data.into_owned(), original_size, checksum));
+    }
+
+    let mut input = chunk_data;
+    let mut recipe = Recipe::new();
+    let mut raw_data = None;
+    if let Some(expanded) = expanded {
+        recipe.push(RecipeStep::new(registry::PRECOMP_ID, Vec::new()));
+        raw_data = Some(std::mem::replace(&mut input, Cow::Owned(expanded)));
+    }
+
+    // 0. Registered user transforms see the chunk before anything else
+    if let Some((encoded, user_recipe)) = apply_user_transforms(&input, config)? {
+        recipe.steps.extend(user_recipe.steps);
+        let previous = std::mem::replace(&mut input, Cow::Owned(encoded));
+        raw_data.get_or_insert(previous);
+    }
     
-    // 1. Analyze the chunk to determine the best compression strategy
-    let strategy = analyzer::analyze_chunk(&chunk.data, config.mode);
-
-    // 2. Apply pre-processing transforms based on the strategy
-    let (mut transformed_data, mut flags) = apply_transforms(chunk.data, &strategy)?;
-
-    // 3. Apply entropy coding if the data isn't stored raw
-    if !flags.contains(PipelineFlags::STORED) {
-        match entropy::encode(&transformed_data, config.entropy_level) {
-            Ok(entropy_compressed) => {
-                // Only use entropy compression if it actually reduces size
-                if entropy_compressed.len() < transformed_data.len() {
-                    transformed_data = entropy_compressed;
-                    flags |= PipelineFlags::ENTROPY;
-                } else {
-                    // Entropy coding didn't help, store as-is
-                    flags = PipelineFlags::STORED;
-                    transformed_data = strategy.original_data.clone();
+    // 1. Ask the strategy callback, if any, how to encode the chunk
+    let context = ChunkContext::new(id, offset, &input, config);
+    let strategy = config.strategy.as_ref().map_or(Strategy::Auto, |f| f.call(&context));
+    if strategy != Strategy::Auto {
+        trace.decision = Decision::Strategy;
+    }
+
+    let mut best: Option<(Vec<u8>, Vec<RecipeStep>)> = None;
+    let mut unprimed = false;
+    match strategy {
+        Strategy::Auto => {
+            // 2. Encode the analyzer's shortlisted candidates in full,
+            // keeping the smallest result
+            let (analysis, analysis_time) = context.into_analysis()?;
+            for candidate in shortlist(&analysis, config.mode) {
+                let (encoded, steps) = encode_plan(&input, &candidate.steps, candidate.entropy_codec, prefix, config)?;
+                trace.tried.push(TriedChain {
+                    steps: steps.clone(),
+                    predicted_size: Some(candidate.predicted_size),
+                    size: encoded.len(),
+                });
+                match &best {
+                    Some((data, _)) if data.len() <= encoded.len() => buffers::give(encoded),
+                    _ => {
+                        if let Some((data, _)) = best.replace((encoded, steps)) {
+                            buffers::give(data);
+                        }
+                    }
                 }
             }
-            Err(_) => {
-                // Entropy coding failed, store as raw
-                flags = PipelineFlags::STORED;
-                transformed_data = strategy.original_data.clone();
+
+            // Optionally compare against real trial encodes of other transform chains
+            if let Some(budget) = &config.search {
+                if let Some((encoded, steps)) = search::search_chunk(&input, config, budget, &mut trace.tried)? {
+                    match &best {
+                        Some((data, _)) if data.len() <= encoded.len() => buffers::give(encoded),
+                        _ => {
+                            if let Some((data, _)) = best.replace((encoded, steps)) {
+                                buffers::give(data);
+                            }
+                            unprimed = true;
+                            trace.decision = Decision::Search;
+                        }
+                    }
+                }
             }
+            trace.analysis = Some((analysis, analysis_time));
+        }
+        Strategy::Store => trace.analysis = context.take_analysis(),
+        Strategy::Steps(steps) => {
+            trace.analysis = context.take_analysis();
+            check_strategy_steps(&steps, &config.registry)?;
+            let (encoded, steps) = encode_exact(&input, steps, prefix, config)?;
+            trace.tried.push(TriedChain { steps: steps.clone(), predicted_size: None, size: encoded.len() });
+            best = Some((encoded, steps));
+        }
+        Strategy::Pipeline(pipeline) => {
+            trace.analysis = context.take_analysis();
+            let pinned = pipeline.resolve(&config.registry, config.entropy_level)?;
+            let encoded = pinned.encode_with_config(&input, config)?;
+            trace.tried.push(TriedChain { steps: pinned.steps.clone(), predicted_size: None, size: encoded.len() });
+            best = Some((encoded, pinned.steps));
+            unprimed = true;
         }
     }
-
-    // 4. Final check: if compressed data is not smaller, store original
-    if transformed_data.len() >= original_size {
-        flags = PipelineFlags::STORED;
-        transformed_data = strategy.original_data.clone();
+    let chosen = best.is_some();
+    let (mut transformed_data, steps) = best.unwrap_or_default();
+    recipe.steps.extend(steps);
+
+    // 3. Final check: if compressed data is not smaller, store original
+    if !chosen || recipe.is_stored() || transformed_data.len() >= original_size {
+        recipe = Recipe::new();
+        buffers::give(std::mem::replace(&mut transformed_data, raw_data.unwrap_or(input).into_owned()));
     }
 
+    let primed = !unprimed && recipe.steps.last().is_some_and(|s| s.is_entropy()) && recipe.codec() == Codec::Zstd;
     Ok(CompressedChunk {
-        id: chunk.id,
-        flags,
+        id,
+        recipe,
+        reference: None,
+        prefix_len: if primed { prefix.len() } else { 0 },
         original_checksum: checksum,
         original_size: original_size as u32,
         compressed_size: transformed_data.len() as u32,
@@ -77,48 +289,252 @@ pub fn process_chunk(chunk: RawChunk, config: &HlcConfig) -> Result<CompressedCh
     })
 }
 
-/// Apply the selected transforms to the data
+/// Apply the registered user transforms whose estimate says they help, in id
+/// order; returns `None` when none of them applied
+fn apply_user_transforms(data: &[u8], config: &HlcConfig) -> Result<Option<(Vec<u8>, Recipe)>, HlcError> {
+    let mut current: Option<Vec<u8>> = None;
+    let mut recipe = Recipe::new();
+
+    for transform in config.registry.user_transforms() {
+        let input = current.as_deref().unwrap_or(data);
+        if transform.estimate(input) > 0.0 {
+            current = Some(transform.encode(input, &[])?);
+            recipe.push(RecipeStep::new(transform.id(), Vec::new()));
+        }
+    }
+
+    Ok(current.map(|encoded| (encoded, recipe)))
+}
+
+/// Candidates worth encoding in full: the best prediction, plus runners-up
+/// within its uncertainty, up to two (three in Max mode)
+///
+/// A fully trialled chunk has confidence 1.0, so only exact ties are retried
+fn shortlist(analysis: &analyzer::Analysis, mode: HlcMode) -> impl Iterator<Item = &analyzer::Candidate> {
+    let limit = if mode == HlcMode::Max { 3 } else { 2 };
+    let margin = analysis.best().map_or(0.0, |best| best.predicted_size as f32 * (2.0 - best.confidence));
+    analysis
+        .candidates
+        .iter()
+        .take(limit)
+        .take_while(move |c| c.predicted_size as f32 <= margin)
+}
+
+/// Run one transform plan followed by the entropy stage, returning the
+/// encoded data and the recipe steps that were actually applied
+fn encode_plan(
+    input: &[u8],
+    plan: &[RecipeStep],
+    hint: Codec,
+    prefix: &[u8],
+    config: &HlcConfig,
+) -> Result<(Vec<u8>, Vec<RecipeStep>), HlcError> {
+    let (transformed, mut steps) = apply_transforms(input, plan, config)?;
+    let data = transformed.as_deref().unwrap_or(input);
+
+    let (codec, entropy_compressed) = entropy_stage(data, hint, prefix, config)?;
+    // Only use entropy compression if it actually reduces size;
+    // otherwise keep the transformed data
+    if entropy_compressed.len() < data.len() {
+        steps.push(RecipeStep::entropy(codec, config.entropy_level));
+        if let Some(transformed) = transformed {
+            buffers::give(transformed);
+        }
+        return Ok((entropy_compressed, steps));
+    }
+
+    buffers::give(entropy_compressed);
+    Ok((transformed.unwrap_or_else(|| input.to_vec()), steps))
+}
+
+/// Checks a strategy callback's steps against the registry, so unknown ids and
+/// bad params fail as configuration errors before any encoding
+fn check_strategy_steps(steps: &[RecipeStep], registry: &registry::TransformRegistry) -> Result<(), HlcError> {
+    for step in steps {
+        let invalid = |reason: &str| {
+            HlcError::ConfigError(format!("Invalid strategy step '{}': {}", step.label(registry), reason))
+        };
+        if step.is_entropy() {
+            return Err(invalid("the entropy stage is added by the encoder"));
+        }
+        let transform = registry.get(step.id).ok_or_else(|| invalid("no transform has this id"))?;
+        transform.check_params(&step.params).map_err(|e| match e {
+            HlcError::TransformError(reason) | HlcError::ConfigError(reason) => invalid(&reason),
+            e => e,
+        })?;
+    }
+    Ok(())
+}
+
+/// Apply every step of `steps` in order, whether or not it shrinks the data,
+/// then the entropy stage with the configured codec
+fn encode_exact(
+    input: &[u8],
+    steps: Vec<RecipeStep>,
+    prefix: &[u8],
+    config: &HlcConfig,
+) -> Result<(Vec<u8>, Vec<RecipeStep>), HlcError> {
+    let recipe = Recipe { steps };
+    let transformed = recipe.encode(input, &config.registry)?;
+    let (codec, encoded) = entropy_stage(&transformed, config.codec, prefix, config)?;
+    buffers::give(transformed);
+
+    let mut steps = recipe.steps;
+    steps.push(RecipeStep::entropy(codec, config.entropy_level));
+    Ok((encoded, steps))
+}
+
+/// Run the entropy stage, returning the smallest encoding found
+///
+/// The configured general-purpose codec is always tried. When the analyzer's
+/// trials favoured a native coder, and always in Max mode, both Huffman and
+/// rANS are tried as well, since they are cheap and which one wins depends on
+/// how skewed the data is; sampled windows understate how well they do after
+/// a BWT. The general-purpose codec still wins when the transforms left
+/// matches behind. zstd is primed with `prefix` when one is given
+fn entropy_stage(
+    data: &[u8],
+    hint: Codec,
+    prefix: &[u8],
+    config: &HlcConfig,
+) -> Result<(Codec, Vec<u8>), HlcError> {
+    let encoded = match config.codec {
+        Codec::Zstd => entropy::encode_zstd(data, config.entropy_level, prefix, &config.zstd)?,
+        codec => entropy::encode_with(codec, data, config.entropy_level)?,
+    };
+    let mut best = (config.codec, encoded);
+
+    if hint != Codec::Zstd || config.mode == HlcMode::Max {
+        for codec in [Codec::Huffman, Codec::Rans] {
+            let encoded = entropy::encode_with(codec, data, config.entropy_level)?;
+            if encoded.len() < best.1.len() {
+                buffers::give(std::mem::replace(&mut best, (codec, encoded)).1);
+            } else {
+                buffers::give(encoded);
+            }
+        }
+    }
+
+    Ok(best)
+}
+
+/// Apply the planned transforms to the data through the registry
+///
+/// Each transform is only kept if it reduces the data size, except the
+/// size-neutral reorderings (shuffle, delta) whose benefit shows up in the
+/// entropy stage. Returns `None` for the data when nothing was applied
 fn apply_transforms(
-    mut data: Vec<u8>,
-    strategy: &analyzer::CompressionStrategy,
-) -> Result<(Vec<u8>, PipelineFlags), HlcError> {
-    let mut flags = PipelineFlags::empty();
-    let original_len = data.len();
-
-    // Apply transforms in order: RLE -> Delta -> Dictionary
-    // Each transform is only applied if it reduces the data size
-    
-    if strategy.use_rle {
-        let rle_data = rle::encode(&data);
-        if rle_data.len() < data.len() {
-            data = rle_data;
-            flags |= PipelineFlags::RLE;
+    input: &[u8],
+    plan: &[RecipeStep],
+    config: &HlcConfig,
+) -> Result<(Option<Vec<u8>>, Vec<RecipeStep>), HlcError> {
+    let mut current: Option<Vec<u8>> = None;
+    let mut applied = Vec::new();
+
+    for step in plan {
+        let data = current.as_deref().unwrap_or(input);
+        let encoded = config.registry.require(step.id)?.encode(data, &step.params)?;
+        let keep = match step.id {
+            registry::SHUFFLE_ID | registry::DELTA_ID => encoded.len() <= data.len() + reorder_overhead(step.id),
+            _ => encoded.len() < data.len(),
+        };
+        if keep {
+            if let Some(previous) = current.replace(encoded) {
+                buffers::give(previous);
+            }
+            applied.push(step.clone());
+        } else {
+            buffers::give(encoded);
+        }
+    }
+
+    // If the transforms grew the data overall, hand the original to the entropy stage
+    let overhead: usize = applied.iter().map(|s| reorder_overhead(s.id)).sum();
+    match current {
+        Some(data) if data.len() <= input.len() + overhead => Ok((Some(data), applied)),
+        Some(data) => {
+            buffers::give(data);
+            Ok((None, Vec::new()))
         }
+        None => Ok((None, Vec::new())),
     }
+}
 
-    if strategy.use_delta {
-        let delta_data = delta::encode(&data);
-        if delta_data.len() <= data.len() {
-            data = delta_data;
-            flags |= PipelineFlags::DELTA;
+/// Header bytes a size-neutral reordering adds on top of the element data
+fn reorder_overhead(id: u16) -> usize {
+    if id == registry::SHUFFLE_ID {
+        2
+    } else {
+        0
+    }
+}
+
+/// How input is cut into chunks before processing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Chunking {
+    /// Cut every `HlcConfig::chunk_size` bytes
+    #[default]
+    Fixed,
+    /// Cut where the content hash says so (FastCDC), so boundaries survive
+    /// insertions and deletions earlier in the input
+    ContentDefined {
+        min_size: usize,
+        avg_size: usize,
+        max_size: usize,
+    },
+}
+
+impl Chunking {
+    /// Content-defined chunking around `avg_size`, with the conventional
+    /// quarter/four-times bounds
+    pub fn content_defined(avg_size: usize) -> Self {
+        Self::content_defined_with(avg_size / 4, avg_size, avg_size * 4)
+    }
+
+    /// Content-defined chunking with explicit bounds, clamped to the ranges
+    /// FastCDC supports and put in order
+    pub fn content_defined_with(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let (min_size, avg_size, max_size) = clamp_cdc_sizes(min_size, avg_size, max_size);
+        Self::ContentDefined {
+            min_size: min_size as usize,
+            avg_size: avg_size as usize,
+            max_size: max_size as usize,
         }
     }
+}
 
-    if strategy.use_dictionary {
-        let dict_data = dictionary::encode(&data);
-        if dict_data.len() < data.len() {
-            data = dict_data;
-            flags |= PipelineFlags::DICTIONARY;
+fn clamp_cdc_sizes(min_size: usize, avg_size: usize, max_size: usize) -> (u32, u32, u32) {
+    let clamp = |size: usize, lo: u32, hi: u32| size.clamp(lo as usize, hi as usize) as u32;
+    let avg_size = clamp(avg_size, cdc::AVERAGE_MIN, cdc::AVERAGE_MAX);
+    let min_size = clamp(min_size, cdc::MINIMUM_MIN, cdc::MINIMUM_MAX).min(avg_size);
+    let max_size = clamp(max_size, cdc::MAXIMUM_MIN, cdc::MAXIMUM_MAX).max(avg_size);
+    (min_size, avg_size, max_size)
+}
+
+/// Split data into chunks the way `config` asks for
+pub fn split_for_config(data: &[u8], config: &HlcConfig) -> Vec<RawChunk> {
+    match config.chunking {
+        Chunking::Fixed => split_into_chunks(data, config.chunk_size),
+        Chunking::ContentDefined { min_size, avg_size, max_size } => {
+            split_content_defined(data, min_size, avg_size, max_size)
         }
     }
+}
 
-    // If no transforms were beneficial, mark as stored
-    if flags.is_empty() || data.len() >= original_len {
-        flags = PipelineFlags::STORED;
-        data = strategy.original_data.clone();
+/// Split data at content-defined boundaries using FastCDC
+///
+/// Sizes outside FastCDC's supported ranges are clamped, see
+/// `Chunking::content_defined_with`
+pub fn split_content_defined(data: &[u8], min_size: usize, avg_size: usize, max_size: usize) -> Vec<RawChunk> {
+    if data.is_empty() {
+        return vec![RawChunk::new(0, Vec::new())];
     }
 
-    Ok((data, flags))
+    let (min_size, avg_size, max_size) = clamp_cdc_sizes(min_size, avg_size, max_size);
+    cdc::FastCDC::new(data, min_size, avg_size, max_size)
+        .enumerate()
+        .map(|(id, cut)| RawChunk::new(id, data[cut.offset..cut.offset + cut.length].to_vec()).with_offset(cut.offset as u64))
+        .collect()
 }
 
 /// Split data into chunks for processing
@@ -129,7 +545,38 @@ pub fn split_into_chunks(data: &[u8], chunk_size: usize) -> Vec<RawChunk> {
 
     data.chunks(chunk_size)
         .enumerate()
-        .map(|(id, chunk_data)| RawChunk::new(id, chunk_data.to_vec()))
+        .map(|(id, chunk_data)| RawChunk::new(id, chunk_data.to_vec()).with_offset((id * chunk_size) as u64))
+        .collect()
+}
+
+/// Finds chunks whose content repeats an earlier chunk
+///
+/// Returns, for each chunk, the index of the first chunk with identical content
+/// when there is an earlier one. Chunks are keyed on their SHA-256 digest and
+/// the bytes are compared as well, so a match is never a false positive
+pub fn find_duplicates(chunks: &[RawChunk]) -> Vec<Option<usize>> {
+    let digests: Vec<[u8; 32]> = chunks
+        .par_iter()
+        .map(|chunk| Sha256::digest(&chunk.data).into())
+        .collect();
+
+    let mut first_seen: HashMap<[u8; 32], usize> = HashMap::new();
+    digests
+        .iter()
+        .enumerate()
+        .map(|(index, digest)| {
+            if chunks[index].is_empty() {
+                return None;
+            }
+            match first_seen.get(digest) {
+                Some(&first) if chunks[first].data == chunks[index].data => Some(first),
+                Some(_) => None,
+                None => {
+                    first_seen.insert(*digest, index);
+                    None
+                }
+            }
+        })
         .collect()
 }
 
@@ -145,29 +592,28 @@ pub fn merge_chunks(chunks: &[RawChunk]) -> Vec<u8> {
     result
 }
 
+/// Maximum number of bytes from each chunk that estimation trial-compresses
+const ESTIMATE_SAMPLE_SIZE: usize = 64 * 1024;
+
 /// Estimate compression effectiveness for a chunk
+///
+/// Runs the real pipeline over a bounded prefix of the chunk and scales the
+/// result, so the estimate tracks what `process_chunk` would actually produce.
 pub fn estimate_compression_ratio(chunk: &RawChunk, config: &HlcConfig) -> f32 {
     if chunk.is_empty() {
         return 1.0;
     }
 
-    let strategy = analyzer::analyze_chunk(&chunk.data, config.mode);
-    
-    // Simple estimation based on entropy and detected patterns
-    let base_ratio = entropy::estimate_compression_ratio(&chunk.data);
-    
-    let mut bonus = 1.0;
-    if strategy.use_rle {
-        bonus += 0.2; // RLE can be very effective for sparse data
-    }
-    if strategy.use_delta {
-        bonus += 0.1; // Delta coding provides moderate improvement
-    }
-    if strategy.use_dictionary {
-        bonus += 0.15; // Dictionary compression can be quite effective
-    }
-    
-    base_ratio * bonus
+    let sample_len = chunk.size().min(ESTIMATE_SAMPLE_SIZE);
+    let sample = RawChunk::new(chunk.id, chunk.data[..sample_len].to_vec());
+
+    let (compressed_sample, header_size) = match process_chunk(sample, config) {
+        Ok(compressed) => (compressed.compressed_size as usize, compressed.header_size()),
+        Err(_) => (sample_len, CompressedChunk::new(0, Vec::new(), 0, 0).header_size()),
+    };
+
+    let scaled = compressed_sample as f32 * (chunk.size() as f32 / sample_len as f32);
+    chunk.size() as f32 / (scaled + header_size as f32)
 }
 
 /// Validate chunk data integrity
@@ -190,7 +636,24 @@ pub struct ChunkStats {
     pub rle_chunks: usize,
     pub delta_chunks: usize,
     pub dictionary_chunks: usize,
+    pub float_xor_chunks: usize,
+    pub shuffle_chunks: usize,
+    pub bwt_chunks: usize,
     pub entropy_chunks: usize,
+    pub huffman_chunks: usize,
+    pub rans_chunks: usize,
+    /// Chunks stored as references to an identical earlier chunk
+    pub reference_chunks: usize,
+    /// Original bytes not stored again thanks to references
+    pub dedup_saved_bytes: u64,
+    /// Chunks whose zstd stage was primed with the preceding chunk
+    pub prefixed_chunks: usize,
+    /// Chunks whose embedded deflate streams were expanded
+    pub precomp_chunks: usize,
+    /// Chunks laid out column by column
+    pub csv_chunks: usize,
+    /// Chunks split into JSON structure and values
+    pub json_chunks: usize,
     pub total_original_size: u64,
     pub total_compressed_size: u64,
 }
@@ -205,20 +668,54 @@ impl ChunkStats {
         self.total_original_size += chunk.original_size as u64;
         self.total_compressed_size += chunk.compressed_size as u64;
 
-        if chunk.flags.contains(PipelineFlags::STORED) {
+        if chunk.is_reference() {
+            self.reference_chunks += 1;
+            self.dedup_saved_bytes += chunk.original_size as u64;
+            return;
+        }
+
+        if chunk.is_prefixed() {
+            self.prefixed_chunks += 1;
+        }
+        if chunk.recipe.steps.iter().any(|step| step.id == registry::PRECOMP_ID) {
+            self.precomp_chunks += 1;
+        }
+        if chunk.recipe.steps.iter().any(|step| step.id == registry::CSV_ID) {
+            self.csv_chunks += 1;
+        }
+        if chunk.recipe.steps.iter().any(|step| step.id == registry::JSON_ID) {
+            self.json_chunks += 1;
+        }
+
+        let flags = chunk.flags();
+        if flags.contains(PipelineFlags::STORED) {
             self.stored_chunks += 1;
         }
-        if chunk.flags.contains(PipelineFlags::RLE) {
+        if flags.contains(PipelineFlags::RLE) {
             self.rle_chunks += 1;
         }
-        if chunk.flags.contains(PipelineFlags::DELTA) {
+        if flags.contains(PipelineFlags::DELTA) {
             self.delta_chunks += 1;
         }
-        if chunk.flags.contains(PipelineFlags::DICTIONARY) {
+        if flags.contains(PipelineFlags::DICTIONARY) {
             self.dictionary_chunks += 1;
         }
-        if chunk.flags.contains(PipelineFlags::ENTROPY) {
+        if flags.contains(PipelineFlags::FLOAT_XOR) {
+            self.float_xor_chunks += 1;
+        }
+        if flags.contains(PipelineFlags::SHUFFLE) {
+            self.shuffle_chunks += 1;
+        }
+        if flags.contains(PipelineFlags::BWT) {
+            self.bwt_chunks += 1;
+        }
+        if flags.contains(PipelineFlags::ENTROPY) {
             self.entropy_chunks += 1;
+            match chunk.codec() {
+                Codec::Huffman => self.huffman_chunks += 1,
+                Codec::Rans => self.rans_chunks += 1,
+                _ => {}
+            }
         }
     }
 
@@ -244,7 +741,6 @@ impl ChunkStats {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::HlcMode;
 
     #[test]
     fn test_chunk_creation() {
@@ -273,6 +769,46 @@ mod tests {
         assert_eq!(chunks[0].data, b"Hello, wor");
         assert_eq!(chunks[1].data, b"ld! This i");
         assert_eq!(chunks[2].data, b"s a test.");
+        assert_eq!(chunks.iter().map(|c| c.offset).collect::<Vec<_>>(), vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn test_content_defined_chunks() {
+        let data: Vec<u8> = (0u64..400_000).map(|i| (i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 56) as u8).collect();
+        let chunks = split_content_defined(&data, 4096, 16 * 1024, 64 * 1024);
+
+        assert!(chunks.len() > 4);
+        assert_eq!(merge_chunks(&chunks), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!((4096..=64 * 1024).contains(&chunk.size()));
+        }
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[1].offset, pair[0].offset + pair[0].size() as u64);
+        }
+
+        // An insertion near the start only disturbs the boundaries around it
+        let mut edited = data[..1000].to_vec();
+        edited.extend_from_slice(b"inserted bytes");
+        edited.extend_from_slice(&data[1000..]);
+        let edited_chunks = split_content_defined(&edited, 4096, 16 * 1024, 64 * 1024);
+
+        let shared = chunks
+            .iter()
+            .filter(|c| edited_chunks.iter().any(|e| e.data == c.data))
+            .count();
+        assert!(shared + 2 >= chunks.len());
+    }
+
+    #[test]
+    fn test_chunking_bounds_are_clamped() {
+        assert_eq!(
+            Chunking::content_defined_with(1, 10, 100),
+            Chunking::ContentDefined { min_size: 64, avg_size: 256, max_size: 1024 }
+        );
+        assert_eq!(
+            Chunking::content_defined(64 * 1024),
+            Chunking::ContentDefined { min_size: 16 * 1024, avg_size: 64 * 1024, max_size: 256 * 1024 }
+        );
     }
 
     #[test]
@@ -298,15 +834,71 @@ mod tests {
         assert!(compressed.original_size > 0);
     }
 
+    #[test]
+    fn test_strategy_fn() {
+        let data: Vec<u8> = (0..4000u32).flat_map(|i| (i * 3).to_le_bytes()).collect();
+        let config = HlcConfig::default().with_strategy_fn(std::sync::Arc::new(|ctx: &ChunkContext| {
+            match ctx.offset {
+                0 => Strategy::Store,
+                _ if ctx.id == 1 => Strategy::Steps(vec![RecipeStep::new(registry::DELTA_ID, vec![4])]),
+                _ => Strategy::Auto,
+            }
+        }));
+
+        let stored = process_chunk(RawChunk::new(0, data.clone()), &config).unwrap();
+        assert!(stored.recipe.is_stored());
+        assert_eq!(stored.data, data);
+
+        let delta = process_chunk(RawChunk::new(1, data.clone()).with_offset(16000), &config).unwrap();
+        assert_eq!(delta.recipe.steps[0], RecipeStep::new(registry::DELTA_ID, vec![4]));
+        assert!(delta.recipe.steps.last().unwrap().is_entropy());
+        assert_eq!(delta.decompress(&config).unwrap().data, data);
+
+        let auto = process_chunk(RawChunk::new(2, data.clone()).with_offset(32000), &config).unwrap();
+        assert!(auto.compressed_size < stored.compressed_size);
+    }
+
+    #[test]
+    fn test_strategy_steps_are_applied_exactly() {
+        let data: Vec<u8> = (0..4000u32).flat_map(|i| (i * 3).to_le_bytes()).collect();
+        let strategy = |steps: Vec<RecipeStep>| {
+            HlcConfig::default().with_strategy_fn(std::sync::Arc::new(move |_: &ChunkContext| {
+                Strategy::Steps(steps.clone())
+            }))
+        };
+
+        // Shuffling after the delta never shrinks the data, and is kept anyway
+        let steps = vec![RecipeStep::new(registry::DELTA_ID, vec![4]), RecipeStep::new(registry::SHUFFLE_ID, vec![4, 0])];
+        let config = strategy(steps.clone()).with_codec(Codec::Huffman);
+        let chunk = process_chunk(RawChunk::new(0, data.clone()), &config).unwrap();
+        assert_eq!(chunk.recipe.steps[..2], steps[..]);
+        assert_ne!(chunk.recipe.codec(), Codec::Zstd);
+        assert_eq!(chunk.decompress(&config).unwrap().data, data);
+
+        for bad in [
+            RecipeStep::new(registry::DELTA_ID, vec![3]),
+            RecipeStep::new(registry::FIRST_USER_TRANSFORM_ID, Vec::new()),
+            RecipeStep::entropy(Codec::Zstd, 3),
+        ] {
+            let result = process_chunk(RawChunk::new(0, data.clone()), &strategy(vec![bad.clone()]));
+            assert!(matches!(result, Err(HlcError::ConfigError(_))), "{:?}", bad);
+        }
+    }
+
     #[test]
     fn test_chunk_stats() {
         let mut stats = ChunkStats::new();
         
         let data = vec![1u8; 100];
         let checksum = calculate_checksum(&data, crate::config::ChecksumType::CRC32);
+        let mut recipe = Recipe::new();
+        recipe.push(RecipeStep::new(registry::RLE_ID, Vec::new()));
+        recipe.push(RecipeStep::entropy(Codec::Zstd, 5));
         let chunk = CompressedChunk {
             id: 0,
-            flags: PipelineFlags::RLE | PipelineFlags::ENTROPY,
+            recipe,
+            reference: None,
+            prefix_len: 0,
             original_checksum: checksum,
             original_size: 100,
             compressed_size: 50,
@@ -323,6 +915,24 @@ mod tests {
         assert_eq!(stats.space_saved_percentage(), 50.0);
     }
 
+    #[test]
+    fn test_find_duplicates() {
+        let chunks = vec![
+            RawChunk::new(0, b"alpha".to_vec()),
+            RawChunk::new(1, b"beta".to_vec()),
+            RawChunk::new(2, b"alpha".to_vec()),
+            RawChunk::new(3, b"beta".to_vec()),
+            RawChunk::new(4, b"alpha".to_vec()),
+        ];
+        assert_eq!(find_duplicates(&chunks), vec![None, None, Some(0), Some(1), Some(0)]);
+
+        let mut stats = ChunkStats::new();
+        stats.add_chunk(&CompressedChunk::new_reference(2, 0, 5, 0));
+        assert_eq!(stats.reference_chunks, 1);
+        assert_eq!(stats.dedup_saved_bytes, 5);
+        assert_eq!(stats.stored_chunks, 0);
+    }
+
     #[test]
     fn test_validate_chunk() {
         let valid_chunk = RawChunk::new(0, vec![1, 2, 3, 4, 5]);
@@ -1,9 +1,16 @@
+use crate::chunk::Chunking;
 use crate::config::{HlcConfig, HlcMode, ChecksumType};
 use crate::error::HlcError;
+use crate::explain;
 use crate::pipeline;
+use crate::recipe::Pipeline;
+use crate::search::SearchBudget;
+use crate::store::ChunkStore;
+use crate::transforms::{Codec, DataKind, Thresholds, ZstdParams};
+use crate::tune;
 use clap::{Parser, Subcommand};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Write};
 use std::path::PathBuf;
 use std::time::Instant;
 
@@ -48,6 +55,28 @@ pub enum Commands {
         #[clap(long, default_value = "crc32")]
         checksum: ChecksumType,
 
+        /// Final-stage codec: lz4, zstd, brotli, xz or deflate
+        #[clap(long, default_value = "zstd")]
+        codec: Codec,
+
+        /// Fixed transform chain instead of automatic analysis,
+        /// e.g. "delta:4,shuffle:8,zstd:19"
+        #[clap(long, value_name = "STAGES")]
+        pipeline: Option<Pipeline>,
+
+        /// Trial-encode candidate transform chains per chunk and keep the
+        /// smallest (slow; meant for archival with --mode max)
+        #[clap(long)]
+        search: bool,
+
+        /// Most transform chains the search tries per chunk
+        #[clap(long, value_name = "N", default_value = "64")]
+        search_candidates: usize,
+
+        /// Time limit per chunk for the search, in milliseconds
+        #[clap(long, value_name = "MS")]
+        search_time: Option<u64>,
+
         /// Number of threads to use (default: all available cores)
         #[clap(short, long)]
         threads: Option<usize>,
@@ -56,6 +85,51 @@ pub enum Commands {
         #[clap(long)]
         chunk_size: Option<usize>,
 
+        /// Cut chunks at content-defined boundaries (FastCDC) averaging the
+        /// chunk size, so unchanged regions of edited files give identical chunks
+        #[clap(long)]
+        cdc: bool,
+
+        /// Store identical chunks once, with later copies as references
+        #[clap(long)]
+        dedup: bool,
+
+        /// Prime each chunk's zstd stage with the chunk before it, in groups of
+        /// N chunks that still decode in parallel
+        #[clap(long, value_name = "N")]
+        context_group: Option<usize>,
+
+        /// zstd long-distance matching with a 2^WINDOW_LOG byte window
+        /// (default 27, like `zstd --long`)
+        #[clap(long, value_name = "WINDOW_LOG", num_args = 0..=1, default_missing_value = "27")]
+        long: Option<u32>,
+
+        /// zstd window size as a power of two (10-31)
+        #[clap(long, value_name = "LOG")]
+        window_log: Option<u32>,
+
+        /// Record the decoded size in each zstd frame
+        #[clap(long)]
+        content_size: bool,
+
+        /// Add zstd's own checksum to each frame
+        #[clap(long)]
+        zstd_checksum: bool,
+
+        /// What the input contains, instead of detecting it per chunk: text,
+        /// json, csv, log, int:<width>, float:<width>, binary or random
+        #[clap(long, value_name = "KIND")]
+        hint: Option<DataKind>,
+
+        /// Analyzer thresholds fitted by `hlc tune`
+        #[clap(long, value_name = "FILE")]
+        model: Option<PathBuf>,
+
+        /// Inflate embedded deflate streams (ZIP/JAR entries, PNG, PDF, gzip)
+        /// that can be re-created exactly, and compress their content instead
+        #[clap(long)]
+        precomp: bool,
+
         /// Force overwrite output file if it exists
         #[clap(short, long)]
         force: bool,
@@ -75,6 +149,11 @@ pub enum Commands {
         #[clap(short, long)]
         threads: Option<usize>,
 
+        /// Largest zstd window to allocate, in MiB; larger frames are
+        /// rejected (default 128)
+        #[clap(long, value_name = "MIB")]
+        memory_limit: Option<u64>,
+
         /// Force overwrite output file if it exists
         #[clap(short, long)]
         force: bool,
@@ -123,6 +202,146 @@ pub enum Commands {
         #[clap(long)]
         all_modes: bool,
     },
+
+    /// Report, per chunk, how its encoding was chosen, what the analyzer
+    /// measured, which chains were tried and which stages were applied, with
+    /// sizes and times per stage
+    Explain {
+        /// File to explain
+        #[clap(value_name = "FILE")]
+        input: PathBuf,
+
+        /// Compression mode
+        #[clap(short, long, default_value = "balanced")]
+        mode: HlcMode,
+
+        /// Chunk size in bytes (default: 1MB)
+        #[clap(long)]
+        chunk_size: Option<usize>,
+
+        /// What the input contains, as for `compress --hint`
+        #[clap(long, value_name = "KIND")]
+        hint: Option<DataKind>,
+
+        /// Analyzer thresholds fitted by `hlc tune`
+        #[clap(long, value_name = "FILE")]
+        model: Option<PathBuf>,
+
+        /// Print JSON instead of a table
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Fit the analyzer's thresholds to a corpus of sample files
+    Tune {
+        /// Directory of files like the ones to be compressed
+        #[clap(value_name = "CORPUS_DIR")]
+        corpus: PathBuf,
+
+        /// Model file to write, for `compress --model`
+        #[clap(short, long, value_name = "FILE", default_value = "hlc-model.txt")]
+        output: PathBuf,
+
+        /// Compression mode the thresholds are fitted for
+        #[clap(short, long, default_value = "balanced")]
+        mode: HlcMode,
+
+        /// Chunk size in bytes (default: 1MB)
+        #[clap(long)]
+        chunk_size: Option<usize>,
+
+        /// Number of threads to use (default: all available cores)
+        #[clap(short, long)]
+        threads: Option<usize>,
+
+        /// Force overwrite output file if it exists
+        #[clap(short, long)]
+        force: bool,
+    },
+
+    /// Manage a content-addressed, deduplicating chunk store
+    Store {
+        #[clap(subcommand)]
+        command: StoreCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StoreCommands {
+    /// Add a file to the store under a manifest name
+    Put {
+        /// Store directory (created if missing)
+        #[clap(short, long, value_name = "DIR")]
+        store: PathBuf,
+
+        /// File to store
+        #[clap(short, long, value_name = "FILE")]
+        input: PathBuf,
+
+        /// Manifest name (default: the input's file name)
+        #[clap(short, long)]
+        name: Option<String>,
+
+        /// Compression mode
+        #[clap(short, long, default_value = "balanced")]
+        mode: HlcMode,
+
+        /// Average content-defined chunk size in bytes
+        #[clap(long, default_value = "65536")]
+        chunk_size: usize,
+
+        /// Number of threads to use (default: all available cores)
+        #[clap(short, long)]
+        threads: Option<usize>,
+    },
+
+    /// Restore a stored file by manifest name
+    Get {
+        /// Store directory
+        #[clap(short, long, value_name = "DIR")]
+        store: PathBuf,
+
+        /// Manifest name
+        #[clap(value_name = "NAME")]
+        name: String,
+
+        /// Output file name
+        #[clap(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// Number of threads to use (default: all available cores)
+        #[clap(short, long)]
+        threads: Option<usize>,
+
+        /// Force overwrite output file if it exists
+        #[clap(short, long)]
+        force: bool,
+    },
+
+    /// List stored manifests
+    List {
+        /// Store directory
+        #[clap(short, long, value_name = "DIR")]
+        store: PathBuf,
+    },
+
+    /// Delete a manifest; its chunks are freed by the next gc
+    Remove {
+        /// Store directory
+        #[clap(short, long, value_name = "DIR")]
+        store: PathBuf,
+
+        /// Manifest name
+        #[clap(value_name = "NAME")]
+        name: String,
+    },
+
+    /// Delete chunks no manifest references
+    Gc {
+        /// Store directory
+        #[clap(short, long, value_name = "DIR")]
+        store: PathBuf,
+    },
 }
 
 pub fn run() -> Result<(), HlcError> {
@@ -141,18 +360,53 @@ pub fn run() -> Result<(), HlcError> {
             output,
             mode,
             checksum,
+            codec,
+            pipeline,
+            search,
+            search_candidates,
+            search_time,
             threads,
             chunk_size,
+            cdc,
+            dedup,
+            context_group,
+            long,
+            window_log,
+            content_size,
+            zstd_checksum,
+            hint,
+            model,
+            precomp,
             force,
         } => {
-            compress_command(input, output, mode, checksum, threads, chunk_size, force, cli.quiet)
+            let search = search.then(|| {
+                let budget = SearchBudget::default().with_max_candidates(search_candidates);
+                match search_time {
+                    Some(ms) => budget.with_time_limit(std::time::Duration::from_millis(ms)),
+                    None => budget,
+                }
+            });
+            let mut zstd = ZstdParams::default()
+                .with_content_size(content_size)
+                .with_checksum(zstd_checksum);
+            if let Some(log) = long {
+                zstd = zstd.with_long_distance(log);
+            }
+            if let Some(log) = window_log {
+                zstd = zstd.with_window_log(log);
+            }
+            compress_command(
+                input, output, mode, checksum, codec, pipeline, search, threads, chunk_size, cdc,
+                dedup, context_group, zstd, hint, model, precomp, force, cli.quiet,
+            )
         }
         Commands::Decompress {
             input,
             output,
             threads,
+            memory_limit,
             force,
-        } => decompress_command(input, output, threads, force, cli.quiet),
+        } => decompress_command(input, output, threads, memory_limit, force, cli.quiet),
         Commands::Info { input } => info_command(input),
         Commands::Validate { input } => validate_command(input, cli.quiet),
         Commands::Estimate {
@@ -165,16 +419,44 @@ pub fn run() -> Result<(), HlcError> {
             iterations,
             all_modes,
         } => benchmark_command(input, iterations, all_modes),
+        Commands::Explain {
+            input,
+            mode,
+            chunk_size,
+            hint,
+            model,
+            json,
+        } => explain_command(input, mode, chunk_size, hint, model, json),
+        Commands::Tune {
+            corpus,
+            output,
+            mode,
+            chunk_size,
+            threads,
+            force,
+        } => tune_command(corpus, output, mode, chunk_size, threads, force, cli.quiet),
+        Commands::Store { command } => store_command(command, cli.quiet),
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compress_command(
     input: PathBuf,
     output: PathBuf,
     mode: HlcMode,
     checksum: ChecksumType,
+    codec: Codec,
+    pipeline: Option<Pipeline>,
+    search: Option<SearchBudget>,
     threads: Option<usize>,
     chunk_size: Option<usize>,
+    cdc: bool,
+    dedup: bool,
+    context_group: Option<usize>,
+    zstd: ZstdParams,
+    hint: Option<DataKind>,
+    model: Option<PathBuf>,
+    precomp: bool,
     force: bool,
     quiet: bool,
 ) -> Result<(), HlcError> {
@@ -196,7 +478,16 @@ fn compress_command(
     // Build configuration
     let mut config = HlcConfig::new()
         .with_mode(mode)
-        .with_checksum(checksum);
+        .with_checksum(checksum)
+        .with_codec(codec);
+
+    if let Some(p) = pipeline {
+        config = config.with_pipeline(p);
+    }
+
+    if let Some(budget) = search {
+        config = config.with_search(budget);
+    }
 
     if let Some(t) = threads {
         config = config.with_threads(t);
@@ -206,13 +497,64 @@ fn compress_command(
         config = config.with_chunk_size(cs);
     }
 
+    if cdc {
+        let chunking = Chunking::content_defined(config.chunk_size);
+        config = config.with_chunking(chunking);
+    }
+
+    config = config
+        .with_dedup(dedup)
+        .with_zstd_params(zstd)
+        .with_precompression(precomp);
+
+    if let Some(group) = context_group {
+        config = config.with_context_group(group);
+    }
+
+    if let Some(kind) = hint {
+        config = config.with_hint(kind);
+    }
+
+    if let Some(path) = &model {
+        config = config.with_thresholds(Thresholds::load(path)?);
+    }
+
     if !quiet {
         println!("Compressing '{}' to '{}'...", input.display(), output.display());
         println!("Configuration:");
         println!("  Mode: {:?}", config.mode);
         println!("  Checksum: {:?}", config.checksum);
+        println!("  Codec: {}", config.codec.name());
+        if let Some(pipeline) = &config.pipeline {
+            println!("  Pipeline: {}", pipeline);
+        }
+        if let Some(budget) = &config.search {
+            println!("  Search: up to {} chains per chunk", budget.max_candidates);
+        }
         println!("  Threads: {}", config.threads);
-        println!("  Chunk size: {} bytes", config.chunk_size);
+        match config.chunking {
+            Chunking::Fixed => println!("  Chunk size: {} bytes", config.chunk_size),
+            Chunking::ContentDefined { min_size, avg_size, max_size } => println!(
+                "  Chunk size: {} bytes average, content-defined ({}-{})",
+                avg_size, min_size, max_size
+            ),
+        }
+        if let Some(window_log) = config.zstd.effective_window_log() {
+            let ldm = if config.zstd.long_distance { ", long-distance matching" } else { "" };
+            println!("  zstd window: 2^{} bytes{}", window_log, ldm);
+        }
+        if let Some(group) = config.context_group {
+            println!("  Context groups: {} chunks", group);
+        }
+        if let Some(kind) = config.hint {
+            println!("  Data kind hint: {}", kind);
+        }
+        if let Some(path) = &model {
+            println!("  Analyzer model: {}", path.display());
+        }
+        if config.precompression {
+            println!("  Precompression: on");
+        }
     }
 
     let start = Instant::now();
@@ -247,7 +589,18 @@ fn compress_command(
         println!("  RLE encoded:      {}", stats.chunk_stats.rle_chunks);
         println!("  Delta encoded:    {}", stats.chunk_stats.delta_chunks);
         println!("  Dictionary:       {}", stats.chunk_stats.dictionary_chunks);
+        println!("  Float XOR:        {}", stats.chunk_stats.float_xor_chunks);
+        println!("  Shuffled:         {}", stats.chunk_stats.shuffle_chunks);
+        println!("  BWT:              {}", stats.chunk_stats.bwt_chunks);
         println!("  Entropy coded:    {}", stats.chunk_stats.entropy_chunks);
+        println!("    Huffman:        {}", stats.chunk_stats.huffman_chunks);
+        println!("    rANS:           {}", stats.chunk_stats.rans_chunks);
+        println!("  Deduplicated:     {} ({} bytes saved)",
+                 stats.chunk_stats.reference_chunks, stats.chunk_stats.dedup_saved_bytes);
+        println!("  Prefixed:         {}", stats.chunk_stats.prefixed_chunks);
+        println!("  Precompressed:    {}", stats.chunk_stats.precomp_chunks);
+        println!("  CSV columnar:     {}", stats.chunk_stats.csv_chunks);
+        println!("  JSON structure:   {}", stats.chunk_stats.json_chunks);
     }
 
     Ok(())
@@ -257,6 +610,7 @@ fn decompress_command(
     input: PathBuf,
     output: PathBuf,
     threads: Option<usize>,
+    memory_limit: Option<u64>,
     force: bool,
     quiet: bool,
 ) -> Result<(), HlcError> {
@@ -276,6 +630,10 @@ fn decompress_command(
     }
 
     let num_threads = threads.unwrap_or_else(num_cpus::get);
+    let mut config = HlcConfig::new().with_threads(num_threads);
+    if let Some(mib) = memory_limit {
+        config = config.with_memory_limit(mib.saturating_mul(1024 * 1024));
+    }
 
     if !quiet {
         println!("Decompressing '{}' to '{}'...", input.display(), output.display());
@@ -292,7 +650,7 @@ fn decompress_command(
     let mut writer = BufWriter::new(output_file);
 
     // Perform decompression
-    pipeline::decompress(&mut reader, &mut writer, num_threads)?;
+    pipeline::decompress_with_config(&mut reader, &mut writer, &config)?;
     let duration = start.elapsed();
 
     if !quiet {
@@ -445,6 +803,173 @@ fn benchmark_command(
 }
 
 /// Helper function to ensure output file has .hlc extension
+fn explain_command(
+    input: PathBuf,
+    mode: HlcMode,
+    chunk_size: Option<usize>,
+    hint: Option<DataKind>,
+    model: Option<PathBuf>,
+    json: bool,
+) -> Result<(), HlcError> {
+    let mut config = HlcConfig::new().with_mode(mode);
+    if let Some(cs) = chunk_size {
+        config = config.with_chunk_size(cs);
+    }
+    if let Some(kind) = hint {
+        kind.validate()?;
+        config = config.with_hint(kind);
+    }
+    if let Some(path) = &model {
+        config = config.with_thresholds(Thresholds::load(path)?);
+    }
+
+    let data = std::fs::read(&input)?;
+    let reports = explain::explain(&data, &config)?;
+    if json {
+        print!("{}", explain::to_json(&reports, &config));
+    } else {
+        print!("{}", explain::to_table(&reports, &config));
+        let total: usize = reports.iter().map(|r| r.compressed_size).sum();
+        let zstd: usize = reports.iter().map(|r| r.zstd_size).sum();
+        println!("Total: {} -> {} (plain zstd {})", format_size(data.len() as u64), format_size(total as u64), format_size(zstd as u64));
+    }
+    Ok(())
+}
+
+fn tune_command(
+    corpus: PathBuf,
+    output: PathBuf,
+    mode: HlcMode,
+    chunk_size: Option<usize>,
+    threads: Option<usize>,
+    force: bool,
+    quiet: bool,
+) -> Result<(), HlcError> {
+    if output.exists() && !force {
+        return Err(HlcError::ConfigError(
+            format!("Output file '{}' already exists. Use --force to overwrite.", output.display())
+        ));
+    }
+
+    let mut config = HlcConfig::new().with_mode(mode);
+    if let Some(cs) = chunk_size {
+        config = config.with_chunk_size(cs);
+    }
+    if let Some(t) = threads {
+        config = config.with_threads(t);
+    }
+
+    if !quiet {
+        println!("Tuning analyzer thresholds on '{}'...", corpus.display());
+    }
+    let start = Instant::now();
+    let report = tune::tune(&corpus, &config)?;
+    report.thresholds.save(&output)?;
+
+    if !quiet {
+        let percent = |size: u64| size as f64 / report.original_size.max(1) as f64 * 100.0;
+        println!("  Files:          {} ({} chunks)", report.files, report.chunks);
+        println!("  Original size:  {}", format_size(report.original_size));
+        println!("  Default model:  {} ({:.1}%)", format_size(report.default_size), percent(report.default_size));
+        println!("  Tuned model:    {} ({:.1}%)", format_size(report.tuned_size), percent(report.tuned_size));
+        println!("  Best possible:  {} ({:.1}%)", format_size(report.best_size), percent(report.best_size));
+        println!("  Time:           {:.2}s", start.elapsed().as_secs_f64());
+        println!("\nThresholds written to '{}':", output.display());
+        print!("{}", report.thresholds);
+    }
+    Ok(())
+}
+
+fn store_command(command: StoreCommands, quiet: bool) -> Result<(), HlcError> {
+    match command {
+        StoreCommands::Put { store, input, name, mode, chunk_size, threads } => {
+            let name = match name {
+                Some(name) => name,
+                None => input
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .ok_or_else(|| HlcError::ConfigError("Cannot derive a manifest name; use --name".to_string()))?,
+            };
+
+            let mut config = HlcConfig::new()
+                .with_mode(mode)
+                .with_chunking(Chunking::content_defined(chunk_size));
+            if let Some(t) = threads {
+                config = config.with_threads(t);
+            }
+
+            let store = ChunkStore::open(store)?;
+            let mut reader = BufReader::new(File::open(&input)?);
+            let start = Instant::now();
+            let stats = store.put(&name, &mut reader, &config)?;
+
+            if !quiet {
+                println!("Stored '{}' as '{}'", input.display(), name);
+                println!("  Original size: {}", format_size(stats.original_size));
+                println!("  Chunks:        {} ({} new)", stats.total_chunks, stats.new_chunks);
+                println!("  Written:       {}", format_size(stats.stored_bytes));
+                println!("  Time:          {:.2}s", start.elapsed().as_secs_f64());
+            }
+            Ok(())
+        }
+        StoreCommands::Get { store, name, output, threads, force } => {
+            if output.exists() && !force {
+                return Err(HlcError::ConfigError(
+                    format!("Output file '{}' already exists. Use --force to overwrite.", output.display())
+                ));
+            }
+
+            let mut config = HlcConfig::new();
+            if let Some(t) = threads {
+                config = config.with_threads(t);
+            }
+
+            let store = ChunkStore::open(store)?;
+            // Resolve the manifest before creating the output file
+            store.manifest(&name)?;
+            let mut writer = BufWriter::new(File::create(&output)?);
+            let size = store.get(&name, &mut writer, &config)?;
+            writer.flush()?;
+
+            if !quiet {
+                println!("Restored '{}' to '{}' ({})", name, output.display(), format_size(size));
+            }
+            Ok(())
+        }
+        StoreCommands::List { store } => {
+            let store = ChunkStore::open(store)?;
+            for name in store.manifests()? {
+                let manifest = store.manifest(&name)?;
+                println!(
+                    "{}  {} in {} chunks",
+                    name,
+                    format_size(manifest.original_size()),
+                    manifest.entries.len()
+                );
+            }
+            Ok(())
+        }
+        StoreCommands::Remove { store, name } => {
+            ChunkStore::open(store)?.remove(&name)?;
+            if !quiet {
+                println!("Removed '{}'; run 'hlc store gc' to free its chunks", name);
+            }
+            Ok(())
+        }
+        StoreCommands::Gc { store } => {
+            let stats = ChunkStore::open(store)?.gc()?;
+            if !quiet {
+                println!(
+                    "Removed {} unreferenced chunks, freed {}",
+                    stats.removed_chunks,
+                    format_size(stats.freed_bytes)
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
 pub fn ensure_hlc_extension(path: PathBuf) -> PathBuf {
     if path.extension().and_then(|s| s.to_str()) != Some("hlc") {
         path.with_extension("hlc")
@@ -511,8 +1036,18 @@ mod tests {
             compressed_file.path().to_path_buf(),
             HlcMode::Balanced,
             ChecksumType::CRC32,
+            Codec::Zstd,
+            None,
+            None,
             Some(1),
             None,
+            false,
+            false,
+            None,
+            ZstdParams::default(),
+            None,
+            None,
+            false,
             true,
             true, // quiet mode for test
         );
@@ -523,6 +1058,7 @@ mod tests {
             compressed_file.path().to_path_buf(),
             decompressed_file.path().to_path_buf(),
             Some(1),
+            None,
             true,
             true, // quiet mode for test
         );
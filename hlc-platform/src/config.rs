@@ -1,5 +1,17 @@
+use crate::chunk::Chunking;
 use crate::error::HlcError;
+use crate::recipe::Pipeline;
+use crate::search::SearchBudget;
+use crate::strategy::{ChunkContext, Strategy, StrategyFn};
+use crate::transforms::analyzer::{DataKind, Thresholds};
+use crate::transforms::entropy::{Codec, ZstdParams, DEFAULT_MAX_WINDOW_LOG};
+use crate::transforms::registry::TransformRegistry;
 use std::str::FromStr;
+use std::sync::Arc;
+
+/// Bounds of the zstd window log
+const MIN_WINDOW_LOG: u32 = 10;
+const MAX_WINDOW_LOG: u32 = 31;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HlcMode {
@@ -19,7 +31,35 @@ pub struct HlcConfig {
     pub checksum: ChecksumType,
     pub threads: usize,
     pub chunk_size: usize,
+    pub chunking: Chunking,
+    /// Store identical chunks once, with later copies as references
+    pub dedup: bool,
+    /// Chunks per context group, at least 2: within a group each chunk's zstd
+    /// stage is primed with the tail of the chunk before it
+    pub context_group: Option<usize>,
     pub entropy_level: i32,
+    pub codec: Codec,
+    /// Advanced zstd settings used whenever the entropy stage is zstd
+    pub zstd: ZstdParams,
+    /// Largest zstd window, in bytes, a decoder will allocate; frames that
+    /// need more are rejected rather than decoded
+    pub memory_limit: u64,
+    pub registry: Arc<TransformRegistry>,
+    /// Fixed transform chain used instead of per-chunk analysis
+    pub pipeline: Option<Pipeline>,
+    /// Trial-encode candidate transform chains per chunk within this budget
+    pub search: Option<SearchBudget>,
+    /// Expand reproducible deflate streams (ZIP entries, PNG images, ...)
+    /// before compressing; needs the `deflate` feature
+    pub precompression: bool,
+    /// What the caller knows the input to be; replaces the analyzer's own
+    /// kind detection
+    pub hint: Option<DataKind>,
+    /// Cutoffs the analyzer names each chunk's kind with
+    pub thresholds: Thresholds,
+    /// Called for every chunk to choose how it is encoded, in place of the
+    /// analyzer's own choice
+    pub strategy: Option<StrategyFn>,
 }
 
 impl Default for HlcConfig {
@@ -29,7 +69,20 @@ impl Default for HlcConfig {
             checksum: ChecksumType::CRC32,
             threads: num_cpus::get(),
             chunk_size: 1024 * 1024, // 1 MB chunks
+            chunking: Chunking::Fixed,
+            dedup: false,
+            context_group: None,
             entropy_level: 5,         // zstd level 5
+            codec: Codec::Zstd,
+            zstd: ZstdParams::default(),
+            memory_limit: 1 << DEFAULT_MAX_WINDOW_LOG,
+            registry: Arc::new(TransformRegistry::new()),
+            pipeline: None,
+            search: None,
+            precompression: false,
+            hint: None,
+            thresholds: Thresholds::default(),
+            strategy: None,
         }
     }
 }
@@ -84,8 +137,111 @@ impl HlcConfig {
         self
     }
     
+    /// Sets the general-purpose codec for the final stage; the entropy level
+    /// is interpreted on the zstd scale and mapped onto the codec's own range
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+    
+    /// Sets the advanced zstd parameters, e.g. long-distance matching with a
+    /// large window for big Max-mode chunks
+    pub fn with_zstd_params(mut self, params: ZstdParams) -> Self {
+        self.zstd = params;
+        self
+    }
+    
+    /// Caps the zstd window a decoder will allocate (default 128 MiB);
+    /// compressing with a larger window than this fails up front
+    pub fn with_memory_limit(mut self, bytes: u64) -> Self {
+        self.memory_limit = bytes.max(1 << MIN_WINDOW_LOG);
+        self
+    }
+    
+    /// Largest zstd window log that fits the memory limit
+    pub fn max_window_log(&self) -> u32 {
+        (63 - self.memory_limit.leading_zeros()).clamp(MIN_WINDOW_LOG, MAX_WINDOW_LOG)
+    }
+    
+    /// Sets the transform registry; user transforms in it are applied when
+    /// their estimate says they help, and decoding looks transforms up in it
+    pub fn with_registry(mut self, registry: Arc<TransformRegistry>) -> Self {
+        self.registry = registry;
+        self
+    }
+    
+    /// Pins every chunk to `pipeline` instead of letting the analyzer choose;
+    /// chunks the chain would expand are still stored raw
+    pub fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+    
+    /// Enables the exhaustive search: each chunk is also encoded with every
+    /// candidate transform chain the budget allows, and the smallest final
+    /// output is kept. Meant for archival jobs alongside `HlcMode::Max`
+    pub fn with_search(mut self, budget: SearchBudget) -> Self {
+        self.search = Some(budget);
+        self
+    }
+    
+    /// Inflates embedded deflate streams that can be re-created bit-exactly,
+    /// so their content is compressed by the pipeline instead of being
+    /// stored as incompressible; decoding re-deflates them. Costs a deflate
+    /// per stream on both sides
+    pub fn with_precompression(mut self, enabled: bool) -> Self {
+        self.precompression = enabled;
+        self
+    }
+    
+    /// Tells the analyzer what every chunk contains, skipping its detectors:
+    /// candidate chains are chosen for `kind` and only trialled against each
+    /// other. `DataKind::Random` stores chunks without any trials, and any
+    /// other hint also skips the already-compressed check
+    pub fn with_hint(mut self, kind: DataKind) -> Self {
+        self.hint = Some(kind);
+        self
+    }
+    
+    /// Replaces the analyzer's hand-picked thresholds, typically with ones
+    /// fitted by `hlc tune` and read with `Thresholds::load`
+    pub fn with_thresholds(mut self, thresholds: Thresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+    
+    /// Lets the caller choose each chunk's encoding: `f` sees the chunk's id,
+    /// offset and data, can ask for the analyzer's suggestion, and returns
+    /// the strategy to use. `Strategy::Auto` falls back to the analyzer. A
+    /// pinned pipeline and already-compressed detection still come first
+    pub fn with_strategy_fn(mut self, f: Arc<dyn Fn(&ChunkContext) -> Strategy + Send + Sync>) -> Self {
+        self.strategy = Some(StrategyFn::new(f));
+        self
+    }
+    
     pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
         self.chunk_size = chunk_size.max(1024); // Minimum 1KB chunks
         self
     }
+    
+    /// Enables or disables chunk deduplication (off by default)
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+    
+    /// Primes each chunk's zstd stage with the tail of the preceding chunk,
+    /// in groups of `group_size` chunks. Matches across chunk boundaries are
+    /// found again, which helps most at small chunk sizes; groups still
+    /// decode in parallel, but the chunks inside one decode in order
+    pub fn with_context_group(mut self, group_size: usize) -> Self {
+        self.context_group = (group_size > 1).then_some(group_size);
+        self
+    }
+    
+    /// Selects how input is split; `Chunking::Fixed` uses `chunk_size`
+    pub fn with_chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
 }
\ No newline at end of file
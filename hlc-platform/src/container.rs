@@ -1,34 +1,63 @@
 use crate::config::{ChecksumType, HlcConfig};
 use crate::chunk::RawChunk;
 use crate::error::HlcError;
-use crate::transforms::{delta, entropy, rle, dictionary};
+use crate::recipe::Recipe;
+use crate::transforms::entropy::Codec;
 use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 use crc32fast::Hasher as Crc32Hasher;
 use sha2::{Digest, Sha256};
 use std::io::{Read, Write};
 
-const MAGIC_NUMBER: &[u8; 4] = b"HLC1";
-const VERSION: u8 = 1;
+pub(crate) const MAGIC_NUMBER: &[u8; 4] = b"HLC1";
+pub(crate) const VERSION: u8 = 6;
+
+/// Oldest version still readable; versions 1-3 describe chunks with pipeline
+/// flags (plus a codec byte from 2 and a transform list from 3), which are
+/// converted to the equivalent recipe on read
+const MIN_VERSION: u8 = 1;
+
+/// Size of the fixed per-chunk header: kind + original size + compressed size +
+/// checksum + recipe step count; each recipe step adds 3 bytes plus its params
+pub const CHUNK_HEADER_SIZE: usize = 1 + 4 + 4 + 8 + 1;
+
+/// Size of a reference chunk entry: kind + target index + original size + checksum
+pub const REFERENCE_HEADER_SIZE: usize = 1 + 4 + 4 + 8;
+
+/// Extra header bytes of a prefixed chunk: the prefix length
+pub const PREFIX_HEADER_SIZE: usize = 4;
+
+// Chunk kinds, from version 5; prefixed chunks from version 6
+const KIND_DATA: u8 = 0;
+const KIND_REFERENCE: u8 = 1;
+const KIND_PREFIXED: u8 = 2;
 
 bitflags::bitflags! {
+    /// Summary of the built-in stages a chunk went through; the container
+    /// itself records the full recipe since version 4
     #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct PipelineFlags: u8 {
         const STORED      = 0b00000001; // Data is stored uncompressed
-        const ENTROPY     = 0b00000010; // Entropy coded (zstd)
+        const ENTROPY     = 0b00000010; // Entropy coded (codec in chunk header)
         const RLE         = 0b00000100; // Run-Length Encoded
         const DELTA       = 0b00001000; // Delta coded
         const DICTIONARY  = 0b00010000; // Dictionary compressed
-        // Reserved flags for future use
-        const RESERVED_1  = 0b00100000;
-        const RESERVED_2  = 0b01000000;
-        const RESERVED_3  = 0b10000000;
+        const FLOAT_XOR   = 0b00100000; // XOR-coded IEEE-754 values
+        const SHUFFLE     = 0b01000000; // Byte/bit shuffled typed array
+        const BWT         = 0b10000000; // Burrows-Wheeler + MTF + zero-run coded
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct CompressedChunk {
     pub id: usize,
-    pub flags: PipelineFlags,
+    /// Steps that produced `data`, in application order
+    pub recipe: Recipe,
+    /// Index of an earlier chunk with identical content; a reference chunk
+    /// stores no data of its own
+    pub reference: Option<usize>,
+    /// Length of the preceding chunk's raw tail the zstd entropy stage was
+    /// primed with; 0 when the chunk decodes on its own
+    pub prefix_len: usize,
     pub original_checksum: u64,
     pub original_size: u32,
     pub compressed_size: u32,
@@ -39,7 +68,9 @@ impl CompressedChunk {
     pub fn new(id: usize, data: Vec<u8>, original_size: usize, checksum: u64) -> Self {
         Self {
             id,
-            flags: PipelineFlags::STORED,
+            recipe: Recipe::new(),
+            reference: None,
+            prefix_len: 0,
             original_checksum: checksum,
             original_size: original_size as u32,
             compressed_size: data.len() as u32,
@@ -47,32 +78,48 @@ impl CompressedChunk {
         }
     }
 
+    /// A chunk whose content is identical to the earlier chunk `target`
+    pub fn new_reference(id: usize, target: usize, original_size: usize, checksum: u64) -> Self {
+        Self {
+            reference: Some(target),
+            ..Self::new(id, Vec::new(), original_size, checksum)
+        }
+    }
+
+    pub fn is_reference(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    pub fn is_prefixed(&self) -> bool {
+        self.prefix_len > 0
+    }
+
+    /// Decodes the chunk's own data; reference chunks have none and are filled
+    /// in from their target with `resolve_references`
     pub fn decompress(&self, config: &HlcConfig) -> Result<RawChunk, HlcError> {
-        let mut data = self.data.clone();
+        self.decompress_with_prefix(config, &[])
+    }
 
-        // Apply decompression in reverse order of compression
-        if self.flags.contains(PipelineFlags::STORED) {
-            // Data is stored raw, no processing needed
-        } else {
-            // First, decode entropy if applied
-            if self.flags.contains(PipelineFlags::ENTROPY) {
-                data = entropy::decode(&data)?;
-            }
+    /// Decodes a chunk given the decoded data of the chunk before it, which
+    /// prefixed chunks need; see `decode_group`
+    pub fn decompress_with_prefix(&self, config: &HlcConfig, previous: &[u8]) -> Result<RawChunk, HlcError> {
+        if self.prefix_len > previous.len() {
+            return Err(HlcError::DecompressionError(format!(
+                "Chunk {} needs the decoded chunk before it as a prefix",
+                self.id
+            )));
+        }
+        let prefix = &previous[previous.len() - self.prefix_len..];
 
-            // Then apply reverse transforms in reverse order
-            if self.flags.contains(PipelineFlags::DICTIONARY) {
-                data = dictionary::decode(&data);
-            }
-            
-            if self.flags.contains(PipelineFlags::DELTA) {
-                data = delta::decode(&data);
-            }
-            
-            if self.flags.contains(PipelineFlags::RLE) {
-                data = rle::decode(&data);
-            }
+        if let Some(target) = self.reference {
+            return Err(HlcError::DecompressionError(format!(
+                "Chunk {} is a reference to chunk {} and has no data of its own",
+                self.id, target
+            )));
         }
 
+        let data = self.recipe.decode_with_config(&self.data, prefix, self.original_size as usize, config)?;
+
         // Verify size
         if data.len() != self.original_size as usize {
             return Err(HlcError::DecompressionError(format!(
@@ -88,7 +135,25 @@ impl CompressedChunk {
             return Err(HlcError::ChecksumMismatch);
         }
 
-        Ok(RawChunk { id: self.id, data })
+        Ok(RawChunk::new(self.id, data))
+    }
+
+    /// Bytes this chunk's header takes in the container
+    pub fn header_size(&self) -> usize {
+        if self.is_reference() {
+            REFERENCE_HEADER_SIZE
+        } else {
+            let prefix = if self.is_prefixed() { PREFIX_HEADER_SIZE } else { 0 };
+            CHUNK_HEADER_SIZE - 1 + self.recipe.encoded_len() + prefix
+        }
+    }
+
+    pub fn flags(&self) -> PipelineFlags {
+        self.recipe.flags()
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.recipe.codec()
     }
 
     pub fn compression_ratio(&self) -> f64 {
@@ -148,7 +213,7 @@ impl ContainerHeader {
         }
 
         let version = reader.read_u8()?;
-        if version != VERSION {
+        if !(MIN_VERSION..=VERSION).contains(&version) {
             return Err(HlcError::InvalidFormat(format!("Unsupported version: {}", version)));
         }
 
@@ -201,16 +266,32 @@ pub fn write_hlc_container<W: Write>(
 
     // Write chunk headers and data
     for chunk in chunks {
-        // Chunk header: flags(1) + original_size(4) + compressed_size(4) + checksum(8)
-        writer.write_u8(chunk.flags.bits())?;
+        if let Some(target) = chunk.reference {
+            // Reference entry: kind(1) + target(4) + original_size(4) + checksum(8)
+            writer.write_u8(KIND_REFERENCE)?;
+            writer.write_u32::<LittleEndian>(target as u32)?;
+            writer.write_u32::<LittleEndian>(chunk.original_size)?;
+            writer.write_u64::<LittleEndian>(chunk.original_checksum)?;
+            total_bytes_written += chunk.header_size() as u64;
+            continue;
+        }
+
+        // Chunk header: kind(1) + [prefix_len(4)] + original_size(4) + compressed_size(4) + checksum(8) + recipe
+        if chunk.is_prefixed() {
+            writer.write_u8(KIND_PREFIXED)?;
+            writer.write_u32::<LittleEndian>(chunk.prefix_len as u32)?;
+        } else {
+            writer.write_u8(KIND_DATA)?;
+        }
         writer.write_u32::<LittleEndian>(chunk.original_size)?;
         writer.write_u32::<LittleEndian>(chunk.compressed_size)?;
         writer.write_u64::<LittleEndian>(chunk.original_checksum)?;
+        chunk.recipe.write(writer)?;
         
         // Chunk data
         writer.write_all(&chunk.data)?;
         
-        total_bytes_written += (1 + 4 + 4 + 8) + chunk.data.len() as u64;
+        total_bytes_written += (chunk.header_size() + chunk.data.len()) as u64;
     }
 
     Ok(total_bytes_written)
@@ -232,12 +313,36 @@ pub fn read_hlc_container<R: Read>(
 
     // Read chunks
     for id in 0..header.chunk_count {
+        let kind = if header.version >= 5 { reader.read_u8()? } else { KIND_DATA };
+        let prefix_len = match kind {
+            KIND_DATA => 0,
+            KIND_PREFIXED if header.version >= 6 => {
+                let prefix_len = reader.read_u32::<LittleEndian>()? as usize;
+                check_prefix(id as usize, prefix_len, &chunks)?;
+                prefix_len
+            }
+            KIND_REFERENCE => {
+                let target = reader.read_u32::<LittleEndian>()? as usize;
+                let original_size = reader.read_u32::<LittleEndian>()?;
+                let original_checksum = reader.read_u64::<LittleEndian>()?;
+                let chunk = CompressedChunk::new_reference(id as usize, target, original_size as usize, original_checksum);
+                check_reference(&chunk, &chunks)?;
+                chunks.push(chunk);
+                continue;
+            }
+            _ => return Err(HlcError::InvalidFormat(format!("Unknown chunk kind: {}", kind))),
+        };
+
         // Read chunk header
-        let flags_byte = reader.read_u8()?;
-        let flags = PipelineFlags::from_bits_truncate(flags_byte);
-        let original_size = reader.read_u32::<LittleEndian>()?;
-        let compressed_size = reader.read_u32::<LittleEndian>()?;
-        let original_checksum = reader.read_u64::<LittleEndian>()?;
+        let (recipe, original_size, compressed_size, original_checksum) = if header.version >= 4 {
+            let original_size = reader.read_u32::<LittleEndian>()?;
+            let compressed_size = reader.read_u32::<LittleEndian>()?;
+            let original_checksum = reader.read_u64::<LittleEndian>()?;
+            let recipe = Recipe::read(reader)?;
+            (recipe, original_size, compressed_size, original_checksum)
+        } else {
+            read_legacy_chunk_header(reader, header.version)?
+        };
 
         // Read chunk data
         let mut data = vec![0; compressed_size as usize];
@@ -245,7 +350,9 @@ pub fn read_hlc_container<R: Read>(
 
         chunks.push(CompressedChunk {
             id: id as usize,
-            flags,
+            recipe,
+            reference: None,
+            prefix_len,
             original_checksum,
             original_size,
             compressed_size,
@@ -256,6 +363,109 @@ pub fn read_hlc_container<R: Read>(
     Ok((chunks, config))
 }
 
+/// A reference must point back at an earlier data chunk with the same content
+fn check_reference(chunk: &CompressedChunk, earlier: &[CompressedChunk]) -> Result<(), HlcError> {
+    let target = chunk.reference.and_then(|target| earlier.get(target));
+    match target {
+        Some(target)
+            if !target.is_reference()
+                && target.original_size == chunk.original_size
+                && target.original_checksum == chunk.original_checksum => Ok(()),
+        _ => Err(HlcError::InvalidFormat(format!(
+            "Chunk {} has an invalid reference to chunk {}",
+            chunk.id,
+            chunk.reference.unwrap_or_default()
+        ))),
+    }
+}
+
+/// A prefix must come from the chunk right before, which has to carry data of
+/// its own and be at least as long
+fn check_prefix(id: usize, prefix_len: usize, earlier: &[CompressedChunk]) -> Result<(), HlcError> {
+    match id.checked_sub(1).and_then(|previous| earlier.get(previous)) {
+        Some(previous)
+            if !previous.is_reference() && prefix_len > 0 && prefix_len <= previous.original_size as usize => Ok(()),
+        _ => Err(HlcError::InvalidFormat(format!("Chunk {} has an invalid prefix", id))),
+    }
+}
+
+/// Splits chunks into groups that decode independently of each other: each
+/// group starts at a chunk without a prefix and runs through the prefixed
+/// chunks after it
+pub fn context_groups(chunks: &[CompressedChunk]) -> Vec<&[CompressedChunk]> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for (index, chunk) in chunks.iter().enumerate().skip(1) {
+        if !chunk.is_prefixed() {
+            groups.push(&chunks[start..index]);
+            start = index;
+        }
+    }
+    if start < chunks.len() {
+        groups.push(&chunks[start..]);
+    }
+    groups
+}
+
+/// Decodes one group from `context_groups` in order, feeding each chunk the
+/// data of the one before it; references come back as empty placeholders for
+/// `resolve_references`
+pub fn decode_group(group: &[CompressedChunk], config: &HlcConfig) -> Result<Vec<RawChunk>, HlcError> {
+    let mut decoded: Vec<RawChunk> = Vec::with_capacity(group.len());
+    for chunk in group {
+        let raw = if chunk.is_reference() {
+            RawChunk::new(chunk.id, Vec::new())
+        } else {
+            let previous = decoded.last().map(|c| c.data.as_slice()).unwrap_or_default();
+            chunk.decompress_with_prefix(config, previous)?
+        };
+        decoded.push(raw);
+    }
+    Ok(decoded)
+}
+
+/// Fills in reference chunks from their decoded targets
+///
+/// `decoded` holds one entry per chunk in container order; entries for
+/// reference chunks are replaced with a copy of their target's data
+pub fn resolve_references(chunks: &[CompressedChunk], decoded: &mut [RawChunk]) {
+    for chunk in chunks {
+        if let Some(target) = chunk.reference {
+            decoded[chunk.id].data = decoded[target].data.clone();
+        }
+    }
+}
+
+/// Reads a version 1-3 chunk header and converts its flags into a recipe
+fn read_legacy_chunk_header<R: Read>(
+    reader: &mut R,
+    version: u8,
+) -> Result<(Recipe, u32, u32, u64), HlcError> {
+    let flags = PipelineFlags::from_bits_truncate(reader.read_u8()?);
+    let codec = if version >= 2 {
+        let codec_id = reader.read_u8()?;
+        Codec::from_id(codec_id).ok_or_else(|| {
+            HlcError::InvalidFormat(format!("Unknown entropy codec: {}", codec_id))
+        })?
+    } else {
+        Codec::Zstd
+    };
+    let original_size = reader.read_u32::<LittleEndian>()?;
+    let compressed_size = reader.read_u32::<LittleEndian>()?;
+    let original_checksum = reader.read_u64::<LittleEndian>()?;
+
+    let mut transforms = Vec::new();
+    if version >= 3 {
+        let transform_count = reader.read_u8()?;
+        for _ in 0..transform_count {
+            transforms.push(reader.read_u16::<LittleEndian>()?);
+        }
+    }
+
+    let recipe = Recipe::from_legacy(flags, codec, &transforms);
+    Ok((recipe, original_size, compressed_size, original_checksum))
+}
+
 /// Calculate checksum for data
 pub fn calculate_checksum(data: &[u8], checksum_type: ChecksumType) -> u64 {
     match checksum_type {
@@ -278,9 +488,10 @@ pub fn calculate_checksum(data: &[u8], checksum_type: ChecksumType) -> u64 {
 pub fn verify_container<R: Read>(reader: &mut R) -> Result<bool, HlcError> {
     let (chunks, config) = read_hlc_container(reader)?;
     
-    for chunk in chunks {
-        // Try to decompress each chunk to verify integrity
-        chunk.decompress(&config)?;
+    // References were checked against their targets when reading; decoding
+    // each group verifies every data chunk's checksum
+    for group in context_groups(&chunks) {
+        decode_group(group, &config)?;
     }
     
     Ok(true)
@@ -289,6 +500,8 @@ pub fn verify_container<R: Read>(reader: &mut R) -> Result<bool, HlcError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::recipe::RecipeStep;
+    use crate::transforms::{entropy, registry};
     use std::io::Cursor;
 
     #[test]
@@ -306,6 +519,168 @@ mod tests {
         assert_eq!(original.chunk_count, decoded.chunk_count);
     }
 
+    #[test]
+    fn test_container_reads_version_1() {
+        // Version 1 chunk headers carry no codec byte and imply zstd
+        let data = b"version one chunk data ".repeat(20);
+        let compressed = entropy::encode(&data, 3).unwrap();
+        let checksum = calculate_checksum(&data, ChecksumType::CRC32);
+
+        let mut header = ContainerHeader::new(ChecksumType::CRC32, 1);
+        header.version = 1;
+        let mut buffer = Vec::new();
+        header.write(&mut buffer).unwrap();
+        buffer.push(PipelineFlags::ENTROPY.bits());
+        buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&checksum.to_le_bytes());
+        buffer.extend_from_slice(&compressed);
+
+        let (chunks, config) = read_hlc_container(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(chunks[0].codec(), Codec::Zstd);
+        assert_eq!(chunks[0].decompress(&config).unwrap().data, data);
+    }
+
+    #[test]
+    fn test_container_reads_version_3() {
+        // Version 3 applies registered transforms first, then flagged built-ins
+        let data: Vec<u8> = (0u8..200).cycle().take(4000).collect();
+        let delta = crate::transforms::delta::encode(&data);
+        let compressed = entropy::encode_with(Codec::Rans, &delta, 0).unwrap();
+        let checksum = calculate_checksum(&data, ChecksumType::CRC32);
+
+        let mut header = ContainerHeader::new(ChecksumType::CRC32, 1);
+        header.version = 3;
+        let mut buffer = Vec::new();
+        header.write(&mut buffer).unwrap();
+        buffer.push((PipelineFlags::DELTA | PipelineFlags::ENTROPY).bits());
+        buffer.push(Codec::Rans.id());
+        buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&checksum.to_le_bytes());
+        buffer.push(0);
+        buffer.extend_from_slice(&compressed);
+
+        let (chunks, config) = read_hlc_container(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(chunks[0].flags(), PipelineFlags::DELTA | PipelineFlags::ENTROPY);
+        assert_eq!(chunks[0].decompress(&config).unwrap().data, data);
+    }
+
+    #[test]
+    fn test_recipe_chunk_roundtrip() {
+        let data: Vec<u8> = (0u32..2000).flat_map(|i| (i * 7).to_le_bytes()).collect();
+        let checksum = calculate_checksum(&data, ChecksumType::CRC32);
+        let config = HlcConfig::default();
+
+        let mut recipe = Recipe::new();
+        recipe.push(RecipeStep::new(registry::DELTA_ID, vec![4]));
+        recipe.push(RecipeStep::new(registry::RLE_ID, Vec::new()));
+        recipe.push(RecipeStep::entropy(Codec::Zstd, 5));
+        let encoded = crate::transforms::rle::encode(&crate::transforms::delta::encode_advanced(&data, 4));
+        let encoded = entropy::encode_with(Codec::Zstd, &encoded, 5).unwrap();
+
+        let mut chunk = CompressedChunk::new(0, encoded, data.len(), checksum);
+        chunk.recipe = recipe.clone();
+
+        let mut buffer = Vec::new();
+        let written = write_hlc_container(&mut buffer, &[chunk], &config).unwrap();
+        assert_eq!(written as usize, buffer.len());
+
+        let (chunks, config) = read_hlc_container(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(chunks[0].recipe, recipe);
+        assert_eq!(chunks[0].decompress(&config).unwrap().data, data);
+    }
+
+    #[test]
+    fn test_native_codec_chunk_roundtrip() {
+        let data = b"abracadabra ".repeat(100);
+        let checksum = calculate_checksum(&data, ChecksumType::CRC32);
+        let config = HlcConfig::default();
+
+        for codec in [Codec::Huffman, Codec::Rans] {
+            let encoded = entropy::encode_with(codec, &data, 0).unwrap();
+            let mut chunk = CompressedChunk::new(0, encoded, data.len(), checksum);
+            chunk.recipe.push(RecipeStep::entropy(codec, 0));
+
+            let mut buffer = Vec::new();
+            write_hlc_container(&mut buffer, &[chunk], &config).unwrap();
+            let (chunks, config) = read_hlc_container(&mut Cursor::new(buffer)).unwrap();
+            assert_eq!(chunks[0].codec(), codec);
+            assert_eq!(chunks[0].decompress(&config).unwrap().data, data);
+        }
+    }
+
+    #[test]
+    fn test_reference_chunks() {
+        let data = b"duplicated block ".repeat(64);
+        let checksum = calculate_checksum(&data, ChecksumType::CRC32);
+        let config = HlcConfig::default();
+
+        let chunks = vec![
+            CompressedChunk::new(0, data.clone(), data.len(), checksum),
+            CompressedChunk::new_reference(1, 0, data.len(), checksum),
+        ];
+        let mut buffer = Vec::new();
+        let written = write_hlc_container(&mut buffer, &chunks, &config).unwrap();
+        assert_eq!(written as usize, buffer.len());
+
+        let (chunks, config) = read_hlc_container(&mut Cursor::new(buffer.clone())).unwrap();
+        assert_eq!(chunks[1].reference, Some(0));
+        assert!(chunks[1].decompress(&config).is_err());
+
+        let mut decoded = vec![chunks[0].decompress(&config).unwrap(), RawChunk::new(1, Vec::new())];
+        resolve_references(&chunks, &mut decoded);
+        assert_eq!(decoded[1].data, data);
+
+        // A reference whose checksum disagrees with its target is rejected
+        let last = buffer.len() - 1;
+        buffer[last] ^= 1;
+        assert!(read_hlc_container(&mut Cursor::new(buffer)).is_err());
+    }
+
+    #[test]
+    fn test_prefixed_chunks() {
+        let config = HlcConfig::default();
+        let first = b"shared context across the chunk boundary; ".repeat(40);
+        let second = [&first[..600], b"and a new ending".as_slice()].concat();
+
+        let mut recipe = Recipe::new();
+        recipe.push(RecipeStep::entropy(Codec::Zstd, 5));
+        let prefixed = CompressedChunk {
+            prefix_len: first.len(),
+            recipe,
+            ..CompressedChunk::new(
+                1,
+                entropy::encode_zstd(&second, 5, &first, &entropy::ZstdParams::default()).unwrap(),
+                second.len(),
+                calculate_checksum(&second, ChecksumType::CRC32),
+            )
+        };
+        let chunks = vec![
+            CompressedChunk::new(0, first.clone(), first.len(), calculate_checksum(&first, ChecksumType::CRC32)),
+            prefixed,
+            CompressedChunk::new(2, b"cold".to_vec(), 4, calculate_checksum(b"cold", ChecksumType::CRC32)),
+        ];
+        let mut buffer = Vec::new();
+        let written = write_hlc_container(&mut buffer, &chunks, &config).unwrap();
+        assert_eq!(written as usize, buffer.len());
+
+        let (chunks, config) = read_hlc_container(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(chunks[1].prefix_len, first.len());
+        assert!(chunks[1].decompress(&config).is_err());
+
+        let groups = context_groups(&chunks);
+        assert_eq!(groups.iter().map(|g| g.len()).collect::<Vec<_>>(), vec![2, 1]);
+        let decoded = decode_group(groups[0], &config).unwrap();
+        assert_eq!(decoded[1].data, second);
+
+        // The first chunk has nothing before it to use as a prefix
+        let lone = CompressedChunk { id: 0, ..chunks[1].clone() };
+        let mut buffer = Vec::new();
+        write_hlc_container(&mut buffer, &[lone], &config).unwrap();
+        assert!(read_hlc_container(&mut Cursor::new(buffer)).is_err());
+    }
+
     #[test]
     fn test_pipeline_flags() {
         let flags = PipelineFlags::RLE | PipelineFlags::DELTA | PipelineFlags::ENTROPY;
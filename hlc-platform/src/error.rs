@@ -26,8 +26,14 @@ pub enum HlcError {
     #[error("Transform error: {0}")]
     TransformError(String),
 
+    #[error("Archive needs transform id {0}, which is not registered with this decoder")]
+    UnregisteredTransform(u16),
+
     #[error("Thread pool initialization error: {0}")]
     ThreadPoolError(String),
+
+    #[error("Chunk store error: {0}")]
+    StoreError(String),
 }
 
 pub type Result<T> = std::result::Result<T, HlcError>;
\ No newline at end of file
@@ -0,0 +1,362 @@
+//! Per-chunk reports of what the compressor measured and decided
+//! Each chunk is encoded as `compress` would, recording which path chose its
+//! encoding, the analysis if one ran, and every chain encoded in full. The
+//! chosen recipe is then replayed one step at a time to record the size after
+//! each stage and how long it took. Plain zstd at the same level is encoded
+//! alongside, so chunks that lose to it stand out. Chunks are explained one
+//! at a time, without context-group priming, so their timings do not skew
+//! each other
+
+use crate::chunk::{find_duplicates, process_chunk_traced, split_for_config, Decision, TriedChain};
+use crate::config::HlcConfig;
+use crate::error::HlcError;
+use crate::recipe::Recipe;
+use crate::transforms::analyzer::Analysis;
+use crate::transforms::entropy;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// One applied stage of a chunk's recipe
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    /// The step in pipeline syntax, e.g. `delta:4`
+    pub name: String,
+    pub output_size: usize,
+    pub time: Duration,
+}
+
+/// What happened to one chunk
+#[derive(Debug, Clone)]
+pub struct ChunkReport {
+    pub id: usize,
+    pub offset: u64,
+    pub original_size: usize,
+    /// Earlier identical chunk this one is stored as a reference to; such
+    /// chunks are neither analyzed nor encoded
+    pub duplicate_of: Option<usize>,
+    /// Path that chose the encoding
+    pub decision: Decision,
+    /// The encoder's analysis of the chunk, when it ran one: not for pinned
+    /// pipelines, incompressible chunks, or callbacks that never asked
+    pub analysis: Option<Analysis>,
+    pub analysis_time: Duration,
+    /// Chains encoded in full, in the order tried
+    pub tried: Vec<TriedChain>,
+    /// Stages of the chosen recipe in order; empty when the chunk is stored
+    pub stages: Vec<Stage>,
+    pub compressed_size: usize,
+    /// Time the whole chunk took to encode, analysis included
+    pub encode_time: Duration,
+    /// Size with plain zstd at the configured level, for comparison
+    pub zstd_size: usize,
+}
+
+impl ChunkReport {
+    pub fn is_stored(&self) -> bool {
+        self.duplicate_of.is_none() && self.stages.is_empty()
+    }
+}
+
+/// Compresses `data` chunk by chunk as `config` says, reporting each chunk's
+/// analysis and stages instead of writing an archive
+pub fn explain(data: &[u8], config: &HlcConfig) -> Result<Vec<ChunkReport>, HlcError> {
+    let chunks = split_for_config(data, config);
+    let duplicates = if config.dedup { find_duplicates(&chunks) } else { vec![None; chunks.len()] };
+
+    let mut reports = Vec::with_capacity(chunks.len());
+    for (chunk, duplicate_of) in chunks.iter().zip(duplicates) {
+        let mut report = ChunkReport {
+            id: chunk.id,
+            offset: chunk.offset,
+            original_size: chunk.size(),
+            duplicate_of,
+            decision: Decision::default(),
+            analysis: None,
+            analysis_time: Duration::ZERO,
+            tried: Vec::new(),
+            stages: Vec::new(),
+            compressed_size: 0,
+            encode_time: Duration::ZERO,
+            zstd_size: 0,
+        };
+        if duplicate_of.is_some() {
+            reports.push(report);
+            continue;
+        }
+
+        let start = Instant::now();
+        let (compressed, trace) = process_chunk_traced(chunk, config)?;
+        report.encode_time = start.elapsed();
+        report.compressed_size = compressed.data.len();
+        report.zstd_size = entropy::encode(&chunk.data, config.entropy_level)?.len();
+        report.decision = trace.decision;
+        report.tried = trace.tried;
+        if let Some((analysis, time)) = trace.analysis {
+            report.analysis = Some(analysis);
+            report.analysis_time = time;
+        }
+
+        let mut current = chunk.data.clone();
+        for step in &compressed.recipe.steps {
+            let start = Instant::now();
+            current = Recipe { steps: vec![step.clone()] }.encode_with_config(&current, config)?;
+            report.stages.push(Stage {
+                name: step.label(&config.registry),
+                output_size: current.len(),
+                time: start.elapsed(),
+            });
+        }
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+/// A chain in pipeline syntax, e.g. `delta:4,zstd:5`
+fn chain_label(chain: &TriedChain, config: &HlcConfig) -> String {
+    let label: Vec<String> = chain.steps.iter().map(|s| s.label(&config.registry)).collect();
+    if label.is_empty() { "(none)".to_string() } else { label.join(",") }
+}
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+/// Human-readable report, one block per chunk
+pub fn to_table(reports: &[ChunkReport], config: &HlcConfig) -> String {
+    let mut out = String::new();
+    for report in reports {
+        let _ = writeln!(
+            out,
+            "Chunk {} @ {}: {} bytes",
+            report.id, report.offset, report.original_size
+        );
+        if let Some(original) = report.duplicate_of {
+            let _ = writeln!(out, "  duplicate of chunk {}\n", original);
+            continue;
+        }
+
+        let _ = writeln!(out, "  decided by {}", report.decision);
+        if let Some(analysis) = &report.analysis {
+            let f = &analysis.features;
+            let record = analysis.record_width.map_or("-".to_string(), |w| w.to_string());
+            let _ = writeln!(out, "  kind {}, record width {}", analysis.kind, record);
+            let _ = writeln!(
+                out,
+                "  text {:.3}  zeros {:.3}  entropy {:.2}  order-1 {:.2}  order-2 {:.2}",
+                f.text_fraction, f.zero_fraction, f.entropy, analysis.entropy.order1, analysis.entropy.order2
+            );
+            let _ = writeln!(
+                out,
+                "  float 4/8 {:.3}/{:.3}  lanes 2/4/8 {:.2}/{:.2}/{:.2}  delta 1/2/4 {:.2}/{:.2}/{:.2}",
+                f.float_fraction[0],
+                f.float_fraction[1],
+                f.lane_entropy[0],
+                f.lane_entropy[1],
+                f.lane_entropy[2],
+                f.delta_entropy[0],
+                f.delta_entropy[1],
+                f.delta_entropy[2]
+            );
+        }
+
+        if !report.tried.is_empty() {
+            let _ = writeln!(out, "  {:<32} {:>12} {:>10}", "tried", "predicted", "size");
+            for chain in &report.tried {
+                let predicted = chain.predicted_size.map_or("-".to_string(), |p| p.to_string());
+                let _ = writeln!(out, "  {:<32} {:>12} {:>10}", chain_label(chain, config), predicted, chain.size);
+            }
+        }
+
+        let _ = writeln!(out, "  {:<32} {:>12} {:>10}", "applied", "size", "ms");
+        if report.analysis.is_some() {
+            let _ = writeln!(out, "  {:<32} {:>12} {:>10.2}", "(analysis)", report.original_size, millis(report.analysis_time));
+        }
+        if report.is_stored() {
+            let _ = writeln!(out, "  {:<32} {:>12}", "(stored)", report.compressed_size);
+        }
+        for stage in &report.stages {
+            let _ = writeln!(out, "  {:<32} {:>12} {:>10.2}", stage.name, stage.output_size, millis(stage.time));
+        }
+
+        let verdict = if report.compressed_size > report.zstd_size { "  <- worse than plain zstd" } else { "" };
+        let _ = writeln!(
+            out,
+            "  result {} bytes in {:.2} ms, plain zstd {} bytes{}\n",
+            report.compressed_size,
+            millis(report.encode_time),
+            report.zstd_size,
+            verdict
+        );
+    }
+    out
+}
+
+/// Machine-readable report: a JSON array with one object per chunk
+pub fn to_json(reports: &[ChunkReport], config: &HlcConfig) -> String {
+    let mut out = String::from("[");
+    for (index, report) in reports.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let _ = write!(
+            out,
+            "\n  {{\"id\": {}, \"offset\": {}, \"original_size\": {}",
+            report.id, report.offset, report.original_size
+        );
+        if let Some(original) = report.duplicate_of {
+            let _ = write!(out, ", \"duplicate_of\": {}}}", original);
+            continue;
+        }
+
+        let _ = write!(out, ", \"decision\": {}", json_string(&report.decision.to_string()));
+        match &report.analysis {
+            Some(analysis) => {
+                let f = &analysis.features;
+                let _ = write!(out, ", \"kind\": {}", json_string(&analysis.kind.to_string()));
+                match analysis.record_width {
+                    Some(width) => {
+                        let _ = write!(out, ", \"record_width\": {}", width);
+                    }
+                    None => out.push_str(", \"record_width\": null"),
+                }
+                let _ = write!(
+                    out,
+                    ", \"features\": {{\"text_fraction\": {}, \"zero_fraction\": {}, \"entropy\": {}, \
+                     \"order1_entropy\": {}, \"order2_entropy\": {}, \"float_fraction\": {}, \
+                     \"lane_entropy\": {}, \"delta_entropy\": {}}}",
+                    json_number(f.text_fraction),
+                    json_number(f.zero_fraction),
+                    json_number(f.entropy),
+                    json_number(analysis.entropy.order1),
+                    json_number(analysis.entropy.order2),
+                    json_numbers(&f.float_fraction),
+                    json_numbers(&f.lane_entropy),
+                    json_numbers(&f.delta_entropy)
+                );
+                let _ = write!(out, ", \"analysis_ms\": {}", json_number(millis(report.analysis_time) as f32));
+            }
+            None => out.push_str(", \"kind\": null, \"record_width\": null, \"features\": null, \"analysis_ms\": null"),
+        }
+
+        out.push_str(", \"tried\": [");
+        for (i, chain) in report.tried.iter().enumerate() {
+            let predicted = chain.predicted_size.map_or("null".to_string(), |p| p.to_string());
+            let _ = write!(
+                out,
+                "{}{{\"chain\": {}, \"predicted_size\": {}, \"size\": {}}}",
+                if i > 0 { ", " } else { "" },
+                json_string(&chain_label(chain, config)),
+                predicted,
+                chain.size
+            );
+        }
+        out.push_str("], \"stages\": [");
+        for (i, stage) in report.stages.iter().enumerate() {
+            let _ = write!(
+                out,
+                "{}{{\"name\": {}, \"size\": {}, \"ms\": {}}}",
+                if i > 0 { ", " } else { "" },
+                json_string(&stage.name),
+                stage.output_size,
+                json_number(millis(stage.time) as f32)
+            );
+        }
+        let _ = write!(
+            out,
+            "], \"stored\": {}, \"compressed_size\": {}, \"encode_ms\": {}, \"zstd_size\": {}}}",
+            report.is_stored(),
+            report.compressed_size,
+            json_number(millis(report.encode_time) as f32),
+            report.zstd_size
+        );
+    }
+    out.push_str("\n]\n");
+    out
+}
+
+fn json_number(value: f32) -> String {
+    if value.is_finite() { format!("{}", value) } else { "null".to_string() }
+}
+
+fn json_numbers(values: &[f32]) -> String {
+    let values: Vec<String> = values.iter().map(|&v| json_number(v)).collect();
+    format!("[{}]", values.join(", "))
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::Pipeline;
+    use crate::transforms::analyzer::analyze_chunk;
+
+    #[test]
+    fn test_explain_replays_recipe() {
+        let counters: Vec<u8> = (0u32..30_000).flat_map(|i| (i * 3).to_le_bytes()).collect();
+        let mut data = counters.clone();
+        data.extend_from_slice(&counters);
+        let config = HlcConfig::default().with_chunk_size(counters.len()).with_dedup(true);
+
+        let reports = explain(&data, &config).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].duplicate_of, Some(0));
+        assert_eq!(reports[1].offset, counters.len() as u64);
+
+        let first = &reports[0];
+        let kind = first.analysis.as_ref().unwrap().kind;
+        assert_eq!(kind, analyze_chunk(&counters, &config).unwrap().kind);
+        assert_eq!(first.decision, Decision::Auto);
+        assert!(first.tried.iter().all(|chain| chain.predicted_size.is_some()));
+        assert!(first.tried.iter().any(|chain| chain.size == first.compressed_size));
+        assert!(first.stages.len() >= 2);
+        assert_eq!(first.stages.last().unwrap().output_size, first.compressed_size);
+        assert!(first.compressed_size < first.zstd_size);
+
+        let table = to_table(&reports, &config);
+        assert!(table.contains("duplicate of chunk 0"));
+        assert!(table.contains(&first.stages[0].name));
+
+        let json = to_json(&reports, &config);
+        assert!(json.starts_with('[') && json.trim_end().ends_with(']'));
+        assert!(json.contains(&format!("\"kind\": \"{}\"", kind)));
+        assert!(json.contains("\"duplicate_of\": 0"));
+        assert_eq!(json_string("a\"b\\\n"), "\"a\\\"b\\\\\\u000a\"");
+    }
+
+    #[test]
+    fn test_explain_pinned_pipeline() {
+        let data: Vec<u8> = (0u32..10_000).flat_map(|i| (i * 5).to_le_bytes()).collect();
+        let config = HlcConfig::default().with_pipeline("delta:4,zstd".parse::<Pipeline>().unwrap());
+
+        let reports = explain(&data, &config).unwrap();
+        let report = &reports[0];
+        assert_eq!(report.decision, Decision::Pinned);
+        assert!(report.analysis.is_none());
+        assert_eq!(report.tried.len(), 1);
+        assert_eq!(report.tried[0].predicted_size, None);
+        assert_eq!(report.tried[0].size, report.compressed_size);
+        assert_eq!(report.stages.len(), 2);
+
+        let table = to_table(&reports, &config);
+        assert!(table.contains("decided by pinned"));
+        assert!(table.contains("delta:4,zstd:"));
+        assert!(!table.contains("(analysis)"));
+        assert!(to_json(&reports, &config).contains("\"decision\": \"pinned\", \"kind\": null"));
+    }
+}
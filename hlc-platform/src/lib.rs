@@ -69,20 +69,32 @@
 //! # }
 //! ```
 
+mod buffers;
 pub mod cli;
 pub mod config;
 pub mod error;
+pub mod explain;
 pub mod pipeline;
 pub mod chunk;
 pub mod container;
+pub mod recipe;
+pub mod search;
+pub mod store;
+pub mod strategy;
 pub mod transforms;
+pub mod tune;
 
 // Re-export commonly used types for convenience
 pub use config::{HlcConfig, HlcMode, ChecksumType};
 pub use error::{HlcError, Result};
 pub use pipeline::{compress, decompress, CompressionStats};
-pub use chunk::{RawChunk, ChunkStats};
+pub use chunk::{Chunking, RawChunk, ChunkStats};
 pub use container::{CompressedChunk, PipelineFlags};
+pub use recipe::{Pipeline, Recipe, RecipeStep};
+pub use search::SearchBudget;
+pub use store::ChunkStore;
+pub use strategy::{ChunkContext, Strategy};
+pub use transforms::{Codec, DataKind, Thresholds, Transform, TransformRegistry, ZstdParams};
 
 use std::io::Cursor;
 
@@ -149,6 +161,34 @@ pub fn decompress_data(compressed_data: &[u8]) -> Result<Vec<u8>> {
     Ok(output)
 }
 
+/// Decompress HLC data that was compressed with user-defined transforms
+/// 
+/// Every transform id recorded in the archive must be registered in
+/// `registry`; otherwise this fails with `HlcError::UnregisteredTransform`.
+pub fn decompress_data_with_registry(
+    compressed_data: &[u8],
+    registry: std::sync::Arc<TransformRegistry>,
+) -> Result<Vec<u8>> {
+    let mut input = Cursor::new(compressed_data);
+    let mut output = Vec::new();
+    
+    pipeline::decompress_with_registry(&mut input, &mut output, num_cpus::get(), registry)?;
+    Ok(output)
+}
+
+/// Decompress HLC data using the thread count, transform registry and zstd
+/// memory limit from `config`
+/// 
+/// Chunks whose zstd window is larger than `config.memory_limit` are rejected
+/// with `HlcError::DecompressionError` instead of being decoded.
+pub fn decompress_data_with_config(compressed_data: &[u8], config: &HlcConfig) -> Result<Vec<u8>> {
+    let mut input = Cursor::new(compressed_data);
+    let mut output = Vec::new();
+    
+    pipeline::decompress_with_config(&mut input, &mut output, config)?;
+    Ok(output)
+}
+
 /// Get information about compressed HLC data
 /// 
 /// # Arguments
@@ -1,7 +1,8 @@
-use crate::chunk::{process_chunk, split_into_chunks, merge_chunks, ChunkStats, RawChunk};
+use crate::chunk::{process_chunk_ref, split_for_config, find_duplicates, ChunkStats, RawChunk};
 use crate::config::HlcConfig;
-use crate::container::{write_hlc_container, read_hlc_container, CompressedChunk};
+use crate::container::{write_hlc_container, read_hlc_container, resolve_references, calculate_checksum, context_groups, decode_group, CompressedChunk};
 use crate::error::HlcError;
+use crate::transforms::registry::TransformRegistry;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use std::io::{Read, Write};
@@ -49,6 +50,12 @@ impl CompressionStats {
     }
 }
 
+impl Default for CompressionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Main compression function using the HLC pipeline
 pub fn compress<R: Read, W: Write>(
     reader: &mut R,
@@ -56,6 +63,50 @@ pub fn compress<R: Read, W: Write>(
     config: &HlcConfig,
 ) -> Result<CompressionStats, HlcError> {
     let start_time = Instant::now();
+
+    if !config.codec.is_available() {
+        return Err(HlcError::ConfigError(format!(
+            "{} codec support is not compiled in (enable the `{}` feature)",
+            config.codec.name(),
+            config.codec.name()
+        )));
+    }
+
+    if config.precompression && !cfg!(feature = "deflate") {
+        return Err(HlcError::ConfigError(
+            "Precompression needs deflate support (enable the `deflate` feature)".to_string(),
+        ));
+    }
+
+    if let Some(hint) = &config.hint {
+        hint.validate()?;
+    }
+    config.thresholds.validate()?;
+
+    // The field may be set directly, bypassing `with_context_group`
+    if let Some(group) = config.context_group {
+        if group < 2 {
+            return Err(HlcError::ConfigError(format!(
+                "A context group must hold at least 2 chunks, not {}",
+                group
+            )));
+        }
+    }
+
+    // Frames must stay decodable under the configured memory limit
+    if let Some(window_log) = config.zstd.effective_window_log() {
+        if window_log > config.max_window_log() {
+            return Err(HlcError::ConfigError(format!(
+                "A zstd window of 2^{} bytes exceeds the {} byte memory limit",
+                window_log, config.memory_limit
+            )));
+        }
+    }
+
+    // Report a bad pinned pipeline once rather than from every chunk
+    if let Some(pipeline) = &config.pipeline {
+        pipeline.resolve(&config.registry, config.entropy_level)?;
+    }
     
     // Read all input data
     let mut buffer = Vec::new();
@@ -87,7 +138,7 @@ pub fn compress<R: Read, W: Write>(
     pb.set_message("Compressing...");
 
     // Split data into chunks
-    let raw_chunks = split_into_chunks(&buffer, config.chunk_size);
+    let raw_chunks = split_for_config(&buffer, config);
     let total_chunks = raw_chunks.len();
 
     // Configure the global thread pool for rayon
@@ -100,13 +151,39 @@ pub fn compress<R: Read, W: Write>(
     let stats = Arc::new(Mutex::new(ChunkStats::new()));
     let pb_clone = pb.clone();
 
-    // Process chunks in parallel
+    // Identical chunks are stored once; later copies become references
+    let duplicates = if config.dedup {
+        pool.install(|| find_duplicates(&raw_chunks))
+    } else {
+        vec![None; total_chunks]
+    };
+
+    // With context groups, each chunk but the first of its group is primed
+    // with the chunk before it, unless that one is a reference
+    let previous_chunk = |id: usize| -> &[u8] {
+        match config.context_group {
+            Some(group) if id > 0 && id / group == (id - 1) / group && duplicates[id - 1].is_none() => {
+                &raw_chunks[id - 1].data
+            }
+            _ => &[],
+        }
+    };
+
+    // Process chunks in parallel; every raw chunk is at hand, so prefixed
+    // chunks encode in parallel too
     let compressed_chunks: Result<Vec<CompressedChunk>, HlcError> = pool.install(|| {
         raw_chunks
-            .into_par_iter()
-            .map(|chunk| {
+            .par_iter()
+            .zip(duplicates.par_iter())
+            .map(|(chunk, duplicate)| {
                 let chunk_size = chunk.size() as u64;
-                let result = process_chunk(chunk, config);
+                let result = match *duplicate {
+                    Some(target) => {
+                        let checksum = calculate_checksum(&chunk.data, config.checksum);
+                        Ok(CompressedChunk::new_reference(chunk.id, target, chunk.size(), checksum))
+                    }
+                    None => process_chunk_ref(chunk, previous_chunk(chunk.id), config),
+                };
                 
                 // Update progress and stats
                 if let Ok(ref compressed_chunk) = result {
@@ -154,11 +231,37 @@ pub fn decompress<R: Read, W: Write>(
     reader: &mut R,
     writer: &mut W,
     num_threads: usize,
+) -> Result<(), HlcError> {
+    decompress_with_registry(reader, writer, num_threads, Arc::new(TransformRegistry::new()))
+}
+
+/// Decompression for archives built with user-defined transforms, which must
+/// be present in `registry`
+pub fn decompress_with_registry<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    num_threads: usize,
+    registry: Arc<TransformRegistry>,
+) -> Result<(), HlcError> {
+    let config = HlcConfig::new().with_threads(num_threads).with_registry(registry);
+    decompress_with_config(reader, writer, &config)
+}
+
+/// Decompression with the thread count, transform registry and memory limit
+/// taken from `options`; everything else comes from the container
+pub fn decompress_with_config<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    options: &HlcConfig,
 ) -> Result<(), HlcError> {
     let start_time = Instant::now();
+    let num_threads = options.threads;
     
     // Read the container
     let (compressed_chunks, config) = read_hlc_container(reader)?;
+    let config = config
+        .with_registry(options.registry.clone())
+        .with_memory_limit(options.memory_limit);
     let total_chunks = compressed_chunks.len();
 
     if total_chunks == 0 {
@@ -183,27 +286,31 @@ pub fn decompress<R: Read, W: Write>(
 
     let pb_clone = pb.clone();
 
-    // Decompress chunks in parallel
-    let decompressed_chunks: Result<Vec<RawChunk>, HlcError> = pool.install(|| {
-        compressed_chunks
+    // Decompress context groups in parallel; chunks within a group depend on
+    // the one before them. References are copied from their target once it
+    // is decoded
+    let decompressed_groups: Result<Vec<Vec<RawChunk>>, HlcError> = pool.install(|| {
+        context_groups(&compressed_chunks)
             .into_par_iter()
-            .map(|chunk| {
-                let result = chunk.decompress(&config);
-                pb_clone.inc(1);
+            .map(|group| {
+                let result = decode_group(group, &config);
+                pb_clone.inc(group.len() as u64);
                 result
             })
             .collect()
     });
 
-    let mut decompressed_chunks = decompressed_chunks?;
+    let mut decompressed_chunks: Vec<RawChunk> = decompressed_groups?.into_iter().flatten().collect();
     pb.finish_with_message("Decompression complete");
 
     // Ensure chunks are in the correct order
     decompressed_chunks.sort_by_key(|c| c.id);
+    resolve_references(&compressed_chunks, &mut decompressed_chunks);
 
-    // Merge chunks and write to output
-    let merged_data = merge_chunks(&decompressed_chunks);
-    writer.write_all(&merged_data)?;
+    // Write chunks straight to the output rather than merging them first
+    for chunk in &decompressed_chunks {
+        writer.write_all(&chunk.data)?;
+    }
 
     println!("Decompression completed in {:?}", start_time.elapsed());
     Ok(())
@@ -223,10 +330,11 @@ pub fn validate<R: Read>(reader: &mut R) -> Result<bool, HlcError> {
             .progress_chars("#>-")
     );
 
-    // Validate each chunk can be decompressed correctly
-    for chunk in compressed_chunks {
-        chunk.decompress(&config)?;
-        pb.inc(1);
+    // Validate each chunk can be decompressed correctly; references were
+    // checked against their targets when the container was read
+    for group in context_groups(&compressed_chunks) {
+        decode_group(group, &config)?;
+        pb.inc(group.len() as u64);
     }
 
     pb.finish_with_message("Validation complete");
@@ -238,7 +346,7 @@ pub fn info<R: Read>(reader: &mut R) -> Result<FileInfo, HlcError> {
     let (compressed_chunks, config) = read_hlc_container(reader)?;
     
     let mut info = FileInfo {
-        version: 1,
+        version: crate::container::VERSION,
         checksum_type: config.checksum,
         total_chunks: compressed_chunks.len(),
         original_size: 0,
@@ -297,8 +405,27 @@ impl FileInfo {
                  self.chunk_stats.delta_chunks, self.total_chunks);
         println!("  Dictionary compression: {}/{}", 
                  self.chunk_stats.dictionary_chunks, self.total_chunks);
+        println!("  Float XOR coding: {}/{}",
+                 self.chunk_stats.float_xor_chunks, self.total_chunks);
+        println!("  Shuffle: {}/{}",
+                 self.chunk_stats.shuffle_chunks, self.total_chunks);
+        println!("  BWT: {}/{}",
+                 self.chunk_stats.bwt_chunks, self.total_chunks);
         println!("  Entropy coding: {}/{}", 
                  self.chunk_stats.entropy_chunks, self.total_chunks);
+        println!("    Huffman: {}, rANS: {}",
+                 self.chunk_stats.huffman_chunks, self.chunk_stats.rans_chunks);
+        println!("  Deduplicated: {}/{} ({} bytes saved)",
+                 self.chunk_stats.reference_chunks, self.total_chunks,
+                 self.chunk_stats.dedup_saved_bytes);
+        println!("  Prefixed: {}/{}",
+                 self.chunk_stats.prefixed_chunks, self.total_chunks);
+        println!("  Precompressed: {}/{}",
+                 self.chunk_stats.precomp_chunks, self.total_chunks);
+        println!("  CSV columnar: {}/{}",
+                 self.chunk_stats.csv_chunks, self.total_chunks);
+        println!("  JSON structure: {}/{}",
+                 self.chunk_stats.json_chunks, self.total_chunks);
     }
 }
 
@@ -314,7 +441,7 @@ pub fn estimate_compression<R: Read>(
         return Ok(1.0);
     }
 
-    let chunks = split_into_chunks(&buffer, config.chunk_size);
+    let chunks = split_for_config(&buffer, config);
     let ratios: Vec<f32> = chunks
         .iter()
         .map(|chunk| crate::chunk::estimate_compression_ratio(chunk, config))
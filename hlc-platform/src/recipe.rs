@@ -0,0 +1,532 @@
+//! Per-chunk transform recipes
+//! A recipe is the ordered list of steps that produced a chunk, each a
+//! transform id from the registry plus the parameters it ran with; decoding
+//! undoes the steps in reverse. The entropy stage is the reserved step id 0
+//!
+//! Format: [STEP COUNT][(ID: u16 LE, PARAM LEN, PARAMS)...]
+//!
+//! A `Pipeline` is the user-facing spelling of a recipe, such as
+//! `delta:4,shuffle:8,zstd:19`, used to pin the chain instead of analyzing
+
+use crate::buffers;
+use crate::container::PipelineFlags;
+use crate::error::HlcError;
+use crate::config::HlcConfig;
+use crate::transforms::entropy::{self, Codec, ZstdParams, DEFAULT_MAX_WINDOW_LOG};
+use crate::transforms::registry::{self, TransformRegistry};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Step id of the final entropy stage; params are [CODEC ID, LEVEL: i8]
+pub const ENTROPY_STEP_ID: u16 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipeStep {
+    pub id: u16,
+    pub params: Vec<u8>,
+}
+
+impl RecipeStep {
+    pub fn new(id: u16, params: Vec<u8>) -> Self {
+        Self { id, params }
+    }
+
+    pub fn entropy(codec: Codec, level: i32) -> Self {
+        let level = level.clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+        Self::new(ENTROPY_STEP_ID, vec![codec.id(), level as u8])
+    }
+
+    pub fn is_entropy(&self) -> bool {
+        self.id == ENTROPY_STEP_ID
+    }
+
+    /// The step in pipeline syntax, e.g. `delta:4` or `zstd:19`; transforms
+    /// missing from `registry` are shown by id
+    pub fn label(&self, registry: &TransformRegistry) -> String {
+        let mut label = if self.is_entropy() {
+            match self.codec() {
+                Ok(codec) => return format!("{}:{}", codec.name(), self.level()),
+                Err(_) => "entropy".to_string(),
+            }
+        } else {
+            match registry.get(self.id) {
+                Some(transform) => transform.name().to_string(),
+                None => format!("#{}", self.id),
+            }
+        };
+        for param in &self.params {
+            label.push_str(&format!(":{}", param));
+        }
+        label
+    }
+
+    /// Entropy level on the zstd scale; 0 for steps recorded without one
+    fn level(&self) -> i32 {
+        self.params.get(1).map_or(0, |&level| level as i8 as i32)
+    }
+
+    fn codec(&self) -> Result<Codec, HlcError> {
+        let id = *self.params.first().ok_or_else(|| {
+            HlcError::InvalidFormat("Entropy step is missing its codec".to_string())
+        })?;
+        Codec::from_id(id).ok_or_else(|| HlcError::InvalidFormat(format!("Unknown entropy codec: {}", id)))
+    }
+}
+
+/// Ordered steps applied to a chunk; an empty recipe means the data is stored
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Recipe {
+    pub steps: Vec<RecipeStep>,
+}
+
+impl Recipe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, step: RecipeStep) {
+        self.steps.push(step);
+    }
+
+    pub fn is_stored(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Codec of the entropy step, or zstd when there is none
+    pub fn codec(&self) -> Codec {
+        self.steps
+            .iter()
+            .find(|s| s.is_entropy())
+            .and_then(|s| s.codec().ok())
+            .unwrap_or_default()
+    }
+
+    /// Summary of the built-in stages used, for statistics
+    pub fn flags(&self) -> PipelineFlags {
+        if self.is_stored() {
+            return PipelineFlags::STORED;
+        }
+
+        self.steps.iter().fold(PipelineFlags::empty(), |flags, step| {
+            flags | match step.id {
+                ENTROPY_STEP_ID => PipelineFlags::ENTROPY,
+                registry::RLE_ID => PipelineFlags::RLE,
+                registry::DELTA_ID => PipelineFlags::DELTA,
+                registry::DICTIONARY_ID => PipelineFlags::DICTIONARY,
+                registry::FLOAT_XOR_ID => PipelineFlags::FLOAT_XOR,
+                registry::SHUFFLE_ID => PipelineFlags::SHUFFLE,
+                registry::BWT_ID => PipelineFlags::BWT,
+                _ => PipelineFlags::empty(),
+            }
+        })
+    }
+
+    /// Rebuilds the recipe implied by a version 1-3 chunk header: registered
+    /// transforms first, then the flagged built-ins in their fixed order
+    pub fn from_legacy(flags: PipelineFlags, codec: Codec, transforms: &[u16]) -> Self {
+        let mut recipe = Recipe::new();
+        for &id in transforms {
+            recipe.push(RecipeStep::new(id, Vec::new()));
+        }
+
+        if flags.contains(PipelineFlags::STORED) {
+            return recipe;
+        }
+
+        let order = [
+            (PipelineFlags::FLOAT_XOR, registry::FLOAT_XOR_ID),
+            (PipelineFlags::SHUFFLE, registry::SHUFFLE_ID),
+            (PipelineFlags::BWT, registry::BWT_ID),
+            (PipelineFlags::RLE, registry::RLE_ID),
+            (PipelineFlags::DELTA, registry::DELTA_ID),
+            (PipelineFlags::DICTIONARY, registry::DICTIONARY_ID),
+        ];
+        for (flag, id) in order {
+            if flags.contains(flag) {
+                recipe.push(RecipeStep::new(id, Vec::new()));
+            }
+        }
+
+        if flags.contains(PipelineFlags::ENTROPY) {
+            recipe.push(RecipeStep::entropy(codec, 0));
+        }
+
+        recipe
+    }
+
+    /// Applies every step in order
+    pub fn encode(&self, data: &[u8], registry: &TransformRegistry) -> Result<Vec<u8>, HlcError> {
+        self.encode_steps(data, registry, &ZstdParams::default())
+    }
+
+    /// Like `encode`, with the config's registry and zstd parameters
+    pub fn encode_with_config(&self, data: &[u8], config: &HlcConfig) -> Result<Vec<u8>, HlcError> {
+        self.encode_steps(data, &config.registry, &config.zstd)
+    }
+
+    fn encode_steps(&self, data: &[u8], registry: &TransformRegistry, zstd: &ZstdParams) -> Result<Vec<u8>, HlcError> {
+        let mut data = Cow::Borrowed(data);
+        for step in &self.steps {
+            let encoded = if !step.is_entropy() {
+                registry.require(step.id)?.encode(&data, &step.params)?
+            } else if step.codec()? == Codec::Zstd {
+                entropy::encode_zstd(&data, step.level(), &[], zstd)?
+            } else {
+                entropy::encode_with(step.codec()?, &data, step.level())?
+            };
+            recycle(std::mem::replace(&mut data, Cow::Owned(encoded)));
+        }
+
+        Ok(data.into_owned())
+    }
+
+    /// Undoes every step in reverse order
+    ///
+    /// All transform ids are resolved before any work is done, so an archive
+    /// that needs an unregistered transform fails up front. A native entropy
+    /// stage may decode to at most the default memory limit
+    pub fn decode(&self, data: &[u8], registry: &TransformRegistry) -> Result<Vec<u8>, HlcError> {
+        self.decode_steps(data, &[], registry, DEFAULT_MAX_WINDOW_LOG, 1 << DEFAULT_MAX_WINDOW_LOG)
+    }
+
+    /// Like `decode`, with the config's registry and memory limit, for a chunk
+    /// of `original_size` bytes whose zstd entropy stage may have been primed
+    /// with `prefix` (the tail of the preceding chunk)
+    ///
+    /// A native entropy stage may decode to the larger of the chunk size and
+    /// the memory limit; transforms such as precompression can leave more
+    /// data for the entropy stage than the chunk holds
+    pub fn decode_with_config(
+        &self,
+        data: &[u8],
+        prefix: &[u8],
+        original_size: usize,
+        config: &HlcConfig,
+    ) -> Result<Vec<u8>, HlcError> {
+        let max_len = original_size.max(usize::try_from(config.memory_limit).unwrap_or(usize::MAX));
+        self.decode_steps(data, prefix, &config.registry, config.max_window_log(), max_len)
+    }
+
+    fn decode_steps(
+        &self,
+        data: &[u8],
+        prefix: &[u8],
+        registry: &TransformRegistry,
+        max_window_log: u32,
+        max_len: usize,
+    ) -> Result<Vec<u8>, HlcError> {
+        for step in self.steps.iter().filter(|s| !s.is_entropy()) {
+            registry.require(step.id)?;
+        }
+
+        let mut data = Cow::Borrowed(data);
+        for step in self.steps.iter().rev() {
+            let decoded = if !step.is_entropy() {
+                registry.require(step.id)?.decode(&data, &step.params)?
+            } else if step.codec()? == Codec::Zstd {
+                entropy::decode_zstd(&data, prefix, max_window_log)?
+            } else {
+                entropy::decode_with(step.codec()?, &data, max_len)?
+            };
+            recycle(std::mem::replace(&mut data, Cow::Owned(decoded)));
+        }
+
+        Ok(data.into_owned())
+    }
+
+    /// Serialized size in bytes
+    pub fn encoded_len(&self) -> usize {
+        1 + self.steps.iter().map(|s| 2 + 1 + s.params.len()).sum::<usize>()
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), HlcError> {
+        let count = u8::try_from(self.steps.len())
+            .map_err(|_| HlcError::PipelineError("Too many steps in one chunk recipe".to_string()))?;
+        writer.write_u8(count)?;
+
+        for step in &self.steps {
+            let param_len = u8::try_from(step.params.len()).map_err(|_| {
+                HlcError::PipelineError(format!("Parameters of transform {} are too long", step.id))
+            })?;
+            writer.write_u16::<LittleEndian>(step.id)?;
+            writer.write_u8(param_len)?;
+            writer.write_all(&step.params)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, HlcError> {
+        let count = reader.read_u8()?;
+        let mut recipe = Recipe::new();
+
+        for _ in 0..count {
+            let id = reader.read_u16::<LittleEndian>()?;
+            let param_len = reader.read_u8()?;
+            let mut params = vec![0u8; param_len as usize];
+            reader.read_exact(&mut params)?;
+            recipe.push(RecipeStep::new(id, params));
+        }
+
+        Ok(recipe)
+    }
+}
+
+/// A fixed transform chain given by the user in place of the analyzer
+///
+/// Stages are separated by commas and written as `name[:param...]`, where the
+/// name is a registered transform and each param is one byte of its params.
+/// A codec name may end the chain as the entropy stage, with an optional
+/// level on the zstd scale; without one no entropy coding is done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    stages: Vec<PipelineStage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PipelineStage {
+    name: String,
+    args: Vec<i32>,
+}
+
+impl Pipeline {
+    /// Resolves the stage names against `registry` into the recipe that every
+    /// chunk is encoded with; `default_level` applies to a codec given without
+    /// a level
+    pub fn resolve(&self, registry: &TransformRegistry, default_level: i32) -> Result<Recipe, HlcError> {
+        let mut recipe = Recipe::new();
+
+        for (index, stage) in self.stages.iter().enumerate() {
+            if let Ok(codec) = stage.name.parse::<Codec>() {
+                if index + 1 != self.stages.len() {
+                    return Err(HlcError::ConfigError(format!(
+                        "Codec '{}' must be the last pipeline stage",
+                        stage.name
+                    )));
+                }
+                if !codec.is_available() {
+                    return Err(HlcError::ConfigError(format!(
+                        "{} codec support is not compiled in (enable the `{}` feature)",
+                        codec.name(),
+                        codec.name()
+                    )));
+                }
+
+                let level = match stage.args.as_slice() {
+                    [] => default_level,
+                    [level] => *level,
+                    _ => return Err(stage.invalid("a codec takes at most one level")),
+                };
+                let levels = codec.levels();
+                if stage.args.len() == 1 && !levels.contains(&level) {
+                    return Err(stage.invalid(&format!(
+                        "level must be {} to {}",
+                        levels.start(),
+                        levels.end()
+                    )));
+                }
+                recipe.push(RecipeStep::entropy(codec, level));
+            } else {
+                let transform = registry.find_by_name(&stage.name).ok_or_else(|| {
+                    HlcError::ConfigError(format!("Unknown pipeline stage '{}'", stage.name))
+                })?;
+                let params = stage
+                    .args
+                    .iter()
+                    .map(|&arg| u8::try_from(arg).map_err(|_| stage.invalid("params must be 0-255")))
+                    .collect::<Result<Vec<u8>, HlcError>>()?;
+                transform.check_params(&params).map_err(|e| match e {
+                    HlcError::TransformError(reason) | HlcError::ConfigError(reason) => stage.invalid(&reason),
+                    e => e,
+                })?;
+                recipe.push(RecipeStep::new(transform.id(), params));
+            }
+        }
+
+        Ok(recipe)
+    }
+}
+
+impl PipelineStage {
+    fn invalid(&self, reason: &str) -> HlcError {
+        HlcError::ConfigError(format!("Invalid pipeline stage '{}': {}", self, reason))
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = HlcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut stages = Vec::new();
+
+        for stage in s.split(',') {
+            let mut parts = stage.trim().split(':');
+            let name = parts.next().unwrap_or_default().trim().to_lowercase();
+            if name.is_empty() {
+                return Err(HlcError::ConfigError(format!("Invalid pipeline: {}", s)));
+            }
+
+            let args = parts
+                .map(|arg| {
+                    arg.trim().parse::<i32>().map_err(|_| {
+                        HlcError::ConfigError(format!("Invalid parameter '{}' for pipeline stage '{}'", arg, name))
+                    })
+                })
+                .collect::<Result<Vec<i32>, HlcError>>()?;
+
+            stages.push(PipelineStage { name, args });
+        }
+
+        Ok(Self { stages })
+    }
+}
+
+impl fmt::Display for PipelineStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for arg in &self.args {
+            write!(f, ":{}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, stage) in self.stages.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", stage)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hands an intermediate step output back to the buffer pool
+fn recycle(data: Cow<'_, [u8]>) {
+    if let Cow::Owned(buffer) = data {
+        buffers::give(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_recipe_serialization() {
+        let mut recipe = Recipe::new();
+        recipe.push(RecipeStep::new(registry::DELTA_ID, vec![4]));
+        recipe.push(RecipeStep::new(registry::RLE_ID, Vec::new()));
+        recipe.push(RecipeStep::entropy(Codec::Zstd, 19));
+
+        let mut buffer = Vec::new();
+        recipe.write(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), recipe.encoded_len());
+
+        let decoded = Recipe::read(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(decoded, recipe);
+        assert_eq!(decoded.flags(), PipelineFlags::DELTA | PipelineFlags::RLE | PipelineFlags::ENTROPY);
+
+        let labels: Vec<String> = recipe.steps.iter().map(|s| s.label(&TransformRegistry::new())).collect();
+        assert_eq!(labels, ["delta:4", "rle", "zstd:19"]);
+    }
+
+    #[test]
+    fn test_recipe_decode_order() {
+        let registry = TransformRegistry::new();
+        let data: Vec<u8> = (0u32..500).flat_map(|i| (i * 3).to_le_bytes()).collect();
+
+        // Delta before RLE, the reverse of the fixed legacy order
+        let delta = registry.require(registry::DELTA_ID).unwrap().encode(&data, &[4]).unwrap();
+        let rle = registry.require(registry::RLE_ID).unwrap().encode(&delta, &[]).unwrap();
+        let coded = entropy::encode_with(Codec::Zstd, &rle, 5).unwrap();
+
+        let mut recipe = Recipe::new();
+        recipe.push(RecipeStep::new(registry::DELTA_ID, vec![4]));
+        recipe.push(RecipeStep::new(registry::RLE_ID, Vec::new()));
+        recipe.push(RecipeStep::entropy(Codec::Zstd, 5));
+
+        assert_eq!(recipe.decode(&coded, &registry).unwrap(), data);
+    }
+
+    #[test]
+    fn test_legacy_recipe() {
+        let flags = PipelineFlags::RLE | PipelineFlags::DELTA | PipelineFlags::ENTROPY;
+        let recipe = Recipe::from_legacy(flags, Codec::Rans, &[300]);
+
+        let ids: Vec<u16> = recipe.steps.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![300, registry::RLE_ID, registry::DELTA_ID, ENTROPY_STEP_ID]);
+        assert_eq!(recipe.codec(), Codec::Rans);
+
+        assert!(Recipe::from_legacy(PipelineFlags::STORED, Codec::Zstd, &[]).is_stored());
+    }
+
+    #[test]
+    fn test_recipe_missing_transform() {
+        let mut recipe = Recipe::new();
+        recipe.push(RecipeStep::new(4242, Vec::new()));
+
+        match recipe.decode(b"data", &TransformRegistry::new()) {
+            Err(HlcError::UnregisteredTransform(4242)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pipeline_parse_and_resolve() {
+        let pipeline: Pipeline = "delta:4, shuffle:8:1 ,ZSTD:19".parse().unwrap();
+        assert_eq!(pipeline.to_string(), "delta:4,shuffle:8:1,zstd:19");
+
+        let recipe = pipeline.resolve(&TransformRegistry::new(), 5).unwrap();
+        assert_eq!(
+            recipe.steps,
+            vec![
+                RecipeStep::new(registry::DELTA_ID, vec![4]),
+                RecipeStep::new(registry::SHUFFLE_ID, vec![8, 1]),
+                RecipeStep::entropy(Codec::Zstd, 19),
+            ]
+        );
+
+        let recipe: Recipe = "rle,rans".parse::<Pipeline>().unwrap().resolve(&TransformRegistry::new(), 5).unwrap();
+        assert_eq!(recipe.codec(), Codec::Rans);
+    }
+
+    #[test]
+    fn test_pipeline_errors() {
+        let registry = TransformRegistry::new();
+        let resolve = |spec: &str| spec.parse::<Pipeline>().and_then(|p| p.resolve(&registry, 5));
+
+        assert!(resolve("").is_err());
+        assert!(resolve("delta,,zstd").is_err());
+        assert!(resolve("delta:x").is_err());
+        assert!(resolve("delta:256").is_err());
+        assert!(resolve("zstd:19,delta").is_err());
+        assert!(resolve("zstd:1:2").is_err());
+        assert!(resolve("nonesuch").is_err());
+
+        // Params and levels are checked against the transform and codec
+        for bad in ["delta:3", "delta:4:1", "float_xor:2", "shuffle:0", "shuffle:4:9", "rle:1", "zstd:23", "xz:0"] {
+            match resolve(bad) {
+                Err(HlcError::ConfigError(message)) => assert!(message.contains(bad), "{}", message),
+                other => panic!("{} resolved to {:?}", bad, other),
+            }
+        }
+        assert!(resolve("delta:2,shuffle:8:1,zstd:-5").is_ok());
+    }
+
+    #[test]
+    fn test_recipe_encode_roundtrip() {
+        let registry = TransformRegistry::new();
+        let data: Vec<u8> = (0u32..3000).flat_map(|i| (i * 11).to_le_bytes()).collect();
+        let recipe = "delta:4,rle,huffman".parse::<Pipeline>().unwrap().resolve(&registry, 5).unwrap();
+
+        let encoded = recipe.encode(&data, &registry).unwrap();
+        assert_eq!(recipe.decode(&encoded, &registry).unwrap(), data);
+    }
+}
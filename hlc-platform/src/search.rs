@@ -0,0 +1,237 @@
+//! Exhaustive trial-based strategy search
+//! Instead of trusting the analyzer's sampled trials, every candidate chain of
+//! built-in transforms is applied to the whole chunk and entropy coded, and the
+//! smallest final output wins. Candidates are tried in parallel, shortest
+//! chains first, until the configured budget runs out
+
+use crate::chunk::TriedChain;
+use crate::config::{HlcConfig, HlcMode};
+use crate::error::HlcError;
+use crate::recipe::{Recipe, RecipeStep};
+use crate::transforms::entropy::{self, Codec};
+use crate::transforms::registry;
+use rayon::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Longest transform chain the search builds
+const MAX_CHAIN_LEN: usize = 3;
+
+/// Encoded chunk data and the recipe steps that produced it
+type Trial = (Vec<u8>, Vec<RecipeStep>);
+
+/// Limits on how much work the search spends per chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchBudget {
+    /// Most transform chains tried per chunk
+    pub max_candidates: usize,
+    /// Wall-clock limit per chunk; candidates not started by then are skipped,
+    /// which makes the output depend on machine speed
+    pub time_limit: Option<Duration>,
+}
+
+impl Default for SearchBudget {
+    fn default() -> Self {
+        Self {
+            max_candidates: 64,
+            time_limit: None,
+        }
+    }
+}
+
+impl SearchBudget {
+    pub fn with_max_candidates(mut self, max_candidates: usize) -> Self {
+        self.max_candidates = max_candidates.max(1);
+        self
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+}
+
+/// Encodes `input` with every candidate chain within the budget, returning the
+/// smallest output and the recipe steps that produced it, or `None` when no
+/// candidate was tried; every chain that was encoded is added to `tried`
+pub(crate) fn search_chunk(
+    input: &[u8],
+    config: &HlcConfig,
+    budget: &SearchBudget,
+    tried: &mut Vec<TriedChain>,
+) -> Result<Option<Trial>, HlcError> {
+    let deadline = budget.time_limit.map(|limit| Instant::now() + limit);
+    let candidates: Vec<Vec<RecipeStep>> = candidate_chains(config.mode)
+        .into_iter()
+        .take(budget.max_candidates)
+        .collect();
+
+    let results = candidates
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, chain)| {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+            trial(input, chain, config).map(|result| result.map(|r| (index, r)))
+        })
+        .collect::<Result<Vec<_>, HlcError>>()?;
+
+    let results: Vec<(usize, Trial)> = results.into_iter().flatten().collect();
+    tried.extend(results.iter().map(|(_, (data, steps))| TriedChain {
+        steps: steps.clone(),
+        predicted_size: None,
+        size: data.len(),
+    }));
+
+    // Ties go to the earlier, shorter chain so the result does not depend on
+    // scheduling
+    Ok(results
+        .into_iter()
+        .min_by_key(|(index, (data, _))| (data.len(), *index))
+        .map(|(_, result)| result))
+}
+
+/// Applies one chain exactly and entropy codes the result with each suitable
+/// codec, keeping the smallest
+fn trial(
+    input: &[u8],
+    chain: Vec<RecipeStep>,
+    config: &HlcConfig,
+) -> Result<Option<Trial>, HlcError> {
+    let recipe = Recipe { steps: chain };
+    let transformed = match recipe.encode(input, &config.registry) {
+        Ok(data) => data,
+        // Parameters that do not fit this chunk simply rule the candidate out
+        Err(HlcError::TransformError(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut best: Option<(Codec, Vec<u8>)> = None;
+    for codec in [config.codec, Codec::Huffman, Codec::Rans] {
+        if best.as_ref().is_some_and(|(chosen, _)| *chosen == codec) {
+            continue;
+        }
+        let encoded = match codec {
+            Codec::Zstd => entropy::encode_zstd(&transformed, config.entropy_level, &[], &config.zstd)?,
+            codec => entropy::encode_with(codec, &transformed, config.entropy_level)?,
+        };
+        match &best {
+            Some((_, data)) if data.len() <= encoded.len() => {}
+            _ => best = Some((codec, encoded)),
+        }
+    }
+
+    let (codec, data) = best.expect("at least one codec is tried");
+    let mut steps = recipe.steps;
+    steps.push(RecipeStep::entropy(codec, config.entropy_level));
+    Ok(Some((data, steps)))
+}
+
+/// Candidate chains in the order they are tried: single transforms, then
+/// pairs, then triples
+///
+/// Element-wise transforms (float XOR, shuffle) only make sense on the raw
+/// values, so they may only start a chain, and never together or with BWT
+fn candidate_chains(mode: HlcMode) -> Vec<Vec<RecipeStep>> {
+    let mut atoms = vec![
+        RecipeStep::new(registry::DELTA_ID, vec![1]),
+        RecipeStep::new(registry::RLE_ID, Vec::new()),
+        RecipeStep::new(registry::SHUFFLE_ID, vec![4, 0]),
+        RecipeStep::new(registry::DELTA_ID, vec![4]),
+        RecipeStep::new(registry::DELTA_ID, vec![2]),
+        RecipeStep::new(registry::SHUFFLE_ID, vec![8, 0]),
+        RecipeStep::new(registry::SHUFFLE_ID, vec![2, 0]),
+        RecipeStep::new(registry::FLOAT_XOR_ID, vec![8]),
+        RecipeStep::new(registry::FLOAT_XOR_ID, vec![4]),
+        RecipeStep::new(registry::BWT_ID, Vec::new()),
+        RecipeStep::new(registry::DICTIONARY_ID, Vec::new()),
+    ];
+    if mode == HlcMode::Max {
+        atoms.push(RecipeStep::new(registry::SHUFFLE_ID, vec![4, 1]));
+        atoms.push(RecipeStep::new(registry::SHUFFLE_ID, vec![8, 1]));
+    }
+
+    let mut chains: Vec<Vec<RecipeStep>> = vec![Vec::new()];
+    let mut layer: Vec<Vec<RecipeStep>> = vec![Vec::new()];
+    for _ in 0..MAX_CHAIN_LEN {
+        let mut next = Vec::new();
+        for chain in &layer {
+            for atom in &atoms {
+                if can_extend(chain, atom) {
+                    let mut extended = chain.clone();
+                    extended.push(atom.clone());
+                    next.push(extended);
+                }
+            }
+        }
+        chains.extend(next.iter().cloned());
+        layer = next;
+    }
+
+    chains
+}
+
+fn can_extend(chain: &[RecipeStep], step: &RecipeStep) -> bool {
+    let element_wise = |id| id == registry::FLOAT_XOR_ID || id == registry::SHUFFLE_ID;
+
+    if chain.iter().any(|s| s.id == step.id) {
+        return false;
+    }
+    if element_wise(step.id) && !chain.is_empty() {
+        return false;
+    }
+    let starts_element_wise = chain.first().is_some_and(|s| element_wise(s.id));
+    !(step.id == registry::BWT_ID && starts_element_wise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidate_chains() {
+        let chains = candidate_chains(HlcMode::Balanced);
+
+        // The bare entropy stage comes first, then chains by length
+        assert!(chains[0].is_empty());
+        assert!(chains.windows(2).all(|w| w[0].len() <= w[1].len()));
+        assert!(chains.iter().all(|c| c.len() <= MAX_CHAIN_LEN));
+
+        for chain in &chains {
+            assert!(chain.iter().skip(1).all(|s| s.id != registry::SHUFFLE_ID && s.id != registry::FLOAT_XOR_ID));
+        }
+        assert!(candidate_chains(HlcMode::Max).len() > chains.len());
+    }
+
+    #[test]
+    fn test_search_finds_smaller_encoding() {
+        // Slowly rising 32-bit counters, which a stride-4 delta reduces to a
+        // handful of repeated words
+        let data: Vec<u8> = (0u32..16_384).flat_map(|i| (1_000_000 + i * 3).to_le_bytes()).collect();
+        let config = HlcConfig::default();
+
+        let (encoded, steps) = search_chunk(&data, &config, &SearchBudget::default(), &mut Vec::new()).unwrap().unwrap();
+        assert!(encoded.len() < entropy::encode_with(Codec::Zstd, &data, 5).unwrap().len());
+        assert!(steps.last().unwrap().is_entropy());
+
+        let recipe = Recipe { steps };
+        assert_eq!(recipe.decode(&encoded, &config.registry).unwrap(), data);
+    }
+
+    #[test]
+    fn test_search_budget() {
+        let data = b"budgeted search input ".repeat(200);
+        let config = HlcConfig::default();
+
+        let one = SearchBudget::default().with_max_candidates(1);
+        let mut tried = Vec::new();
+        let (_, steps) = search_chunk(&data, &config, &one, &mut tried).unwrap().unwrap();
+        assert_eq!(steps.len(), 1); // only the bare entropy stage was tried
+        assert_eq!(tried.len(), 1);
+        assert_eq!(tried[0].steps, steps);
+
+        let expired = SearchBudget::default().with_time_limit(Duration::ZERO);
+        assert!(search_chunk(&data, &config, &expired, &mut tried).unwrap().is_none());
+        assert_eq!(tried.len(), 1);
+    }
+}
@@ -0,0 +1,493 @@
+//! Content-addressed, deduplicating chunk store in a local directory
+//! Inputs are split (content-defined chunking is recommended), each chunk is
+//! compressed on its own and saved under the SHA-256 of its content, and a
+//! manifest lists the chunks of each input in order. Chunks already in the
+//! store are never compressed or written again, so successive snapshots of
+//! mostly unchanged data only add the chunks that changed
+//!
+//! Layout: `<root>/chunks/<first 2 hex digits>/<hex digest>` holds one chunk
+//! as a single-chunk HLC container; `<root>/manifests/<name>` is text:
+//! `HLC-MANIFEST 1` followed by one `<hex digest> <size>` line per chunk
+//!
+//! `put`, `get` and `remove` hold `<root>/lock` shared and `gc` holds it
+//! exclusively, so a collection never deletes a chunk a concurrent `put`
+//! skipped or is still writing. The lock is advisory: it only covers processes
+//! going through `ChunkStore`, and only on filesystems with working file locks
+
+use crate::chunk::{process_chunk_ref, split_for_config, RawChunk};
+use crate::config::HlcConfig;
+use crate::container::{read_hlc_container, write_hlc_container};
+use crate::error::HlcError;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const MANIFEST_HEADER: &str = "HLC-MANIFEST 1";
+
+/// Suffix of objects being written; leftovers from interrupted writes are
+/// removed by `gc`
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Lock file that `gc` holds exclusively and every other writer shared
+const LOCK_FILE: &str = "lock";
+
+/// Hex SHA-256 of a chunk's content
+pub type ChunkKey = String;
+
+/// A local directory of compressed chunks and manifests
+#[derive(Debug, Clone)]
+pub struct ChunkStore {
+    root: PathBuf,
+}
+
+/// One chunk of a stored input, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub key: ChunkKey,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PutStats {
+    pub original_size: u64,
+    pub total_chunks: usize,
+    /// Chunks that were not in the store yet
+    pub new_chunks: usize,
+    /// Compressed bytes written for the new chunks
+    pub stored_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GcStats {
+    pub removed_chunks: usize,
+    pub freed_bytes: u64,
+}
+
+impl Manifest {
+    pub fn original_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("{}\n", MANIFEST_HEADER);
+        for entry in &self.entries {
+            text.push_str(&format!("{} {}\n", entry.key, entry.size));
+        }
+        text
+    }
+
+    fn parse(text: &str) -> Result<Self, HlcError> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(HlcError::StoreError("Not an HLC manifest".to_string()));
+        }
+
+        let entries = lines
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let invalid = || HlcError::StoreError(format!("Invalid manifest line: {}", line));
+                let (key, size) = line.split_once(' ').ok_or_else(invalid)?;
+                if !is_valid_key(key) {
+                    return Err(invalid());
+                }
+                let size = size.trim().parse().map_err(|_| invalid())?;
+                Ok(ManifestEntry { key: key.to_string(), size })
+            })
+            .collect::<Result<Vec<_>, HlcError>>()?;
+
+        Ok(Self { entries })
+    }
+}
+
+impl ChunkStore {
+    /// Opens the store at `root`, creating its directories if needed
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self, HlcError> {
+        let store = Self { root: root.into() };
+        fs::create_dir_all(store.chunks_dir())?;
+        fs::create_dir_all(store.manifests_dir())?;
+        Ok(store)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Chunks, compresses and stores `reader`'s content under manifest `name`,
+    /// replacing any manifest of that name; only chunks missing from the
+    /// store are compressed and written
+    pub fn put<R: Read>(&self, name: &str, reader: &mut R, config: &HlcConfig) -> Result<PutStats, HlcError> {
+        let manifest_path = self.manifest_path(name)?;
+        let _lock = self.lock(false)?;
+
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer)?;
+        let raw_chunks: Vec<RawChunk> = if buffer.is_empty() {
+            Vec::new()
+        } else {
+            split_for_config(&buffer, config)
+        };
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .build()
+            .map_err(|e| HlcError::ThreadPoolError(e.to_string()))?;
+
+        let keys: Vec<ChunkKey> = pool.install(|| raw_chunks.par_iter().map(|c| chunk_key(&c.data)).collect());
+
+        // The same chunk may recur within one input; compress it once
+        let mut seen = HashSet::new();
+        let missing: Vec<(&RawChunk, &ChunkKey)> = raw_chunks
+            .iter()
+            .zip(&keys)
+            .filter(|(_, key)| seen.insert(key.as_str()) && !self.chunk_path(key).exists())
+            .collect();
+
+        let written = pool.install(|| {
+            missing
+                .par_iter()
+                .map(|(chunk, key)| self.write_chunk(key, chunk, config))
+                .collect::<Result<Vec<u64>, HlcError>>()
+        })?;
+
+        let manifest = Manifest {
+            entries: raw_chunks
+                .iter()
+                .zip(keys)
+                .map(|(chunk, key)| ManifestEntry { key, size: chunk.size() as u64 })
+                .collect(),
+        };
+        // The manifest goes last, so it never names a chunk that is not stored
+        write_atomically(&manifest_path, manifest.to_text().as_bytes())?;
+
+        Ok(PutStats {
+            original_size: buffer.len() as u64,
+            total_chunks: raw_chunks.len(),
+            new_chunks: written.len(),
+            stored_bytes: written.iter().sum(),
+        })
+    }
+
+    /// Restores the input stored under manifest `name`, verifying every chunk
+    /// against its key; `config` supplies the transform registry, memory limit
+    /// and threads
+    pub fn get<W: Write>(&self, name: &str, writer: &mut W, config: &HlcConfig) -> Result<u64, HlcError> {
+        let _lock = self.lock(false)?;
+        let manifest = self.manifest(name)?;
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .build()
+            .map_err(|e| HlcError::ThreadPoolError(e.to_string()))?;
+
+        let chunks = pool.install(|| {
+            manifest
+                .entries
+                .par_iter()
+                .map(|entry| self.read_chunk(entry, config))
+                .collect::<Result<Vec<Vec<u8>>, HlcError>>()
+        })?;
+
+        for chunk in &chunks {
+            writer.write_all(chunk)?;
+        }
+        Ok(manifest.original_size())
+    }
+
+    pub fn manifest(&self, name: &str) -> Result<Manifest, HlcError> {
+        let path = self.manifest_path(name)?;
+        let text = fs::read_to_string(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => HlcError::StoreError(format!("No manifest named '{}'", name)),
+            _ => HlcError::Io(e),
+        })?;
+        Manifest::parse(&text)
+    }
+
+    /// Names of all stored manifests, sorted
+    pub fn manifests(&self) -> Result<Vec<String>, HlcError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(self.manifests_dir())? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !name.ends_with(TEMP_SUFFIX) {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Deletes manifest `name`; its chunks stay until the next `gc`
+    pub fn remove(&self, name: &str) -> Result<(), HlcError> {
+        let path = self.manifest_path(name)?;
+        let _lock = self.lock(false)?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Deletes every chunk no manifest references, and leftovers of
+    /// interrupted writes
+    ///
+    /// Waits for running `put`, `get` and `remove` calls to finish and holds
+    /// off new ones until it is done
+    pub fn gc(&self) -> Result<GcStats, HlcError> {
+        let _lock = self.lock(true)?;
+        let mut live = HashSet::new();
+        for name in self.manifests()? {
+            live.extend(self.manifest(&name)?.entries.into_iter().map(|e| e.key));
+        }
+
+        let mut stats = GcStats::default();
+        for shard in fs::read_dir(self.chunks_dir())? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for object in fs::read_dir(shard.path())? {
+                let object = object?;
+                let name = object.file_name().to_string_lossy().into_owned();
+                if live.contains(&name) {
+                    continue;
+                }
+                stats.freed_bytes += object.metadata()?.len();
+                stats.removed_chunks += usize::from(!name.ends_with(TEMP_SUFFIX));
+                fs::remove_file(object.path())?;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn write_chunk(&self, key: &str, chunk: &RawChunk, config: &HlcConfig) -> Result<u64, HlcError> {
+        let compressed = process_chunk_ref(chunk, &[], config)?;
+        let mut object = Vec::new();
+        write_hlc_container(&mut object, &[compressed], config)?;
+
+        let path = self.chunk_path(key);
+        if let Some(shard) = path.parent() {
+            fs::create_dir_all(shard)?;
+        }
+        write_atomically(&path, &object)?;
+        Ok(object.len() as u64)
+    }
+
+    fn read_chunk(&self, entry: &ManifestEntry, config: &HlcConfig) -> Result<Vec<u8>, HlcError> {
+        let object = fs::read(self.chunk_path(&entry.key)).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => HlcError::StoreError(format!("Chunk {} is missing from the store", entry.key)),
+            _ => HlcError::Io(e),
+        })?;
+
+        let (chunks, object_config) = read_hlc_container(&mut object.as_slice())?;
+        let object_config = object_config
+            .with_registry(config.registry.clone())
+            .with_memory_limit(config.memory_limit);
+        let chunk = match chunks.as_slice() {
+            [chunk] => chunk.decompress(&object_config)?,
+            _ => return Err(HlcError::StoreError(format!("Chunk {} is not a single-chunk object", entry.key))),
+        };
+
+        if chunk.data.len() as u64 != entry.size || chunk_key(&chunk.data) != entry.key {
+            return Err(HlcError::StoreError(format!("Chunk {} does not match its key", entry.key)));
+        }
+        Ok(chunk.data)
+    }
+
+    /// Blocks until the store lock is held, shared or exclusively; it is
+    /// released when the returned file is dropped
+    fn lock(&self, exclusive: bool) -> Result<File, HlcError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.root.join(LOCK_FILE))?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(file)
+    }
+
+    fn chunks_dir(&self) -> PathBuf {
+        self.root.join("chunks")
+    }
+
+    fn manifests_dir(&self) -> PathBuf {
+        self.root.join("manifests")
+    }
+
+    fn chunk_path(&self, key: &str) -> PathBuf {
+        self.chunks_dir().join(&key[..2]).join(key)
+    }
+
+    fn manifest_path(&self, name: &str) -> Result<PathBuf, HlcError> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && !name.ends_with(TEMP_SUFFIX)
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.@+".contains(c));
+        if !valid {
+            return Err(HlcError::StoreError(format!("Invalid manifest name: '{}'", name)));
+        }
+        Ok(self.manifests_dir().join(name))
+    }
+}
+
+fn chunk_key(data: &[u8]) -> ChunkKey {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn is_valid_key(key: &str) -> bool {
+    key.len() == 64 && key.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Writes through a temporary file and renames it into place, so readers never
+/// see a partial file
+///
+/// The temporary name is unique to this process and call: concurrent puts
+/// hold the lock shared and may write the same chunk or manifest at once
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), HlcError> {
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.{}{}", std::process::id(), NEXT_TEMP.fetch_add(1, Ordering::Relaxed), TEMP_SUFFIX));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunking;
+    use tempfile::TempDir;
+
+    fn snapshot(day: u32) -> Vec<u8> {
+        (0u32..20_000)
+            .flat_map(|i| {
+                let value = if i % 1000 == day { i * 7 + day } else { i * 7 };
+                format!("row {} value {}\n", i, value).into_bytes()
+            })
+            .collect()
+    }
+
+    fn config() -> HlcConfig {
+        HlcConfig::default().with_chunking(Chunking::content_defined(8 * 1024))
+    }
+
+    #[test]
+    fn test_put_get_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        let data = snapshot(0);
+
+        let stats = store.put("day0", &mut data.as_slice(), &config()).unwrap();
+        assert_eq!(stats.original_size, data.len() as u64);
+        assert_eq!(stats.new_chunks, stats.total_chunks);
+
+        let mut restored = Vec::new();
+        store.get("day0", &mut restored, &config()).unwrap();
+        assert_eq!(restored, data);
+        assert_eq!(store.manifests().unwrap(), vec!["day0".to_string()]);
+    }
+
+    #[test]
+    fn test_snapshots_share_chunks() {
+        let dir = TempDir::new().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+
+        let first = store.put("day0", &mut snapshot(0).as_slice(), &config()).unwrap();
+        let second = store.put("day1", &mut snapshot(1).as_slice(), &config()).unwrap();
+        assert!(second.new_chunks * 2 < second.total_chunks);
+        assert!(second.stored_bytes < first.stored_bytes);
+
+        let mut restored = Vec::new();
+        store.get("day1", &mut restored, &config()).unwrap();
+        assert_eq!(restored, snapshot(1));
+    }
+
+    #[test]
+    fn test_gc_removes_unreferenced_chunks() {
+        let dir = TempDir::new().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        store.put("day0", &mut snapshot(0).as_slice(), &config()).unwrap();
+        store.put("day1", &mut snapshot(1).as_slice(), &config()).unwrap();
+
+        assert_eq!(store.gc().unwrap().removed_chunks, 0);
+
+        store.remove("day0").unwrap();
+        let stats = store.gc().unwrap();
+        assert!(stats.removed_chunks > 0);
+        assert!(stats.freed_bytes > 0);
+
+        let mut restored = Vec::new();
+        store.get("day1", &mut restored, &config()).unwrap();
+        assert_eq!(restored, snapshot(1));
+    }
+
+    #[test]
+    fn test_gc_waits_for_writers() {
+        let dir = TempDir::new().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        store.put("day0", &mut snapshot(0).as_slice(), &config()).unwrap();
+
+        // A writer between skipping an existing chunk and writing its
+        // manifest holds the shared lock; the orphaned chunk must survive
+        let writer = store.lock(false).unwrap();
+        store.remove("day0").unwrap();
+        let (done, finished) = std::sync::mpsc::channel();
+        let collector = store.clone();
+        let gc = std::thread::spawn(move || done.send(collector.gc().unwrap()).unwrap());
+        assert!(finished.recv_timeout(std::time::Duration::from_millis(200)).is_err());
+
+        drop(writer);
+        assert!(finished.recv().unwrap().removed_chunks > 0);
+        gc.join().unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_puts_of_same_input() {
+        let dir = TempDir::new().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || store.put("day0", &mut snapshot(0).as_slice(), &config()).unwrap())
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let mut restored = Vec::new();
+        store.get("day0", &mut restored, &config()).unwrap();
+        assert_eq!(restored, snapshot(0));
+        assert_eq!(store.gc().unwrap().freed_bytes, 0);
+    }
+
+    #[test]
+    fn test_store_errors() {
+        let dir = TempDir::new().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+
+        assert!(store.put("../escape", &mut &b"data"[..], &config()).is_err());
+        assert!(matches!(store.get("nothing", &mut Vec::new(), &config()), Err(HlcError::StoreError(_))));
+
+        // A corrupted chunk is caught on read
+        store.put("small", &mut &b"some small input"[..], &config()).unwrap();
+        let key = store.manifest("small").unwrap().entries[0].key.clone();
+        let path = store.chunk_path(&key);
+        let mut object = fs::read(&path).unwrap();
+        let last = object.len() - 1;
+        object[last] ^= 0xFF;
+        fs::write(&path, object).unwrap();
+        assert!(store.get("small", &mut Vec::new(), &config()).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::config::HlcConfig;
+use crate::error::HlcError;
+use crate::recipe::{Pipeline, RecipeStep};
+use crate::transforms::analyzer::{self, Analysis};
+use std::cell::OnceCell;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How one chunk should be encoded, as decided by a strategy callback
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Strategy {
+    /// Encode the analyzer's shortlist, as without a callback
+    #[default]
+    Auto,
+    /// Store the chunk uncompressed
+    Store,
+    /// Apply exactly these transform steps, checked against the registry
+    /// first, then the entropy stage with the configured codec; the chunk is
+    /// only stored if the result is no smaller than the input
+    Steps(Vec<RecipeStep>),
+    /// A complete chain, resolved like `HlcConfig::with_pipeline`
+    Pipeline(Pipeline),
+}
+
+/// What a strategy callback knows about the chunk it is deciding for
+///
+/// `data` is what the analyzer would see: the chunk after precompression and
+/// any registered user transforms. The analyzer only runs if the callback asks
+/// for its suggestion
+pub struct ChunkContext<'a> {
+    /// Position of the chunk in the input
+    pub id: usize,
+    /// Byte offset of the chunk in the input
+    pub offset: u64,
+    pub data: &'a [u8],
+    config: &'a HlcConfig,
+    /// The analysis and how long it took
+    analysis: OnceCell<(Analysis, Duration)>,
+}
+
+impl<'a> ChunkContext<'a> {
+    pub(crate) fn new(id: usize, offset: u64, data: &'a [u8], config: &'a HlcConfig) -> Self {
+        Self { id, offset, data, config, analysis: OnceCell::new() }
+    }
+
+    /// The analyzer's ranking of candidate chains for this chunk, computed on
+    /// first use
+    pub fn suggestion(&self) -> Result<&Analysis, HlcError> {
+        if let Some((analysis, _)) = self.analysis.get() {
+            return Ok(analysis);
+        }
+        let analyzed = self.analyze()?;
+        Ok(&self.analysis.get_or_init(|| analyzed).0)
+    }
+
+    /// The analysis and the time it took, reusing the callback's if it asked
+    /// for one
+    pub(crate) fn into_analysis(mut self) -> Result<(Analysis, Duration), HlcError> {
+        match self.analysis.take() {
+            Some(analyzed) => Ok(analyzed),
+            None => self.analyze(),
+        }
+    }
+
+    /// The callback's analysis, if it asked for one
+    pub(crate) fn take_analysis(self) -> Option<(Analysis, Duration)> {
+        self.analysis.into_inner()
+    }
+
+    fn analyze(&self) -> Result<(Analysis, Duration), HlcError> {
+        let start = Instant::now();
+        let analysis = analyzer::analyze_chunk(self.data, self.config)?;
+        Ok((analysis, start.elapsed()))
+    }
+}
+
+/// A per-chunk strategy callback, as set by `HlcConfig::with_strategy_fn`
+#[derive(Clone)]
+pub struct StrategyFn(Arc<dyn Fn(&ChunkContext) -> Strategy + Send + Sync>);
+
+impl StrategyFn {
+    pub fn new(f: Arc<dyn Fn(&ChunkContext) -> Strategy + Send + Sync>) -> Self {
+        Self(f)
+    }
+
+    pub fn call(&self, context: &ChunkContext) -> Strategy {
+        (self.0)(context)
+    }
+}
+
+impl fmt::Debug for StrategyFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StrategyFn")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggestion_is_computed_once() {
+        let config = HlcConfig::default();
+        let data = b"strategy context ".repeat(200);
+        let context = ChunkContext::new(3, 4096, &data, &config);
+
+        let first = context.suggestion().unwrap() as *const Analysis;
+        let second = context.suggestion().unwrap() as *const Analysis;
+        assert_eq!(first, second);
+        assert!(context.into_analysis().unwrap().0.best().is_some());
+    }
+}
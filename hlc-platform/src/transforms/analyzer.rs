@@ -1,147 +1,1020 @@
-use crate::config::HlcMode;
+//! Chunk analysis by sampled trial compression
+//! Cheap linear detectors propose candidate transform chains, and each one is
+//! applied to a few windows sampled across the chunk and entropy coded at a
+//! fast setting. The candidates come back ranked by the chunk size their
+//! samples predict, with a confidence taken from how well the windows agree
+//!
+//! The same windows also give order-0/1/2 entropy estimates and, through
+//! autocorrelation, the period of fixed-width records, which together name
+//! the likely kind of data and size the strides of shuffle and delta
+//!
+//! Before any of that, `detect_compressed` recognises content that is already
+//! compressed or encrypted, from magic bytes or entropy, so it can be stored
 
-#[derive(Debug, Clone)]
-pub struct CompressionStrategy {
-    pub use_rle: bool,
-    pub use_delta: bool,
-    pub use_dictionary: bool,
-    pub original_data: Vec<u8>,
+use crate::config::{HlcConfig, HlcMode};
+use crate::container::MAGIC_NUMBER;
+use crate::error::HlcError;
+use crate::recipe::{Recipe, RecipeStep};
+use crate::transforms::entropy::{self, Codec};
+use crate::transforms::csv;
+use crate::transforms::json;
+use crate::transforms::delta;
+use crate::transforms::registry;
+use crate::transforms::shuffle::ShuffleMode;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Windows sampled from chunks too large to trial in full
+pub const SAMPLE_WINDOWS: usize = 4;
+
+/// Bytes per sampled window; a multiple of every element width
+pub const SAMPLE_WINDOW_SIZE: usize = 16 * 1024;
+
+/// zstd level used for trials; only the ranking matters
+const TRIAL_LEVEL: i32 = 1;
+
+/// Longest record period autocorrelation looks for; also the widest shuffle
+pub const MAX_RECORD_WIDTH: usize = 255;
+
+/// Bytes autocorrelation is computed over
+const PERIOD_SAMPLE_SIZE: usize = 8 * 1024;
+
+/// Default thresholds of the kind decision; see `Thresholds`
+const RANDOM_ENTROPY: f32 = 7.9;
+const TEXT_FRACTION: f32 = 0.9;
+const FLOAT_FRACTION: f32 = 0.9;
+const LANE_ENTROPY_RATIO: f32 = 0.75;
+const DELTA_STRIDE_GAIN: f32 = 0.9;
+
+/// Bytes the delta entropies are measured over
+const DELTA_SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Order-0 entropy above which a chunk starting with a compressed format's
+/// magic is stored; lower than for unknown data, as headers and tables dilute
+/// it, but high enough that a ZIP of stored text entries still compresses
+const MAGIC_ENTROPY: f32 = 7.5;
+
+/// Already-compressed or encrypted content that is stored as is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressedFormat {
+    Jpeg,
+    Png,
+    Zip,
+    Gzip,
+    Zstd,
+    Mp4,
+    /// An HLC archive
+    Hlc,
+    /// No known format, but random at order 0 and order 1: encrypted, or
+    /// compressed in a format without recognisable framing here
+    HighEntropy,
 }
 
-/// Analyzes data chunk to determine the best compression strategy
-/// This is a simplified implementation - production versions would use
-/// more sophisticated entropy analysis, pattern detection, etc.
-pub fn analyze_chunk(data: &[u8], mode: HlcMode) -> CompressionStrategy {
-    let mut use_rle = false;
-    let mut use_delta = false;
-    let mut use_dictionary = false;
+/// What a chunk most likely contains
+///
+/// The analyzer tells text, typed arrays, random and other binary data apart,
+/// and recognises newline-delimited JSON and delimited text; logs are only
+/// known from a caller's hint (`HlcConfig::with_hint`). Parses from and
+/// displays as `text`, `json`, `csv`, `log`, `int:<width>`, `float:<width>`,
+/// `binary` or `random`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataKind {
+    /// Mostly printable text
+    Text,
+    /// JSON documents or newline-delimited JSON records
+    Json,
+    /// Comma- or tab-separated rows
+    Csv,
+    /// Line-oriented log output
+    Log,
+    /// Fixed-width integers, `width` bytes each
+    IntArray { width: usize },
+    /// IEEE-754 values, 4 or 8 bytes each
+    FloatArray { width: usize },
+    /// Anything else
+    #[default]
+    Binary,
+    /// No structure at any context order: compressed or encrypted data
+    Random,
+}
 
-    if data.is_empty() {
-        return CompressionStrategy {
-            use_rle: false,
-            use_delta: false,
-            use_dictionary: false,
-            original_data: data.to_vec(),
-        };
+impl DataKind {
+    /// Text of any flavour
+    pub fn is_text(&self) -> bool {
+        matches!(self, DataKind::Text | DataKind::Json | DataKind::Csv | DataKind::Log)
     }
 
-    // Analyze for RLE effectiveness (good for sparse data)
-    let zero_runs = count_zero_runs(data);
-    let zero_percentage = data.iter().filter(|&&b| b == 0).count() as f32 / data.len() as f32;
-    
-    if zero_percentage > 0.3 || zero_runs > data.len() / 20 {
-        use_rle = true;
+    /// Checks that an array width is one the transforms support
+    pub fn validate(&self) -> Result<(), HlcError> {
+        match *self {
+            DataKind::IntArray { width } if !(1..=255).contains(&width) => Err(HlcError::ConfigError(format!(
+                "Integer width must be 1-255 bytes, not {}",
+                width
+            ))),
+            DataKind::FloatArray { width } if width != 4 && width != 8 => Err(HlcError::ConfigError(format!(
+                "Float width must be 4 or 8 bytes, not {}",
+                width
+            ))),
+            _ => Ok(()),
+        }
     }
+}
+
+impl FromStr for DataKind {
+    type Err = HlcError;
 
-    // Analyze for delta coding effectiveness
-    let delta_entropy = calculate_delta_entropy(data);
-    let original_entropy = calculate_entropy(data);
-    
-    if delta_entropy < original_entropy * 0.8 {
-        use_delta = true;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_lowercase();
+        let (name, width) = match lower.split_once(':') {
+            Some((name, width)) => {
+                let width = width
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| HlcError::ConfigError(format!("Invalid width in data kind: {}", s)))?;
+                (name.trim(), Some(width))
+            }
+            None => (lower.as_str(), None),
+        };
+
+        let kind = match (name, width) {
+            ("text", None) => DataKind::Text,
+            ("json", None) => DataKind::Json,
+            ("csv", None) => DataKind::Csv,
+            ("log", None) => DataKind::Log,
+            ("int", Some(width)) => DataKind::IntArray { width },
+            ("float", Some(width)) => DataKind::FloatArray { width },
+            ("binary", None) => DataKind::Binary,
+            ("random", None) => DataKind::Random,
+            _ => return Err(HlcError::ConfigError(format!("Invalid data kind: {}", s))),
+        };
+        kind.validate()?;
+        Ok(kind)
     }
+}
 
-    // In Max mode, be more aggressive with transforms
-    if mode == HlcMode::Max {
-        if !use_delta && has_sequential_patterns(data) {
-            use_delta = true;
+impl fmt::Display for DataKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataKind::Text => write!(f, "text"),
+            DataKind::Json => write!(f, "json"),
+            DataKind::Csv => write!(f, "csv"),
+            DataKind::Log => write!(f, "log"),
+            DataKind::IntArray { width } => write!(f, "int:{}", width),
+            DataKind::FloatArray { width } => write!(f, "float:{}", width),
+            DataKind::Binary => write!(f, "binary"),
+            DataKind::Random => write!(f, "random"),
         }
-        
-        if has_repeating_patterns(data) {
-            use_dictionary = true;
+    }
+}
+
+/// Entropy estimates of the sampled windows, in bits per byte
+///
+/// Higher orders are conditioned on the one or two preceding bytes; on small
+/// samples they are biased low, since many contexts are seen only a few times
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ContextEntropy {
+    pub order0: f32,
+    pub order1: f32,
+    pub order2: f32,
+}
+
+/// Cheap whole-chunk measurements the kind decision is made from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Features {
+    /// Fraction of bytes that are printable ASCII or whitespace
+    pub text_fraction: f32,
+    /// Fraction of zero bytes
+    pub zero_fraction: f32,
+    /// Order-0 entropy of the whole chunk, in bits per byte
+    pub entropy: f32,
+    /// Fraction of 4- and 8-byte elements with a plausible float exponent;
+    /// zero for chunks under 64 bytes
+    pub float_fraction: [f32; 2],
+    /// Mean entropy of the byte lanes of 2-, 4- and 8-byte elements; equal to
+    /// `entropy` for chunks under 256 bytes
+    pub lane_entropy: [f32; 3],
+    /// Entropy of 1-, 2- and 4-byte deltas over the start of the chunk;
+    /// strides too wide for the sample repeat the byte-wise value
+    pub delta_entropy: [f32; 3],
+}
+
+impl Features {
+    /// Measures `data`, in a few passes linear in its size
+    pub fn measure(data: &[u8]) -> Self {
+        let mut counts = [0u32; 256];
+        for &byte in data {
+            counts[byte as usize] += 1;
+        }
+        let printable: u32 = counts[0x20..0x7F].iter().sum::<u32>()
+            + counts[b'\n' as usize]
+            + counts[b'\r' as usize]
+            + counts[b'\t' as usize];
+        let len = data.len() as f32;
+        let entropy = entropy_bits(&counts);
+
+        let float_fraction = if data.len() < 64 { [0.0; 2] } else { [4, 8].map(|width| float_fraction(data, width)) };
+        let lane_entropy = if data.len() < 256 { [entropy; 3] } else { [2, 4, 8].map(|width| lane_entropy(data, width)) };
+
+        let sample = &data[..data.len().min(DELTA_SAMPLE_SIZE)];
+        let byte_delta = calculate_entropy(&delta::encode(sample));
+        let delta_entropy = [1, 2, 4].map(|stride| match stride {
+            1 => byte_delta,
+            _ if sample.len() < stride * 16 => byte_delta,
+            _ => calculate_entropy(&delta::encode_advanced(sample, stride)),
+        });
+
+        Self {
+            text_fraction: printable as f32 / len,
+            zero_fraction: counts[0] as f32 / len,
+            entropy,
+            float_fraction,
+            lane_entropy,
+            delta_entropy,
         }
     }
+}
 
-    CompressionStrategy {
-        use_rle,
-        use_delta,
-        use_dictionary,
-        original_data: data.to_vec(),
+/// Cutoffs the analyzer names a chunk's kind and delta stride with
+///
+/// The defaults are hand-picked; `hlc tune` fits them to a corpus and saves
+/// them as `name = value` lines that `Thresholds::load` reads back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// Text fraction above which a chunk is text
+    pub text_fraction: f32,
+    /// Float fraction at or above which a chunk is a float array
+    pub float_fraction: f32,
+    /// Ratio of lane entropy to whole-chunk entropy below which a chunk is an
+    /// integer array
+    pub lane_entropy_ratio: f32,
+    /// Sampled order-0 entropy above which a chunk is random, given order 1
+    /// agrees
+    pub random_entropy: f32,
+    /// Factor a wider delta stride must beat the narrower one's entropy by
+    pub delta_stride_gain: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            text_fraction: TEXT_FRACTION,
+            float_fraction: FLOAT_FRACTION,
+            lane_entropy_ratio: LANE_ENTROPY_RATIO,
+            random_entropy: RANDOM_ENTROPY,
+            delta_stride_gain: DELTA_STRIDE_GAIN,
+        }
     }
 }
 
-fn count_zero_runs(data: &[u8]) -> usize {
-    let mut runs = 0;
-    let mut in_run = false;
-    
-    for &byte in data {
-        if byte == 0 {
-            if !in_run {
-                runs += 1;
-                in_run = true;
+impl Thresholds {
+    /// Each threshold by its name in model files
+    pub fn fields_mut(&mut self) -> [(&'static str, &mut f32); 5] {
+        [
+            ("text_fraction", &mut self.text_fraction),
+            ("float_fraction", &mut self.float_fraction),
+            ("lane_entropy_ratio", &mut self.lane_entropy_ratio),
+            ("random_entropy", &mut self.random_entropy),
+            ("delta_stride_gain", &mut self.delta_stride_gain),
+        ]
+    }
+
+    /// Checks every threshold is in range: fractions, ratios and gains in
+    /// (0, 1], the random entropy in (0, 8]
+    pub fn validate(&self) -> Result<(), HlcError> {
+        let mut copy = *self;
+        for (name, &mut value) in copy.fields_mut() {
+            let max = if name == "random_entropy" { 8.0 } else { 1.0 };
+            if !(value > 0.0 && value <= max) {
+                return Err(HlcError::ConfigError(format!(
+                    "Threshold {} must be in (0, {}], not {}",
+                    name, max, value
+                )));
             }
+        }
+        Ok(())
+    }
+
+    /// Reads a model file written by `save`
+    pub fn load(path: &Path) -> Result<Self, HlcError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), HlcError> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Names the likely kind of data from its features: text, then float and
+    /// integer arrays, then random, then other binary data
+    pub fn kind(&self, features: &Features, entropy: &ContextEntropy) -> DataKind {
+        if features.text_fraction > self.text_fraction {
+            return DataKind::Text;
+        }
+        if let Some(width) = [4, 8]
+            .into_iter()
+            .zip(features.float_fraction)
+            .find_map(|(width, fraction)| (fraction >= self.float_fraction).then_some(width))
+        {
+            return DataKind::FloatArray { width };
+        }
+
+        // The most predictable lanes, the narrowest width winning ties
+        let mut best = (2, features.lane_entropy[0]);
+        for (width, mean) in [4, 8].into_iter().zip(features.lane_entropy[1..].iter().copied()) {
+            if mean < best.1 {
+                best = (width, mean);
+            }
+        }
+        if best.1 < features.entropy * self.lane_entropy_ratio {
+            return DataKind::IntArray { width: best.0 };
+        }
+
+        if self.is_random(entropy) {
+            DataKind::Random
         } else {
-            in_run = false;
+            DataKind::Binary
+        }
+    }
+
+    /// Random at order 0 and order 1
+    ///
+    /// On sampled windows order 1 sees too few of each context to approach 8
+    /// bits even for random bytes, so it only has to stay within a quarter of
+    /// order 0
+    fn is_random(&self, entropy: &ContextEntropy) -> bool {
+        entropy.order0 > self.random_entropy && entropy.order1 > entropy.order0 * 0.75
+    }
+
+    /// The delta word size whose differences have the lowest entropy; wider
+    /// strides must clearly win, as byte deltas are the safer default
+    pub fn delta_stride(&self, features: &Features) -> usize {
+        let mut best = (1, features.delta_entropy[0]);
+        for (stride, entropy) in [2, 4].into_iter().zip(features.delta_entropy[1..].iter().copied()) {
+            if entropy < best.1 * self.delta_stride_gain {
+                best = (stride, entropy);
+            }
         }
+        best.0
     }
-    
-    runs
 }
 
-fn calculate_entropy(data: &[u8]) -> f32 {
+impl FromStr for Thresholds {
+    type Err = HlcError;
+
+    /// Parses `name = value` lines; `#` starts a comment, and thresholds not
+    /// given keep their defaults
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut thresholds = Thresholds::default();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| HlcError::ConfigError(format!("Invalid model line: {}", line)))?;
+            let value = value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| HlcError::ConfigError(format!("Invalid threshold value: {}", line)))?;
+            let (_, field) = thresholds
+                .fields_mut()
+                .into_iter()
+                .find(|(known, _)| *known == name.trim())
+                .ok_or_else(|| HlcError::ConfigError(format!("Unknown threshold: {}", name.trim())))?;
+            *field = value;
+        }
+        thresholds.validate()?;
+        Ok(thresholds)
+    }
+}
+
+impl fmt::Display for Thresholds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# HLC analyzer thresholds")?;
+        let mut copy = *self;
+        for (name, value) in copy.fields_mut() {
+            writeln!(f, "{} = {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// One candidate transform chain and what the samples predict for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// Transform steps, without the entropy stage
+    pub steps: Vec<RecipeStep>,
+    /// Entropy coder that did best on the samples
+    pub entropy_codec: Codec,
+    /// Predicted encoded size of the whole chunk
+    pub predicted_size: usize,
+    /// How far the prediction can be trusted, from 0.0 to 1.0; 1.0 when the
+    /// whole chunk was trialled
+    pub confidence: f32,
+}
+
+/// Result of analyzing a chunk
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    /// Candidates ordered by predicted size, smallest first
+    pub candidates: Vec<Candidate>,
+    /// Likely kind of content
+    pub kind: DataKind,
+    /// Period of fixed-width records, when autocorrelation shows a clear one;
+    /// not looked for in text or random data
+    pub record_width: Option<usize>,
+    /// Order-0/1/2 entropy of the sampled windows
+    pub entropy: ContextEntropy,
+    /// Whole-chunk measurements behind the kind decision
+    pub features: Features,
+}
+
+impl Analysis {
+    /// The candidate with the smallest predicted size
+    pub fn best(&self) -> Option<&Candidate> {
+        self.candidates.first()
+    }
+}
+
+/// Analyzes a chunk by trial-compressing sampled windows with each candidate
+/// chain, returning the candidates ranked by predicted size
+///
+/// Runs in time linear in the chunk size; the trials themselves only ever see
+/// `SAMPLE_WINDOWS * SAMPLE_WINDOW_SIZE` bytes. A hint in `config` replaces
+/// kind detection, and the hinted kind's candidates are still trialled
+pub fn analyze_chunk(data: &[u8], config: &HlcConfig) -> Result<Analysis, HlcError> {
+    if data.is_empty() {
+        return Ok(Analysis::default());
+    }
+
+    let windows = sample_windows(data);
+    let sampled: usize = windows.iter().map(|w| w.len()).sum();
+    let scale = data.len() as f64 / sampled as f64;
+
+    let entropy = context_entropy(&windows);
+    let features = Features::measure(data);
+    // A caller's hint stands in for the whole-chunk detectors
+    let kind = match config.hint {
+        Some(hint) => hint,
+        None => match config.thresholds.kind(&features, &entropy) {
+            DataKind::Text => text_kind(windows[0]),
+            kind => kind,
+        },
+    };
+    let record_width = match kind {
+        DataKind::Random => None,
+        kind if kind.is_text() => None,
+        _ => detect_record_width(windows[0]),
+    };
+    let stride = delta_stride(&features, record_width, &config.thresholds);
+
+    let mut candidates = Vec::new();
+    for steps in candidate_chains(&features, kind, record_width, stride, config.mode) {
+        if let Some((entropy_codec, sizes)) = trial(&windows, &steps, config)? {
+            let total: usize = sizes.iter().sum();
+            let confidence = if sampled == data.len() { 1.0 } else { agreement(&windows, &sizes) };
+            candidates.push(Candidate {
+                steps,
+                entropy_codec,
+                predicted_size: (total as f64 * scale).ceil() as usize,
+                confidence,
+            });
+        }
+    }
+
+    // Stable, so ties keep the simpler chain proposed first
+    candidates.sort_by_key(|c| c.predicted_size);
+    Ok(Analysis {
+        candidates,
+        kind,
+        record_width,
+        entropy,
+        features,
+    })
+}
+
+/// Recognises a chunk whose content is already compressed or encrypted
+///
+/// A known magic at the start of the chunk only needs moderately high entropy
+/// over the sampled windows; anything else must look random. Normal data is
+/// ruled out by the order-0 pass alone, before any context statistics; what
+/// counts as random comes from `thresholds`
+pub fn detect_compressed(data: &[u8], thresholds: &Thresholds) -> Option<CompressedFormat> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let windows = sample_windows(data);
     let mut counts = [0u32; 256];
-    for &byte in data {
+    for &byte in windows.iter().flat_map(|w| w.iter()) {
         counts[byte as usize] += 1;
     }
-    
-    let len = data.len() as f32;
-    let mut entropy = 0.0;
-    
-    for &count in &counts {
-        if count > 0 {
-            let p = count as f32 / len;
-            entropy -= p * p.log2();
+    let order0 = entropy_bits(&counts);
+
+    if let Some(format) = magic_format(data) {
+        return (order0 > MAGIC_ENTROPY).then_some(format);
+    }
+    if order0 <= thresholds.random_entropy {
+        return None;
+    }
+    thresholds.is_random(&context_entropy(&windows)).then_some(CompressedFormat::HighEntropy)
+}
+
+/// Format named by the magic bytes at the start of `data`
+fn magic_format(data: &[u8]) -> Option<CompressedFormat> {
+    const MAGICS: [(&[u8], CompressedFormat); 6] = [
+        (&[0xFF, 0xD8, 0xFF], CompressedFormat::Jpeg),
+        (&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A], CompressedFormat::Png),
+        (b"PK\x03\x04", CompressedFormat::Zip),
+        (&[0x1F, 0x8B], CompressedFormat::Gzip),
+        (&[0x28, 0xB5, 0x2F, 0xFD], CompressedFormat::Zstd),
+        (MAGIC_NUMBER, CompressedFormat::Hlc),
+    ];
+
+    if let Some(&(_, format)) = MAGICS.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(format);
+    }
+    // ISO base media files open with a box size followed by `ftyp`
+    (data.get(4..8) == Some(b"ftyp")).then_some(CompressedFormat::Mp4)
+}
+
+/// Evenly spaced windows across the chunk, starting on 8-byte boundaries so
+/// element-wise transforms see whole values; small chunks are one window
+pub(crate) fn sample_windows(data: &[u8]) -> Vec<&[u8]> {
+    if data.len() <= SAMPLE_WINDOWS * SAMPLE_WINDOW_SIZE {
+        return vec![data];
+    }
+
+    let span = data.len() - SAMPLE_WINDOW_SIZE;
+    (0..SAMPLE_WINDOWS)
+        .map(|i| {
+            let start = (span * i / (SAMPLE_WINDOWS - 1)) & !7;
+            &data[start..start + SAMPLE_WINDOW_SIZE]
+        })
+        .collect()
+}
+
+/// Encoded size of every window with `steps` applied, using whichever of
+/// zstd and rANS does better overall; `None` when the chain does not fit
+fn trial(windows: &[&[u8]], steps: &[RecipeStep], config: &HlcConfig) -> Result<Option<(Codec, Vec<usize>)>, HlcError> {
+    let recipe = Recipe { steps: steps.to_vec() };
+    let mut zstd_sizes = Vec::with_capacity(windows.len());
+    let mut rans_sizes = Vec::with_capacity(windows.len());
+
+    for window in windows {
+        let transformed = match recipe.encode(window, &config.registry) {
+            Ok(data) => data,
+            // Parameters that do not fit this chunk rule the candidate out
+            Err(HlcError::TransformError(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        zstd_sizes.push(entropy::encode(&transformed, TRIAL_LEVEL)?.len());
+        rans_sizes.push(entropy::encode_with(Codec::Rans, &transformed, TRIAL_LEVEL)?.len());
+    }
+
+    if rans_sizes.iter().sum::<usize>() < zstd_sizes.iter().sum::<usize>() {
+        Ok(Some((Codec::Rans, rans_sizes)))
+    } else {
+        Ok(Some((Codec::Zstd, zstd_sizes)))
+    }
+}
+
+/// Confidence from the spread of per-window compression ratios: windows that
+/// compress alike make the extrapolation to the whole chunk trustworthy
+fn agreement(windows: &[&[u8]], sizes: &[usize]) -> f32 {
+    let ratios: Vec<f32> = windows.iter().zip(sizes).map(|(w, &s)| s as f32 / w.len() as f32).collect();
+    let mean = ratios.iter().sum::<f32>() / ratios.len() as f32;
+    let min = ratios.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = ratios.iter().copied().fold(0.0, f32::max);
+    1.0 / (1.0 + (max - min) / mean.max(f32::EPSILON))
+}
+
+/// Transform chains worth a trial, from the detected kind and record width
+///
+/// The bare entropy stage is always a candidate. Typed arrays get float XOR
+/// or shuffles, Max mode adds bit shuffles and BWT for text, random data gets
+/// nothing else, and other binary data gets delta at `stride`, with RLE when
+/// there are zeros. Records wider than their elements are also shuffled a
+/// whole record at a time
+pub(crate) fn candidate_chains(
+    features: &Features,
+    kind: DataKind,
+    record_width: Option<usize>,
+    stride: usize,
+    mode: HlcMode,
+) -> Vec<Vec<RecipeStep>> {
+    let mut chains = vec![Vec::new()];
+    let shuffle = |width: usize, mode: ShuffleMode| RecipeStep::new(registry::SHUFFLE_ID, vec![width as u8, mode.id()]);
+
+    match kind {
+        DataKind::Random => return chains,
+        DataKind::Text | DataKind::Json | DataKind::Csv | DataKind::Log => {
+            match kind {
+                DataKind::Json => chains.push(vec![RecipeStep::new(registry::JSON_ID, Vec::new())]),
+                DataKind::Csv => chains.push(vec![RecipeStep::new(registry::CSV_ID, Vec::new())]),
+                _ => {}
+            }
+            if mode == HlcMode::Max {
+                chains.push(vec![RecipeStep::new(registry::BWT_ID, Vec::new())]);
+                // The split-out JSON streams still suit the BWT
+                if kind == DataKind::Json {
+                    chains.push(vec![
+                        RecipeStep::new(registry::JSON_ID, Vec::new()),
+                        RecipeStep::new(registry::BWT_ID, Vec::new()),
+                    ]);
+                }
+            }
+            return chains;
+        }
+        DataKind::FloatArray { width } => {
+            chains.push(vec![RecipeStep::new(registry::FLOAT_XOR_ID, vec![width as u8])]);
+            chains.push(vec![shuffle(width, ShuffleMode::Byte)]);
+        }
+        DataKind::IntArray { width } => {
+            chains.push(vec![shuffle(width, ShuffleMode::Byte)]);
+            if mode == HlcMode::Max {
+                chains.push(vec![shuffle(width, ShuffleMode::Bit)]);
+            }
+        }
+        DataKind::Binary => {
+            let rle = RecipeStep::new(registry::RLE_ID, Vec::new());
+            let delta = RecipeStep::new(registry::DELTA_ID, vec![stride as u8]);
+            chains.push(vec![delta.clone()]);
+            if features.zero_fraction > 0.0 {
+                chains.push(vec![rle.clone()]);
+                chains.push(vec![rle.clone(), delta.clone()]);
+                // Runs often only appear once a ramp has been differenced
+                if mode == HlcMode::Max {
+                    chains.push(vec![delta, rle]);
+                }
+            }
         }
     }
-    
-    entropy
+
+    if let Some(width) = record_width {
+        let record = vec![shuffle(width, ShuffleMode::Byte)];
+        if !chains.contains(&record) {
+            chains.push(record);
+        }
+    }
+
+    chains
 }
 
-fn calculate_delta_entropy(data: &[u8]) -> f32 {
-    if data.len() < 2 {
-        return calculate_entropy(data);
+/// Which kind of text `sample` is: JSON when its lines are objects, CSV when
+/// it has a consistent delimiter
+///
+/// JSON goes first, as object lines with the same keys also split evenly on
+/// commas
+pub(crate) fn text_kind(sample: &[u8]) -> DataKind {
+    if json::is_ndjson(sample) {
+        DataKind::Json
+    } else if csv::detect_delimiter(sample).is_some() {
+        DataKind::Csv
+    } else {
+        DataKind::Text
     }
-    
-    let mut deltas = Vec::with_capacity(data.len() - 1);
-    for i in 1..data.len() {
-        deltas.push(data[i].wrapping_sub(data[i - 1]));
+}
+
+/// Delta stride for binary data: the record width when records are 2 or 4
+/// bytes wide, otherwise the most predictable one
+pub(crate) fn delta_stride(features: &Features, record_width: Option<usize>, thresholds: &Thresholds) -> usize {
+    match record_width {
+        Some(width @ (2 | 4)) => width,
+        _ => thresholds.delta_stride(features),
     }
-    
-    calculate_entropy(&deltas)
 }
 
-fn has_sequential_patterns(data: &[u8]) -> bool {
-    if data.len() < 4 {
-        return false;
+/// Order-0, order-1 and order-2 entropy over the windows
+///
+/// Conditional entropies come from joint ones, H(X | ctx) = H(ctx, X) - H(ctx),
+/// with byte pairs counted in a table and triples by sorting
+pub(crate) fn context_entropy(windows: &[&[u8]]) -> ContextEntropy {
+    let mut singles = [0u32; 256];
+    let mut pairs = vec![0u32; 1 << 16];
+    let mut triples = Vec::with_capacity(windows.iter().map(|w| w.len()).sum());
+
+    for window in windows {
+        for &byte in *window {
+            singles[byte as usize] += 1;
+        }
+        for pair in window.windows(2) {
+            pairs[(pair[0] as usize) << 8 | pair[1] as usize] += 1;
+        }
+        triples.extend(window.windows(3).map(|t| u32::from_be_bytes([0, t[0], t[1], t[2]])));
+    }
+    triples.sort_unstable();
+
+    // Context marginals: the first byte of each pair, the first two of each triple
+    let mut leading = [0u32; 256];
+    for (key, &count) in pairs.iter().enumerate() {
+        leading[key >> 8] += count;
     }
-    
-    let mut sequential_count = 0;
-    for i in 1..data.len() {
-        let diff = data[i].wrapping_sub(data[i - 1]);
-        if diff <= 2 {
-            sequential_count += 1;
+    let triple_counts = run_lengths(triples.iter().copied());
+    let prefix_counts = run_lengths(triples.iter().map(|key| key >> 8));
+
+    ContextEntropy {
+        order0: entropy_bits(&singles),
+        order1: (entropy_bits(&pairs) - entropy_bits(&leading)).max(0.0),
+        order2: (entropy_bits(&triple_counts) - entropy_bits(&prefix_counts)).max(0.0),
+    }
+}
+
+/// Counts of each run of equal values in a sorted sequence
+fn run_lengths(sorted: impl Iterator<Item = u32>) -> Vec<u32> {
+    let mut counts = Vec::new();
+    let mut previous = None;
+    for key in sorted {
+        if previous == Some(key) {
+            *counts.last_mut().unwrap() += 1;
+        } else {
+            counts.push(1);
+            previous = Some(key);
         }
     }
-    
-    sequential_count > data.len() / 3
+    counts
 }
 
-fn has_repeating_patterns(data: &[u8]) -> bool {
-    if data.len() < 8 {
-        return false;
+/// Finds the period of fixed-width records by autocorrelation
+///
+/// Each lag is scored by how often a byte equals the one `lag` bytes earlier.
+/// A period must score at least halfway between chance (the order-0
+/// collision probability) and a perfect match. Multiples of the period score
+/// as well or better, since fields that cycle over several records also line
+/// up, so the shortest divisor of the best lag that is significant wins
+pub fn detect_record_width(data: &[u8]) -> Option<usize> {
+    let sample = &data[..data.len().min(PERIOD_SAMPLE_SIZE)];
+    if sample.len() < MAX_RECORD_WIDTH * 4 {
+        return None;
     }
-    
-    // Look for 4-byte patterns that repeat
-    let mut pattern_count = 0;
-    for i in 0..data.len().saturating_sub(8) {
-        let pattern = &data[i..i + 4];
-        for j in (i + 4..data.len().saturating_sub(4)).step_by(4) {
-            if &data[j..j + 4] == pattern {
-                pattern_count += 1;
-                break;
-            }
+
+    let mut counts = [0u32; 256];
+    for &byte in sample {
+        counts[byte as usize] += 1;
+    }
+    let len = sample.len() as f32;
+    let chance: f32 = counts.iter().map(|&c| (c as f32 / len).powi(2)).sum();
+
+    let scores: Vec<f32> = (2..=MAX_RECORD_WIDTH)
+        .map(|lag| {
+            let matches = sample.iter().zip(&sample[lag..]).filter(|(a, b)| a == b).count();
+            matches as f32 / (sample.len() - lag) as f32
+        })
+        .collect();
+    let (best_index, &best) = scores.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+    let best_lag = best_index + 2;
+
+    let significant = chance + (1.0 - chance) / 2.0;
+    if best <= significant {
+        return None;
+    }
+    (2..=best_lag).find(|&lag| best_lag / lag * lag == best_lag && scores[lag - 2] > significant)
+}
+
+/// Fraction of `width`-byte little-endian elements whose float exponent is
+/// plausible for a measurement
+fn float_fraction(data: &[u8], width: usize) -> f32 {
+    let mut plausible = 0;
+    let mut total = 0;
+
+    for word in data.chunks_exact(width) {
+        let exponent = if width == 4 {
+            let bits = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            ((bits >> 23) & 0xFF) as i32 - 127
+        } else {
+            let bits = u64::from_le_bytes(word.try_into().unwrap());
+            ((bits >> 52) & 0x7FF) as i32 - 1023
+        };
+
+        // Real-world measurements rarely leave this range; integers, text and
+        // the low halves of wider values usually do
+        if (-64..=64).contains(&exponent) {
+            plausible += 1;
         }
+        total += 1;
     }
-    
-    pattern_count > data.len() / 64
-}
\ No newline at end of file
+
+    if total == 0 { 0.0 } else { plausible as f32 / total as f32 }
+}
+
+/// Mean entropy of the byte lanes of `width`-byte elements
+fn lane_entropy(data: &[u8], width: usize) -> f32 {
+    let count = data.len() / width;
+    let mut lane = Vec::with_capacity(count);
+    let mut total = 0.0;
+    for offset in 0..width {
+        lane.clear();
+        lane.extend((0..count).map(|i| data[i * width + offset]));
+        total += calculate_entropy(&lane);
+    }
+    total / width as f32
+}
+
+fn calculate_entropy(data: &[u8]) -> f32 {
+    let mut counts = [0u32; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    entropy_bits(&counts)
+}
+
+/// Shannon entropy, in bits, of the distribution given by `counts`
+fn entropy_bits(counts: &[u32]) -> f32 {
+    let total: u64 = counts.iter().map(|&c| c as u64).sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let total = total as f32;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f32 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates_are_ranked() {
+        let column: Vec<u8> = (0u32..100_000).flat_map(|i| (1_000_000 + i * 3).to_le_bytes()).collect();
+        let analysis = analyze_chunk(&column, &HlcConfig::default()).unwrap();
+
+        assert!(analysis.candidates.len() > 1);
+        assert!(analysis.candidates.windows(2).all(|w| w[0].predicted_size <= w[1].predicted_size));
+        assert!(analysis.candidates.iter().all(|c| (0.0..=1.0).contains(&c.confidence)));
+        assert!(analysis.candidates.iter().any(|c| c.steps.is_empty()));
+
+        // Transforming the counters clearly beats the bare entropy stage
+        let best = analysis.best().unwrap();
+        assert!(!best.steps.is_empty());
+        let bare = analysis.candidates.iter().find(|c| c.steps.is_empty()).unwrap();
+        assert!(best.predicted_size * 2 < bare.predicted_size);
+    }
+
+    #[test]
+    fn test_sampling() {
+        // Small chunks are trialled whole, so the prediction is exact
+        let small = b"short chunk, trialled in full ".repeat(100);
+        let analysis = analyze_chunk(&small, &HlcConfig::default()).unwrap();
+        assert!(analysis.candidates.iter().all(|c| c.confidence == 1.0));
+        let bare = analysis.candidates.iter().find(|c| c.steps.is_empty()).unwrap();
+        assert_eq!(bare.predicted_size, entropy::encode(&small, TRIAL_LEVEL).unwrap().len());
+
+        let large = vec![7u8; 1 << 20];
+        let windows = sample_windows(&large);
+        assert_eq!(windows.len(), SAMPLE_WINDOWS);
+        assert!(windows.iter().all(|w| w.len() == SAMPLE_WINDOW_SIZE));
+        assert!(windows.iter().all(|w| (w.as_ptr() as usize - large.as_ptr() as usize) & 7 == 0));
+
+        assert!(analyze_chunk(&[], &HlcConfig::default()).unwrap().candidates.is_empty());
+    }
+
+    #[test]
+    fn test_context_entropy() {
+        let text = b"the quick brown fox jumps over the lazy dog; ".repeat(300);
+        let entropy = analyze_chunk(&text, &HlcConfig::default()).unwrap().entropy;
+        assert!(entropy.order0 > 3.0);
+        assert!(entropy.order1 < entropy.order0);
+        assert!(entropy.order2 < entropy.order1);
+        assert!(entropy.order2 < 0.5); // two bytes of context nearly fix the next
+
+        let constant = context_entropy(&[&[9u8; 1000][..]]);
+        assert_eq!(constant, ContextEntropy::default());
+    }
+
+    #[test]
+    fn test_record_width_and_kind() {
+        // 12-byte records: a 4-byte counter, a 2-byte tag and 6 constant bytes
+        let records: Vec<u8> = (0u32..20_000)
+            .flat_map(|i| {
+                let mut record = i.to_le_bytes().to_vec();
+                record.extend_from_slice(&((i % 7) as u16).to_le_bytes());
+                record.extend_from_slice(b"HLCREC");
+                record
+            })
+            .collect();
+        let analysis = analyze_chunk(&records, &HlcConfig::default()).unwrap();
+        assert_eq!(analysis.record_width, Some(12));
+        assert!(analysis.candidates.iter().any(|c| c.steps == [RecipeStep::new(registry::SHUFFLE_ID, vec![12, 0])]));
+
+        assert_eq!(detect_record_width(&[0u8; 4096]), None);
+        assert_eq!(detect_record_width(b"short"), None);
+
+        let text = b"plain words of text ".repeat(100);
+        assert_eq!(analyze_chunk(&text, &HlcConfig::default()).unwrap().kind, DataKind::Text);
+
+        let floats: Vec<u8> = (0..4000).flat_map(|i| (i as f64 * 0.25).sin().to_le_bytes()).collect();
+        assert_eq!(analyze_chunk(&floats, &HlcConfig::default()).unwrap().kind, DataKind::FloatArray { width: 8 });
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let analysis = analyze_chunk(&noise, &HlcConfig::default()).unwrap();
+        assert_eq!(analysis.kind, DataKind::Random);
+        assert_eq!(analysis.candidates.len(), 1);
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let defaults = Thresholds::default();
+        assert_eq!(defaults.to_string().parse::<Thresholds>().unwrap(), defaults);
+
+        let tuned: Thresholds = "# fitted\ntext_fraction = 0.8\n\nrandom_entropy=7.25 # note\n".parse().unwrap();
+        assert_eq!(tuned.text_fraction, 0.8);
+        assert_eq!(tuned.random_entropy, 7.25);
+        assert_eq!(tuned.float_fraction, defaults.float_fraction);
+        assert!("nonesuch = 1".parse::<Thresholds>().is_err());
+        assert!("text_fraction = 1.5".parse::<Thresholds>().is_err());
+        assert!("text_fraction".parse::<Thresholds>().is_err());
+
+        // 85% printable is binary by default, text once the cutoff drops
+        let mostly_text: Vec<u8> = (0..1000u32).map(|i| if i % 20 < 17 { b'a' + (i % 26) as u8 } else { 0x90 }).collect();
+        let features = Features::measure(&mostly_text);
+        let entropy = context_entropy(&[&mostly_text[..]]);
+        assert_ne!(defaults.kind(&features, &entropy), DataKind::Text);
+        assert_eq!(tuned.kind(&features, &entropy), DataKind::Text);
+
+        let analysis = analyze_chunk(&mostly_text, &HlcConfig::default().with_thresholds(tuned)).unwrap();
+        assert_eq!(analysis.kind, DataKind::Text);
+        assert!((analysis.features.text_fraction - 0.85).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_hint() {
+        let rows = b"id,name,score\n1,alpha,0.5\n2,beta,0.75\n".repeat(200);
+        let hinted = HlcConfig::default().with_mode(HlcMode::Max).with_hint(DataKind::Csv);
+        let analysis = analyze_chunk(&rows, &hinted).unwrap();
+        assert_eq!(analysis.kind, DataKind::Csv);
+        assert_eq!(analysis.record_width, None);
+        assert!(analysis.candidates.iter().any(|c| c.steps == [RecipeStep::new(registry::BWT_ID, Vec::new())]));
+
+        // The hinted width is used even where detection would see plain binary
+        let values: Vec<u8> = (0u32..8192).flat_map(|i| i.wrapping_mul(2654435761).to_le_bytes()).collect();
+        let analysis = analyze_chunk(&values, &HlcConfig::default().with_hint(DataKind::IntArray { width: 4 })).unwrap();
+        assert_eq!(analysis.kind, DataKind::IntArray { width: 4 });
+        assert!(analysis.candidates.iter().any(|c| c.steps == [RecipeStep::new(registry::SHUFFLE_ID, vec![4, 0])]));
+
+        let analysis = analyze_chunk(&rows, &HlcConfig::default().with_hint(DataKind::Random)).unwrap();
+        assert_eq!(analysis.candidates.len(), 1);
+
+        for spec in ["text", "json", "csv", "log", "int:2", "float:8", "binary", "random"] {
+            assert_eq!(spec.parse::<DataKind>().unwrap().to_string(), spec);
+        }
+        assert_eq!("Float:4".parse::<DataKind>().unwrap(), DataKind::FloatArray { width: 4 });
+        for spec in ["float:3", "int:0", "int:256", "int", "text:1", "xml"] {
+            assert!(spec.parse::<DataKind>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_detect_compressed() {
+        let thresholds = Thresholds::default();
+        let detect = |data: &[u8]| detect_compressed(data, &thresholds);
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let noise: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        assert_eq!(detect(&noise), Some(CompressedFormat::HighEntropy));
+
+        let text: Vec<u8> = (0u32..20_000).flat_map(|i| format!("entry {} -> {}\n", i, i * 37 % 1009).into_bytes()).collect();
+        assert_eq!(detect(&text), None);
+        let zstd = entropy::encode(&text, 19).unwrap();
+        assert_eq!(detect(&zstd), Some(CompressedFormat::Zstd));
+
+        let png = [&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A][..], &noise].concat();
+        assert_eq!(detect(&png), Some(CompressedFormat::Png));
+        let mp4 = [&[0, 0, 0, 0x20][..], b"ftypisom", &noise].concat();
+        assert_eq!(detect(&mp4), Some(CompressedFormat::Mp4));
+
+        // A magic alone is not enough, nor is a flat byte histogram with structure
+        let stored_zip = [&b"PK\x03\x04"[..], &text].concat();
+        assert_eq!(detect(&stored_zip), None);
+        let ramp: Vec<u8> = (0u32..200_000).map(|i| (i.wrapping_mul(40_503) >> 5) as u8).collect();
+        assert_eq!(detect(&ramp), None);
+        assert_eq!(detect(b""), None);
+
+        // A tuned random entropy moves the cutoff for unknown data
+        let strict = Thresholds { random_entropy: 8.0, ..Thresholds::default() };
+        assert_eq!(detect_compressed(&noise, &strict), None);
+        assert_eq!(detect_compressed(&png, &strict), Some(CompressedFormat::Png));
+    }
+}
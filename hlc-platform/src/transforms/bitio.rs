@@ -0,0 +1,66 @@
+//! MSB-first bit-level I/O shared by the bit-packed transforms
+
+/// MSB-first bit writer appending to an existing buffer
+pub(crate) struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    pending: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn new(out: Vec<u8>) -> Self {
+        Self { out, acc: 0, pending: 0 }
+    }
+
+    pub(crate) fn write_bits(&mut self, value: u64, count: u32) {
+        if count > 32 {
+            self.write_bits(value >> 32, count - 32);
+            self.write_bits(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        if count == 0 {
+            return;
+        }
+
+        self.acc = (self.acc << count) | (value & ((1u64 << count) - 1));
+        self.pending += count;
+        while self.pending >= 8 {
+            self.pending -= 8;
+            self.out.push((self.acc >> self.pending) as u8);
+        }
+        self.acc &= (1u64 << self.pending) - 1;
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.pending > 0 {
+            self.out.push((self.acc << (8 - self.pending)) as u8);
+        }
+        self.out
+    }
+}
+
+/// MSB-first bit reader
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn read_bits(&mut self, count: u32) -> Option<u64> {
+        if self.pos + count as usize > self.data.len() * 8 {
+            return None;
+        }
+
+        let mut value = 0u64;
+        for _ in 0..count {
+            let bit = (self.data[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+}
@@ -0,0 +1,346 @@
+//! Burrows-Wheeler transform with move-to-front and zero-run coding
+//! The BWT (built from a suffix array) groups bytes that share a right context,
+//! MTF turns those groups into runs of small values and the zero-run coder
+//! collapses the resulting runs of zeros, much like bzip2's front end
+//! This is effective for text such as source code and logs
+//!
+//! Format: [PRIMARY INDEX: u32 LE][ORIGINAL LEN: u32 LE][SYMBOLS]
+//! Symbols: 0/1 are RUNA/RUNB digits of a bijective base-2 zero-run length,
+//! 2..=254 are MTF values 1..=253, and 255 is followed by 0 (value 254) or
+//! 1 (value 255)
+
+use crate::error::HlcError;
+
+/// Size of the fixed header: primary index + original length
+const HEADER_SIZE: usize = 4 + 4;
+
+const RUNA: u8 = 0;
+const RUNB: u8 = 1;
+const ESCAPE: u8 = 255;
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let (last_column, primary) = forward_bwt(data);
+    let ranks = move_to_front(&last_column);
+
+    let mut encoded = Vec::with_capacity(data.len() / 2 + HEADER_SIZE);
+    encoded.extend_from_slice(&(primary as u32).to_le_bytes());
+    encoded.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    encode_zero_runs(&ranks, &mut encoded);
+
+    encoded
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if data.len() < HEADER_SIZE {
+        return Err(malformed("truncated header"));
+    }
+
+    let primary = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    if primary == 0 || primary > len {
+        return Err(malformed("primary index out of range"));
+    }
+
+    let ranks = decode_zero_runs(&data[HEADER_SIZE..], len)?;
+    let last_column = inverse_move_to_front(&ranks);
+
+    Ok(inverse_bwt(&last_column, primary))
+}
+
+/// Builds the BWT of `data` terminated by a virtual sentinel that sorts first
+/// Returns the last column without the sentinel and the row where it belongs
+fn forward_bwt(data: &[u8]) -> (Vec<u8>, usize) {
+    let sa = suffix_array(data);
+    let mut last_column = Vec::with_capacity(data.len());
+    let mut primary = 0;
+
+    // Row 0 is the sentinel suffix, preceded by the final byte
+    last_column.push(data[data.len() - 1]);
+    for (row, &start) in sa.iter().enumerate() {
+        if start == 0 {
+            primary = row + 1;
+        } else {
+            last_column.push(data[start as usize - 1]);
+        }
+    }
+
+    (last_column, primary)
+}
+
+fn inverse_bwt(last_column: &[u8], primary: usize) -> Vec<u8> {
+    let n = last_column.len();
+    let byte_at = |row: usize| last_column[if row < primary { row } else { row - 1 }];
+
+    let mut counts = [0usize; 256];
+    for &b in last_column {
+        counts[b as usize] += 1;
+    }
+
+    // The sentinel occupies the first slot of the first column
+    let mut starts = [0usize; 256];
+    let mut total = 1;
+    for (start, &count) in starts.iter_mut().zip(counts.iter()) {
+        *start = total;
+        total += count;
+    }
+
+    let mut lf = vec![0u32; n + 1];
+    let mut seen = [0usize; 256];
+    for (row, slot) in lf.iter_mut().enumerate() {
+        if row == primary {
+            continue;
+        }
+        let b = byte_at(row) as usize;
+        *slot = (starts[b] + seen[b]) as u32;
+        seen[b] += 1;
+    }
+
+    let mut decoded = vec![0u8; n];
+    let mut row = 0;
+    for slot in decoded.iter_mut().rev() {
+        *slot = byte_at(row);
+        row = lf[row] as usize;
+    }
+
+    decoded
+}
+
+/// Suffix array by prefix doubling with two counting-sort passes per round
+fn suffix_array(data: &[u8]) -> Vec<u32> {
+    let n = data.len();
+    let mut sa: Vec<u32> = (0..n as u32).collect();
+    let mut rank: Vec<u32> = data.iter().map(|&b| b as u32 + 1).collect();
+    let mut next_rank = vec![0u32; n];
+    let mut scratch = vec![0u32; n];
+    let mut max_rank = 256usize;
+    let mut k = 1;
+
+    loop {
+        // Rank 0 marks "past the end", so shorter suffixes sort first
+        let second = |i: u32, rank: &[u32]| rank.get(i as usize + k).copied().unwrap_or(0);
+
+        counting_sort(&sa, &mut scratch, max_rank, |i| second(i, &rank));
+        counting_sort(&scratch, &mut sa, max_rank, |i| rank[i as usize]);
+
+        next_rank[sa[0] as usize] = 1;
+        for w in 1..n {
+            let (prev, cur) = (sa[w - 1], sa[w]);
+            let differs = rank[prev as usize] != rank[cur as usize] || second(prev, &rank) != second(cur, &rank);
+            next_rank[cur as usize] = next_rank[prev as usize] + differs as u32;
+        }
+        std::mem::swap(&mut rank, &mut next_rank);
+
+        max_rank = rank[sa[n - 1] as usize] as usize;
+        if max_rank == n {
+            break;
+        }
+        k *= 2;
+    }
+
+    sa
+}
+
+/// Stable counting sort of `input` into `output` by `key`, keys in 0..=max_key
+fn counting_sort(input: &[u32], output: &mut [u32], max_key: usize, key: impl Fn(u32) -> u32) {
+    let mut buckets = vec![0usize; max_key + 2];
+    for &i in input {
+        buckets[key(i) as usize + 1] += 1;
+    }
+    for b in 1..buckets.len() {
+        buckets[b] += buckets[b - 1];
+    }
+    for &i in input {
+        let slot = &mut buckets[key(i) as usize];
+        output[*slot] = i;
+        *slot += 1;
+    }
+}
+
+fn move_to_front(data: &[u8]) -> Vec<u8> {
+    let mut order: Vec<u8> = (0..=255).collect();
+    data.iter()
+        .map(|&b| {
+            let pos = order.iter().position(|&o| o == b).unwrap();
+            order.copy_within(0..pos, 1);
+            order[0] = b;
+            pos as u8
+        })
+        .collect()
+}
+
+fn inverse_move_to_front(ranks: &[u8]) -> Vec<u8> {
+    let mut order: Vec<u8> = (0..=255).collect();
+    ranks
+        .iter()
+        .map(|&r| {
+            let pos = r as usize;
+            let b = order[pos];
+            order.copy_within(0..pos, 1);
+            order[0] = b;
+            b
+        })
+        .collect()
+}
+
+fn encode_zero_runs(ranks: &[u8], out: &mut Vec<u8>) {
+    let mut run = 0usize;
+
+    for &r in ranks {
+        if r == 0 {
+            run += 1;
+            continue;
+        }
+        flush_run(&mut run, out);
+        match r {
+            1..=253 => out.push(r + 1),
+            _ => {
+                out.push(ESCAPE);
+                out.push(r - 254);
+            }
+        }
+    }
+    flush_run(&mut run, out);
+}
+
+/// Writes a zero-run length in bijective base 2 using RUNA (1) and RUNB (2)
+fn flush_run(run: &mut usize, out: &mut Vec<u8>) {
+    let mut n = *run;
+    while n > 0 {
+        if n & 1 == 1 {
+            out.push(RUNA);
+            n = (n - 1) / 2;
+        } else {
+            out.push(RUNB);
+            n = (n - 2) / 2;
+        }
+    }
+    *run = 0;
+}
+
+fn decode_zero_runs(symbols: &[u8], len: usize) -> Result<Vec<u8>, HlcError> {
+    let mut ranks = Vec::with_capacity(len);
+    let mut run = 0usize;
+    let mut weight = 1usize;
+    let mut i = 0;
+
+    while i < symbols.len() {
+        let symbol = symbols[i];
+        i += 1;
+
+        if symbol == RUNA || symbol == RUNB {
+            run += weight * (symbol as usize + 1);
+            weight = weight.checked_mul(2).ok_or_else(|| malformed("zero run too long"))?;
+            if run > len {
+                return Err(malformed("zero run exceeds length"));
+            }
+            continue;
+        }
+
+        ranks.resize(ranks.len() + run, 0);
+        run = 0;
+        weight = 1;
+
+        if symbol == ESCAPE {
+            let extra = *symbols.get(i).ok_or_else(|| malformed("truncated escape"))?;
+            if extra > 1 {
+                return Err(malformed("invalid escape"));
+            }
+            ranks.push(254 + extra);
+            i += 1;
+        } else {
+            ranks.push(symbol - 1);
+        }
+    }
+    ranks.resize(ranks.len() + run, 0);
+
+    if ranks.len() != len {
+        return Err(malformed("length mismatch"));
+    }
+    Ok(ranks)
+}
+
+fn malformed(reason: &str) -> HlcError {
+    HlcError::DecompressionError(format!("Malformed BWT data: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bwt_empty() {
+        let data = vec![];
+        let encoded = encode(&data);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_bwt_known_output() {
+        let (last_column, primary) = forward_bwt(b"banana");
+        // Rows: $ a$ ana$ anana$ banana$ na$ nana$
+        assert_eq!(last_column, b"annbaa".to_vec());
+        assert_eq!(primary, 4);
+        assert_eq!(inverse_bwt(&last_column, primary), b"banana".to_vec());
+    }
+
+    #[test]
+    fn test_bwt_roundtrip_text() {
+        let data = b"The quick brown fox jumps over the lazy dog. ".repeat(200);
+        let encoded = encode(&data);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+        assert!(encoded.len() < data.len() / 10);
+    }
+
+    #[test]
+    fn test_bwt_roundtrip_all_bytes() {
+        let mut data: Vec<u8> = (0..=255).collect();
+        data.extend((0..5000).map(|i| ((i * 7919) % 251) as u8));
+        data.extend(vec![255u8; 300]);
+        data.extend(vec![0u8; 300]);
+
+        let encoded = encode(&data);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_bwt_single_byte_and_runs() {
+        for data in [vec![42u8], vec![7u8; 1000], b"abababababab".to_vec()] {
+            let encoded = encode(&data);
+            assert_eq!(decode(&encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_zero_run_lengths() {
+        for run in 0..40 {
+            let mut ranks = vec![3u8];
+            ranks.extend(vec![0u8; run]);
+            ranks.push(255);
+
+            let mut symbols = Vec::new();
+            encode_zero_runs(&ranks, &mut symbols);
+            assert_eq!(decode_zero_runs(&symbols, ranks.len()).unwrap(), ranks);
+        }
+    }
+
+    #[test]
+    fn test_bwt_malformed() {
+        assert!(decode(&[1, 0, 0]).is_err());
+        assert!(decode(&[0, 0, 0, 0, 5, 0, 0, 0, 2]).is_err());
+
+        let mut encoded = encode(b"hello hello hello");
+        encoded.pop();
+        assert!(decode(&encoded).is_err());
+    }
+}
@@ -1,6 +1,6 @@
-/// Delta coding implementation
-/// Transforms data[i] = data[i] - data[i-1] for i > 0
-/// This is effective for data with gradual changes or sequential patterns
+//! Delta coding implementation
+//! Transforms data[i] = data[i] - data[i-1] for i > 0
+//! This is effective for data with gradual changes or sequential patterns
 
 pub fn encode(data: &[u8]) -> Vec<u8> {
     if data.is_empty() {
@@ -1,6 +1,6 @@
-/// Dictionary-based compression placeholder
-/// This would implement LZ77-style dictionary compression or similar
-/// For now, it's a pass-through that can be extended later
+//! Dictionary-based compression placeholder
+//! This would implement LZ77-style dictionary compression or similar
+//! For now, it's a pass-through that can be extended later
 
 use crate::error::HlcError;
 use std::collections::HashMap;
@@ -40,7 +40,7 @@ impl Dictionary {
         
         // Select most frequent patterns
         let mut patterns: Vec<_> = pattern_counts.into_iter().collect();
-        patterns.sort_by(|a, b| b.1.cmp(&a.1)); // Sort by frequency, descending
+        patterns.sort_by_key(|p| std::cmp::Reverse(p.1)); // Sort by frequency, descending
         
         for (pattern, count) in patterns.into_iter().take(max_patterns) {
             if count > 1 && self.next_id < u16::MAX {
@@ -93,12 +93,13 @@ pub fn encode_with_dictionary(data: &[u8], dict: &Dictionary) -> Result<Vec<u8>,
         
         // Try to find the longest matching pattern
         for (pattern, &id) in &dict.patterns {
-            if i + pattern.len() <= data.len() && &data[i..i + pattern.len()] == pattern {
-                if pattern.len() > best_len {
-                    best_len = pattern.len();
-                    best_id = id;
-                    found_pattern = true;
-                }
+            if i + pattern.len() <= data.len()
+                && &data[i..i + pattern.len()] == pattern
+                && pattern.len() > best_len
+            {
+                best_len = pattern.len();
+                best_id = id;
+                found_pattern = true;
             }
         }
         
@@ -1,26 +1,457 @@
+//! Entropy coding wrapper around zstd and the native coders
+//! This provides the final compression stage after pre-processing transforms
+
+use crate::buffers;
 use crate::error::HlcError;
+use crate::transforms::{huffman, rans};
+use std::cell::RefCell;
+use std::io::Read;
+use std::ops::RangeInclusive;
+use zstd::zstd_safe;
+use std::str::FromStr;
 
-/// Entropy coding wrapper around zstd
-/// This provides the final compression stage after pre-processing transforms
+/// Final-stage coder used for a chunk, recorded in its header
+///
+/// The general-purpose backends other than zstd are behind cargo features;
+/// every variant keeps its id so archives name the codec they need even when
+/// this build cannot decode it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// zstd at the configured level; also finds LZ matches
+    #[default]
+    Zstd,
+    /// Order-0 canonical Huffman, for data already decorrelated by a transform
+    Huffman,
+    /// Order-0 rANS, for decorrelated data with a very skewed distribution
+    Rans,
+    /// LZ4 block format, for the fastest decode (feature `lz4`)
+    Lz4,
+    /// Brotli (feature `brotli`)
+    Brotli,
+    /// XZ/LZMA2, for archive-class ratio (feature `xz`)
+    Xz,
+    /// Raw deflate (feature `deflate`)
+    Deflate,
+}
 
-pub fn encode(data: &[u8], level: i32) -> Result<Vec<u8>, HlcError> {
+impl Codec {
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Zstd => 0,
+            Codec::Huffman => 1,
+            Codec::Rans => 2,
+            Codec::Lz4 => 3,
+            Codec::Brotli => 4,
+            Codec::Xz => 5,
+            Codec::Deflate => 6,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::Zstd),
+            1 => Some(Codec::Huffman),
+            2 => Some(Codec::Rans),
+            3 => Some(Codec::Lz4),
+            4 => Some(Codec::Brotli),
+            5 => Some(Codec::Xz),
+            6 => Some(Codec::Deflate),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Huffman => "huffman",
+            Codec::Rans => "rans",
+            Codec::Lz4 => "lz4",
+            Codec::Brotli => "brotli",
+            Codec::Xz => "xz",
+            Codec::Deflate => "deflate",
+        }
+    }
+
+    /// Levels a pipeline may give the codec, on the zstd scale; zstd also
+    /// takes its fast negative levels and ultra levels, down to the lowest a
+    /// recipe can record
+    pub fn levels(self) -> RangeInclusive<i32> {
+        match self {
+            Codec::Zstd => zstd_safe::min_c_level().max(i8::MIN as i32)..=zstd_safe::max_c_level(),
+            _ => 1..=19,
+        }
+    }
+
+    /// Whether this build can encode and decode the codec
+    pub fn is_available(self) -> bool {
+        match self {
+            Codec::Zstd | Codec::Huffman | Codec::Rans => true,
+            Codec::Lz4 => cfg!(feature = "lz4"),
+            Codec::Brotli => cfg!(feature = "brotli"),
+            Codec::Xz => cfg!(feature = "xz"),
+            Codec::Deflate => cfg!(feature = "deflate"),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = HlcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "zstd" => Ok(Codec::Zstd),
+            "huffman" => Ok(Codec::Huffman),
+            "rans" => Ok(Codec::Rans),
+            "lz4" => Ok(Codec::Lz4),
+            "brotli" => Ok(Codec::Brotli),
+            "xz" | "lzma" => Ok(Codec::Xz),
+            "deflate" => Ok(Codec::Deflate),
+            _ => Err(HlcError::ConfigError(format!("Invalid codec: {}", s))),
+        }
+    }
+}
+
+/// Encodes with the given codec
+///
+/// `level` is on the zstd scale (1-19) and is mapped linearly onto the range of
+/// the other leveled codecs; LZ4 and the native coders ignore it
+pub fn encode_with(codec: Codec, data: &[u8], level: i32) -> Result<Vec<u8>, HlcError> {
     if data.is_empty() {
         return Ok(Vec::new());
     }
-    
-    zstd::encode_all(data, level)
-        .map_err(|e| HlcError::CompressionError(format!("Entropy encoding failed: {}", e)))
+
+    match codec {
+        Codec::Zstd => encode(data, level),
+        Codec::Huffman => Ok(huffman::encode(data)),
+        Codec::Rans => Ok(rans::encode(data)),
+        Codec::Lz4 => backends::lz4_encode(data),
+        Codec::Brotli => backends::brotli_encode(data, scale_level(level, 11)),
+        Codec::Xz => backends::xz_encode(data, scale_level(level, 9)),
+        Codec::Deflate => backends::deflate_encode(data, scale_level(level, 9)),
+    }
+}
+
+/// Decodes `data` with `codec`; the native coders reject a stream claiming
+/// more than `max_len` bytes before allocating for it
+pub fn decode_with(codec: Codec, data: &[u8], max_len: usize) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    match codec {
+        Codec::Zstd => decode(data),
+        Codec::Huffman => huffman::decode(data, max_len),
+        Codec::Rans => rans::decode(data, max_len),
+        Codec::Lz4 => backends::lz4_decode(data),
+        Codec::Brotli => backends::brotli_decode(data),
+        Codec::Xz => backends::xz_decode(data),
+        Codec::Deflate => backends::deflate_decode(data),
+    }
+}
+
+/// Maps a zstd level (1-19) onto a codec's 0..=max range
+fn scale_level(level: i32, max: u32) -> u32 {
+    (level.clamp(1, 19) as u32 * max).div_ceil(19)
+}
+
+/// The optional backends; each function is stubbed with a clear error when
+/// its feature is disabled
+mod backends {
+    use crate::error::HlcError;
+    #[cfg(any(feature = "brotli", feature = "xz", feature = "deflate"))]
+    use std::io::{Read, Write};
+
+    #[cfg(not(all(feature = "lz4", feature = "brotli", feature = "xz", feature = "deflate")))]
+    fn unavailable(name: &str) -> HlcError {
+        HlcError::ConfigError(format!(
+            "{} codec support is not compiled in (enable the `{}` feature)",
+            name, name
+        ))
+    }
+
+    #[cfg(feature = "lz4")]
+    pub fn lz4_encode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(lz4_flex::compress_prepend_size(data))
+    }
+
+    #[cfg(feature = "lz4")]
+    pub fn lz4_decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        lz4_flex::decompress_size_prepended(data)
+            .map_err(|e| HlcError::DecompressionError(format!("LZ4 decoding failed: {}", e)))
+    }
+
+    #[cfg(not(feature = "lz4"))]
+    pub fn lz4_encode(_data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("lz4"))
+    }
+
+    #[cfg(not(feature = "lz4"))]
+    pub fn lz4_decode(_data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("lz4"))
+    }
+
+    #[cfg(feature = "brotli")]
+    pub fn brotli_encode(data: &[u8], quality: u32) -> Result<Vec<u8>, HlcError> {
+        let mut encoded = Vec::new();
+        {
+            let mut writer = brotli::CompressorWriter::new(&mut encoded, 4096, quality, 22);
+            writer
+                .write_all(data)
+                .map_err(|e| HlcError::CompressionError(format!("Brotli encoding failed: {}", e)))?;
+        }
+        Ok(encoded)
+    }
+
+    #[cfg(feature = "brotli")]
+    pub fn brotli_decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(data, 4096)
+            .read_to_end(&mut decoded)
+            .map_err(|e| HlcError::DecompressionError(format!("Brotli decoding failed: {}", e)))?;
+        Ok(decoded)
+    }
+
+    #[cfg(not(feature = "brotli"))]
+    pub fn brotli_encode(_data: &[u8], _quality: u32) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("brotli"))
+    }
+
+    #[cfg(not(feature = "brotli"))]
+    pub fn brotli_decode(_data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("brotli"))
+    }
+
+    #[cfg(feature = "xz")]
+    pub fn xz_encode(data: &[u8], preset: u32) -> Result<Vec<u8>, HlcError> {
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), preset);
+        encoder
+            .write_all(data)
+            .and_then(|_| encoder.finish())
+            .map_err(|e| HlcError::CompressionError(format!("XZ encoding failed: {}", e)))
+    }
+
+    #[cfg(feature = "xz")]
+    pub fn xz_decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        let mut decoded = Vec::new();
+        xz2::read::XzDecoder::new(data)
+            .read_to_end(&mut decoded)
+            .map_err(|e| HlcError::DecompressionError(format!("XZ decoding failed: {}", e)))?;
+        Ok(decoded)
+    }
+
+    #[cfg(not(feature = "xz"))]
+    pub fn xz_encode(_data: &[u8], _preset: u32) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("xz"))
+    }
+
+    #[cfg(not(feature = "xz"))]
+    pub fn xz_decode(_data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("xz"))
+    }
+
+    #[cfg(feature = "deflate")]
+    pub fn deflate_encode(data: &[u8], level: u32) -> Result<Vec<u8>, HlcError> {
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::new(level));
+        encoder
+            .write_all(data)
+            .and_then(|_| encoder.finish())
+            .map_err(|e| HlcError::CompressionError(format!("Deflate encoding failed: {}", e)))
+    }
+
+    #[cfg(feature = "deflate")]
+    pub fn deflate_decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        let mut decoded = Vec::new();
+        flate2::read::DeflateDecoder::new(data)
+            .read_to_end(&mut decoded)
+            .map_err(|e| HlcError::DecompressionError(format!("Deflate decoding failed: {}", e)))?;
+        Ok(decoded)
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    pub fn deflate_encode(_data: &[u8], _level: u32) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("deflate"))
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    pub fn deflate_decode(_data: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Err(unavailable("deflate"))
+    }
+}
+
+pub fn encode(data: &[u8], level: i32) -> Result<Vec<u8>, HlcError> {
+    encode_zstd(data, level, &[], &ZstdParams::default())
 }
 
 pub fn decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+    decode_zstd(data, &[], DEFAULT_MAX_WINDOW_LOG)
+}
+
+thread_local! {
+    // zstd contexts are expensive to set up, so each rayon worker keeps one
+    // of each and resets it between chunks
+    static COMPRESSOR: RefCell<zstd_safe::CCtx<'static>> = RefCell::new(zstd_safe::CCtx::create());
+    static DECOMPRESSOR: RefCell<zstd_safe::DCtx<'static>> = RefCell::new(zstd_safe::DCtx::create());
+}
+
+/// Largest zstd window a decoder accepts by default, as a power of two
+/// (128 MiB, the zstd library's own default limit)
+pub const DEFAULT_MAX_WINDOW_LOG: u32 = 27;
+
+/// Advanced zstd settings for the entropy stage; the defaults give the same
+/// frames as plain `encode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ZstdParams {
+    /// Long-distance matching, which finds repeats far apart in large chunks
+    pub long_distance: bool,
+    /// Match window as a power of two (10-31); `None` lets the level decide,
+    /// or 27 with long-distance matching
+    pub window_log: Option<u32>,
+    /// Record the decoded size in each frame header
+    pub content_size: bool,
+    /// Append zstd's own 32-bit checksum to each frame
+    pub checksum: bool,
+}
+
+impl ZstdParams {
+    /// Enables long-distance matching with a `2^window_log` byte window, like
+    /// `zstd --long=27`
+    pub fn with_long_distance(mut self, window_log: u32) -> Self {
+        self.long_distance = true;
+        self.window_log = Some(window_log);
+        self
+    }
+
+    pub fn with_window_log(mut self, window_log: u32) -> Self {
+        self.window_log = Some(window_log);
+        self
+    }
+
+    pub fn with_content_size(mut self, content_size: bool) -> Self {
+        self.content_size = content_size;
+        self
+    }
+
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Window log the frames will need to decode, as far as it is known up
+    /// front
+    pub fn effective_window_log(&self) -> Option<u32> {
+        self.window_log.or(self.long_distance.then_some(DEFAULT_MAX_WINDOW_LOG))
+    }
+}
+
+/// zstd-encodes `data` with `params`, priming the match window with `prefix`
+/// when it is not empty; decoding needs the same prefix
+///
+/// Uses this thread's pooled context, which borrows the prefix only for the
+/// duration of the call
+pub fn encode_zstd(data: &[u8], level: i32, prefix: &[u8], params: &ZstdParams) -> Result<Vec<u8>, HlcError> {
     if data.is_empty() {
         return Ok(Vec::new());
     }
-    
-    zstd::decode_all(data)
-        .map_err(|e| HlcError::DecompressionError(format!("Entropy decoding failed: {}", e)))
+
+    let mut encoded = buffers::take(zstd_safe::compress_bound(data.len()));
+    let result = COMPRESSOR.with(|context| {
+        let mut context = context.borrow_mut();
+        configure_compressor(&mut context, level, params)?;
+        if prefix.is_empty() {
+            context.compress2(&mut encoded, data)
+        } else {
+            compress_primed(&mut context, prefix, &mut encoded, data)
+        }
+    });
+
+    match result {
+        Ok(_) => {
+            // The chunk keeps this buffer, so give back the unused part of
+            // the compress_bound reservation
+            encoded.shrink_to_fit();
+            Ok(encoded)
+        }
+        Err(code) => Err(HlcError::CompressionError(format!(
+            "Entropy encoding failed: {}",
+            zstd_safe::get_error_name(code)
+        ))),
+    }
 }
 
+/// Compresses one frame with the pooled `context` primed by `prefix`
+fn compress_primed(
+    context: &mut zstd_safe::CCtx<'static>,
+    prefix: &[u8],
+    output: &mut Vec<u8>,
+    data: &[u8],
+) -> zstd_safe::SafeResult {
+    // SAFETY: only the lifetime changes. zstd keeps a prefix for the next
+    // frame alone, and the reset below drops it before `prefix` goes out of
+    // scope; every use of the pooled context also starts with a reset
+    let primed = unsafe { &mut *(context as *mut zstd_safe::CCtx<'static>).cast::<zstd_safe::CCtx<'_>>() };
+    let result = primed.ref_prefix(prefix).and_then(|_| primed.compress2(output, data));
+    primed.reset(zstd_safe::ResetDirective::SessionAndParameters)?;
+    result
+}
+
+fn configure_compressor(context: &mut zstd_safe::CCtx, level: i32, params: &ZstdParams) -> zstd_safe::SafeResult {
+    use zstd_safe::CParameter;
+
+    context.reset(zstd_safe::ResetDirective::SessionAndParameters)?;
+    context.set_parameter(CParameter::CompressionLevel(level))?;
+    context.set_parameter(CParameter::EnableLongDistanceMatching(params.long_distance))?;
+    if let Some(window_log) = params.window_log {
+        context.set_parameter(CParameter::WindowLog(window_log))?;
+    }
+    context.set_parameter(CParameter::ChecksumFlag(params.checksum))?;
+    context.set_parameter(CParameter::ContentSizeFlag(params.content_size))
+}
+
+/// Decodes a zstd frame written by `encode_zstd`, refusing frames whose window
+/// is larger than `2^max_window_log` bytes
+pub fn decode_zstd(data: &[u8], prefix: &[u8], max_window_log: u32) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let failed = |e: std::io::Error| HlcError::DecompressionError(format!("Entropy decoding failed: {}", e));
+    let size_hint = match zstd_safe::get_frame_content_size(data) {
+        Ok(Some(size)) => (size as usize).min(MAX_SIZE_HINT),
+        _ => data.len().saturating_mul(4).min(MAX_SIZE_HINT),
+    };
+    let mut decoded = buffers::take(size_hint);
+
+    if prefix.is_empty() {
+        DECOMPRESSOR.with(|context| {
+            let mut context = context.borrow_mut();
+            context
+                .reset(zstd_safe::ResetDirective::SessionAndParameters)
+                .and_then(|_| context.set_parameter(zstd_safe::DParameter::WindowLogMax(max_window_log)))
+                .map_err(|code| {
+                    HlcError::DecompressionError(format!(
+                        "Entropy decoding failed: {}",
+                        zstd_safe::get_error_name(code)
+                    ))
+                })?;
+            let mut decoder = zstd::stream::read::Decoder::with_context(data, &mut context);
+            decoder.read_to_end(&mut decoded).map_err(failed)?;
+            Ok::<_, HlcError>(())
+        })?;
+    } else {
+        let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(data, prefix).map_err(failed)?;
+        decoder.window_log_max(max_window_log).map_err(failed)?;
+        decoder.read_to_end(&mut decoded).map_err(failed)?;
+    }
+
+    // A pooled buffer may be far larger than this chunk, and decoded chunks
+    // stay alive until they are written
+    decoded.shrink_to_fit();
+    Ok(decoded)
+}
+
+/// Cap on the output buffer reserved up front from a frame's declared size
+const MAX_SIZE_HINT: usize = 64 << 20;
+
 /// Advanced entropy coding with dictionary support
 pub struct EntropyEncoder {
     level: i32,
@@ -157,6 +588,110 @@ mod tests {
         println!("Max: {} bytes", max.len());
     }
 
+    #[test]
+    fn test_zstd_output_is_not_oversized() {
+        let data = vec![7u8; 1 << 20];
+        let encoded = encode_zstd(&data, 3, &[], &ZstdParams::default()).unwrap();
+        assert!(encoded.len() < 1024);
+        assert!(encoded.capacity() <= encoded.len() + 64);
+
+        // Decoding into a large pooled buffer hands back an exact-size one
+        buffers::give(Vec::with_capacity(8 << 20));
+        let small = vec![7u8; 1000];
+        let encoded = encode_zstd(&small, 3, &[], &ZstdParams::default()).unwrap();
+        let decoded = decode_zstd(&encoded, &[], DEFAULT_MAX_WINDOW_LOG).unwrap();
+        assert_eq!(decoded, small);
+        assert!(decoded.capacity() <= decoded.len() + 64);
+    }
+
+    const ALL_CODECS: [Codec; 7] = [
+        Codec::Zstd,
+        Codec::Huffman,
+        Codec::Rans,
+        Codec::Lz4,
+        Codec::Brotli,
+        Codec::Xz,
+        Codec::Deflate,
+    ];
+
+    #[test]
+    fn test_codec_roundtrip() {
+        let data = b"Hello, world! This is a test string for compression.".repeat(20);
+
+        for codec in ALL_CODECS.into_iter().filter(|c| c.is_available()) {
+            let encoded = encode_with(codec, &data, 5).unwrap();
+            assert_eq!(decode_with(codec, &encoded, data.len()).unwrap(), data);
+            assert_eq!(Codec::from_id(codec.id()), Some(codec));
+        }
+        assert_eq!(Codec::from_id(200), None);
+    }
+
+    #[test]
+    fn test_prefix_roundtrip() {
+        let prefix: Vec<u8> = (0u32..4096).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let data = [&prefix[1000..3000], b"new tail".as_slice()].concat();
+
+        let cold = encode(&data, 5).unwrap();
+        let primed = encode_zstd(&data, 5, &prefix, &ZstdParams::default()).unwrap();
+        assert!(primed.len() * 4 < cold.len());
+        assert_eq!(decode_zstd(&primed, &prefix, DEFAULT_MAX_WINDOW_LOG).unwrap(), data);
+        assert!(decode(&primed).is_err());
+
+        // The pooled context forgets the prefix once the primed frame is done
+        let after = encode_zstd(&data, 5, &[], &ZstdParams::default()).unwrap();
+        assert_eq!(decode_zstd(&after, &[], DEFAULT_MAX_WINDOW_LOG).unwrap(), data);
+        assert_eq!(after.len(), encode_zstd(&data, 5, &[], &ZstdParams::default()).unwrap().len());
+    }
+
+    #[test]
+    fn test_zstd_params() {
+        let block: Vec<u8> = (0u32..8192).map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8).collect();
+        let data = [block.as_slice(), &[0u8; 600_000], block.as_slice()].concat();
+
+        let params = ZstdParams::default().with_long_distance(20).with_checksum(true);
+        let encoded = encode_zstd(&data, 3, &[], &params).unwrap();
+        assert_eq!(decode_zstd(&encoded, &[], DEFAULT_MAX_WINDOW_LOG).unwrap(), data);
+        assert_eq!(params.effective_window_log(), Some(20));
+
+        // The frame needs its full 1 MiB window, which a tighter limit refuses
+        assert!(matches!(decode_zstd(&encoded, &[], 19), Err(HlcError::DecompressionError(_))));
+
+        let sized = encode_zstd(&data, 3, &[], &params.with_content_size(true)).unwrap();
+        assert_eq!(zstd::zstd_safe::get_frame_content_size(&sized).ok(), Some(Some(data.len() as u64)));
+        assert!(zstd::zstd_safe::get_frame_content_size(&encoded).ok() == Some(None));
+
+        // Windows shrink to fit small inputs
+        let small = encode_zstd(&block, 3, &[], &params).unwrap();
+        assert_eq!(decode_zstd(&small, &[], 14).unwrap(), block);
+
+        // Default parameters produce the same frames as plain encoding
+        assert_eq!(encode_zstd(&data, 3, &[], &ZstdParams::default()).unwrap(), encode(&data, 3).unwrap());
+    }
+
+    #[test]
+    fn test_codec_names() {
+        for codec in ALL_CODECS {
+            assert_eq!(codec.name().parse::<Codec>().unwrap(), codec);
+        }
+        assert!("snappy".parse::<Codec>().is_err());
+    }
+
+    #[test]
+    fn test_unavailable_codec_errors() {
+        for codec in ALL_CODECS.into_iter().filter(|c| !c.is_available()) {
+            assert!(encode_with(codec, b"data", 5).is_err());
+            assert!(decode_with(codec, b"data", 4).is_err());
+        }
+    }
+
+    #[test]
+    fn test_scale_level() {
+        assert_eq!(scale_level(1, 9), 1);
+        assert_eq!(scale_level(19, 9), 9);
+        assert_eq!(scale_level(19, 11), 11);
+        assert_eq!(scale_level(100, 11), 11);
+    }
+
     #[test]
     fn test_compression_ratio_estimation() {
         // Highly compressible data (all zeros)
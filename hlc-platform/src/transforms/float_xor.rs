@@ -0,0 +1,237 @@
+//! XOR-based floating-point coding (Gorilla/Chimp style)
+//! Each value is XORed with the previous one and only the meaningful bits of the
+//! result are stored, framed by their leading/trailing zero counts
+//! This is effective for f32/f64 columns where neighbouring values share sign,
+//! exponent and high mantissa bits
+//!
+//! Format: [WIDTH][COUNT: u32 LE][TAIL_LEN][TAIL BYTES][BITSTREAM]
+
+use crate::error::HlcError;
+use crate::transforms::bitio::{BitReader, BitWriter};
+
+/// Size of the fixed header: width byte + value count + tail length byte
+const HEADER_SIZE: usize = 1 + 4 + 1;
+
+pub fn encode(data: &[u8], width: usize) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let width = if width == 4 { 4 } else { 8 };
+    let bits = (width * 8) as u32;
+    let (lz_bits, len_bits) = field_widths(width);
+    let max_lz = (1u32 << lz_bits) - 1;
+
+    let values: Vec<u64> = data.chunks_exact(width).map(|w| read_word(w, width)).collect();
+    let tail = &data[values.len() * width..];
+
+    let mut encoded = Vec::with_capacity(data.len() / 2 + HEADER_SIZE + tail.len());
+    encoded.push(width as u8);
+    encoded.extend_from_slice(&(values.len() as u32).to_le_bytes());
+    encoded.push(tail.len() as u8);
+    encoded.extend_from_slice(tail);
+
+    let mut writer = BitWriter::new(encoded);
+    if let Some(&first) = values.first() {
+        writer.write_bits(first, bits);
+    }
+
+    let mut prev = values.first().copied().unwrap_or(0);
+    let mut window: Option<(u32, u32)> = None;
+
+    for &value in values.iter().skip(1) {
+        let xor = value ^ prev;
+        prev = value;
+
+        if xor == 0 {
+            writer.write_bits(0, 1);
+            continue;
+        }
+        writer.write_bits(1, 1);
+
+        let leading = (xor.leading_zeros() - (64 - bits)).min(max_lz);
+        let trailing = xor.trailing_zeros();
+
+        match window {
+            // Meaningful bits fit inside the previous window: reuse it
+            Some((prev_leading, prev_trailing)) if leading >= prev_leading && trailing >= prev_trailing => {
+                writer.write_bits(0, 1);
+                writer.write_bits(xor >> prev_trailing, bits - prev_leading - prev_trailing);
+            }
+            _ => {
+                let length = bits - leading - trailing;
+                writer.write_bits(1, 1);
+                writer.write_bits(leading as u64, lz_bits);
+                writer.write_bits((length - 1) as u64, len_bits);
+                writer.write_bits(xor >> trailing, length);
+                window = Some((leading, trailing));
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if data.len() < HEADER_SIZE {
+        return Err(malformed("truncated header"));
+    }
+
+    let width = data[0] as usize;
+    if width != 4 && width != 8 {
+        return Err(malformed(&format!("unsupported element width {}", width)));
+    }
+    let bits = (width * 8) as u32;
+    let (lz_bits, len_bits) = field_widths(width);
+
+    let count = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    let tail_len = data[5] as usize;
+    if data.len() < HEADER_SIZE + tail_len {
+        return Err(malformed("truncated tail"));
+    }
+    let tail = &data[HEADER_SIZE..HEADER_SIZE + tail_len];
+    let stream = &data[HEADER_SIZE + tail_len..];
+
+    // The first value takes a full word and every later one at least a bit,
+    // so a count the stream cannot hold is rejected before reserving for it
+    if count > 0 && bits as u64 + (count as u64 - 1) > stream.len() as u64 * 8 {
+        return Err(malformed("value count exceeds stream"));
+    }
+
+    let mut reader = BitReader::new(stream);
+    let mut decoded = Vec::with_capacity(count * width + tail_len);
+
+    if count > 0 {
+        let mut prev = reader.read_bits(bits).ok_or_else(|| malformed("truncated stream"))?;
+        write_word(&mut decoded, prev, width);
+
+        let mut window = (0u32, 0u32);
+        for _ in 1..count {
+            let changed = reader.read_bits(1).ok_or_else(|| malformed("truncated stream"))?;
+            if changed == 1 {
+                let new_window = reader.read_bits(1).ok_or_else(|| malformed("truncated stream"))?;
+                if new_window == 1 {
+                    let leading = reader.read_bits(lz_bits).ok_or_else(|| malformed("truncated stream"))? as u32;
+                    let length = reader.read_bits(len_bits).ok_or_else(|| malformed("truncated stream"))? as u32 + 1;
+                    if leading + length > bits {
+                        return Err(malformed("invalid bit window"));
+                    }
+                    window = (leading, bits - leading - length);
+                }
+
+                let (leading, trailing) = window;
+                let meaningful = reader
+                    .read_bits(bits - leading - trailing)
+                    .ok_or_else(|| malformed("truncated stream"))?;
+                prev ^= meaningful << trailing;
+            }
+            write_word(&mut decoded, prev, width);
+        }
+    }
+
+    decoded.extend_from_slice(tail);
+    Ok(decoded)
+}
+
+/// Bit widths of the leading-zero count and meaningful-length fields
+fn field_widths(width: usize) -> (u32, u32) {
+    if width == 4 {
+        (5, 5)
+    } else {
+        (6, 6)
+    }
+}
+
+fn read_word(bytes: &[u8], width: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf[..width].copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+fn write_word(out: &mut Vec<u8>, value: u64, width: usize) {
+    out.extend_from_slice(&value.to_le_bytes()[..width]);
+}
+
+fn malformed(reason: &str) -> HlcError {
+    HlcError::DecompressionError(format!("Malformed float XOR data: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f64_bytes(values: &[f64]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_float_xor_empty() {
+        let data = vec![];
+        let encoded = encode(&data, 8);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_float_xor_f64_roundtrip() {
+        let values: Vec<f64> = (0..1000).map(|i| 20.0 + (i as f64 * 0.01).sin()).collect();
+        let data = f64_bytes(&values);
+
+        let encoded = encode(&data, 8);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_float_xor_f32_roundtrip() {
+        let data: Vec<u8> = (0..1000)
+            .flat_map(|i| (100.0f32 + i as f32 * 0.25).to_le_bytes())
+            .collect();
+
+        let encoded = encode(&data, 4);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_float_xor_repeated_values() {
+        let data = f64_bytes(&[3.5; 512]);
+
+        let encoded = encode(&data, 8);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+
+        // Repeated values cost a single bit each
+        assert!(encoded.len() < data.len() / 32);
+    }
+
+    #[test]
+    fn test_float_xor_tail_bytes() {
+        let mut data = f64_bytes(&[1.0, 1.5, 2.0]);
+        data.extend_from_slice(&[0xAB, 0xCD, 0xEF]);
+
+        let encoded = encode(&data, 8);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_float_xor_malformed() {
+        assert!(decode(&[8, 10, 0]).is_err());
+        assert!(decode(&[3, 0, 0, 0, 0, 0]).is_err());
+
+        let encoded = encode(&f64_bytes(&[1.0, 2.0, 3.0]), 8);
+        assert!(decode(&encoded[..encoded.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_float_xor_corrupted_count() {
+        let mut encoded = encode(&f64_bytes(&[1.0, 2.0, 3.0]), 8);
+        encoded[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(decode(&encoded), Err(HlcError::DecompressionError(_))));
+    }
+}
@@ -0,0 +1,250 @@
+//! Order-0 canonical Huffman coder
+//! A pure entropy coder for data whose redundancy has already been removed by
+//! earlier transforms (BWT, shuffle); it decodes faster than zstd and does not
+//! spend bits on LZ match headers that would never be used
+//!
+//! Format: [ORIGINAL LEN: u32 LE][CODE LENGTHS: 256 x 4 bits][BITSTREAM]
+
+use crate::error::HlcError;
+use crate::transforms::bitio::{BitReader, BitWriter};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Longest code the 4-bit length table can describe
+const MAX_CODE_LEN: u8 = 15;
+
+/// Size of the fixed header: original length + nibble-packed code lengths
+const HEADER_SIZE: usize = 4 + 128;
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let mut freqs = [0u64; 256];
+    for &b in data {
+        freqs[b as usize] += 1;
+    }
+    let lengths = code_lengths(&freqs);
+    let codes = canonical_codes(&lengths);
+
+    let mut encoded = Vec::with_capacity(data.len() / 2 + HEADER_SIZE);
+    encoded.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    for pair in lengths.chunks_exact(2) {
+        encoded.push((pair[0] << 4) | pair[1]);
+    }
+
+    let mut writer = BitWriter::new(encoded);
+    for &b in data {
+        writer.write_bits(codes[b as usize] as u64, lengths[b as usize] as u32);
+    }
+    writer.finish()
+}
+
+/// Decodes a stream from `encode`, rejecting one that claims more than
+/// `max_len` bytes
+pub fn decode(data: &[u8], max_len: usize) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if data.len() < HEADER_SIZE {
+        return Err(malformed("truncated header"));
+    }
+
+    let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    // Every symbol takes at least one bit
+    if len > max_len || len > (data.len() - HEADER_SIZE) * 8 {
+        return Err(malformed("length exceeds stream"));
+    }
+    let mut lengths = [0u8; 256];
+    for (i, &packed) in data[4..HEADER_SIZE].iter().enumerate() {
+        lengths[i * 2] = packed >> 4;
+        lengths[i * 2 + 1] = packed & 0x0F;
+    }
+
+    // Canonical decoding tables: symbols sorted by (length, value), plus the
+    // first code and first symbol index of each length
+    let mut count = [0u32; MAX_CODE_LEN as usize + 1];
+    for &l in lengths.iter() {
+        count[l as usize] += 1;
+    }
+    count[0] = 0;
+
+    let mut symbols: Vec<u8> = (0..=255u8).filter(|&s| lengths[s as usize] > 0).collect();
+    symbols.sort_by_key(|&s| lengths[s as usize]);
+    if symbols.is_empty() {
+        return Err(malformed("empty code table"));
+    }
+
+    let mut first_code = [0u32; MAX_CODE_LEN as usize + 2];
+    let mut first_index = [0u32; MAX_CODE_LEN as usize + 2];
+    let mut code = 0u32;
+    let mut index = 0u32;
+    for l in 1..=MAX_CODE_LEN as usize {
+        first_code[l] = code;
+        first_index[l] = index;
+        code = (code + count[l]) << 1;
+        index += count[l];
+    }
+
+    let mut reader = BitReader::new(&data[HEADER_SIZE..]);
+    let mut decoded = Vec::with_capacity(len);
+
+    for _ in 0..len {
+        let mut code = 0u32;
+        let mut symbol = None;
+        for l in 1..=MAX_CODE_LEN as usize {
+            code = (code << 1) | reader.read_bits(1).ok_or_else(|| malformed("truncated stream"))? as u32;
+            let offset = code.wrapping_sub(first_code[l]);
+            if offset < count[l] {
+                symbol = Some(symbols[(first_index[l] + offset) as usize]);
+                break;
+            }
+        }
+        decoded.push(symbol.ok_or_else(|| malformed("invalid code"))?);
+    }
+
+    Ok(decoded)
+}
+
+/// Builds Huffman code lengths, flattening the frequencies until no code is
+/// longer than `MAX_CODE_LEN`
+fn code_lengths(freqs: &[u64; 256]) -> [u8; 256] {
+    let mut scaled = *freqs;
+
+    loop {
+        let lengths = tree_lengths(&scaled);
+        if lengths.iter().all(|&l| l <= MAX_CODE_LEN) {
+            return lengths;
+        }
+        for f in scaled.iter_mut().filter(|f| **f > 0) {
+            *f = (*f / 2).max(1);
+        }
+    }
+}
+
+fn tree_lengths(freqs: &[u64; 256]) -> [u8; 256] {
+    let mut lengths = [0u8; 256];
+    let present: Vec<usize> = (0..256).filter(|&s| freqs[s] > 0).collect();
+
+    // A lone symbol still needs a one-bit code
+    if present.len() == 1 {
+        lengths[present[0]] = 1;
+        return lengths;
+    }
+
+    // Nodes 0..256 are leaves; internal nodes are appended with their parent links
+    let mut parent = vec![usize::MAX; 256];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> =
+        present.iter().map(|&s| Reverse((freqs[s], s))).collect();
+
+    while heap.len() > 1 {
+        let Reverse((fa, a)) = heap.pop().unwrap();
+        let Reverse((fb, b)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[a] = node;
+        parent[b] = node;
+        heap.push(Reverse((fa + fb, node)));
+    }
+
+    for &s in &present {
+        let mut depth = 0u32;
+        let mut node = s;
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            depth += 1;
+        }
+        lengths[s] = depth.min(u8::MAX as u32) as u8;
+    }
+
+    lengths
+}
+
+fn canonical_codes(lengths: &[u8; 256]) -> [u32; 256] {
+    let mut codes = [0u32; 256];
+    let mut symbols: Vec<usize> = (0..256).filter(|&s| lengths[s] > 0).collect();
+    symbols.sort_by_key(|&s| (lengths[s], s));
+
+    let mut code = 0u32;
+    let mut prev_len = 0u8;
+    for s in symbols {
+        code <<= lengths[s] - prev_len;
+        codes[s] = code;
+        code += 1;
+        prev_len = lengths[s];
+    }
+
+    codes
+}
+
+fn malformed(reason: &str) -> HlcError {
+    HlcError::DecompressionError(format!("Malformed Huffman data: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_huffman_empty() {
+        let data = vec![];
+        let encoded = encode(&data);
+        let decoded = decode(&encoded, data.len()).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_huffman_roundtrip() {
+        let data = b"Hello, world! This is a test string for Huffman coding.".repeat(50);
+        let encoded = encode(&data);
+        let decoded = decode(&encoded, data.len()).unwrap();
+        assert_eq!(data, decoded);
+        assert!(encoded.len() < data.len());
+    }
+
+    #[test]
+    fn test_huffman_single_symbol() {
+        let data = vec![9u8; 1000];
+        let encoded = encode(&data);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+        assert!(encoded.len() <= HEADER_SIZE + 1000 / 8 + 1);
+    }
+
+    #[test]
+    fn test_huffman_length_limit() {
+        // Fibonacci-like frequencies force a very deep tree before limiting
+        let mut data = Vec::new();
+        let (mut a, mut b) = (1usize, 1usize);
+        for symbol in 0..25u8 {
+            data.resize(data.len() + a, symbol);
+            (a, b) = (b, a + b);
+        }
+
+        let encoded = encode(&data);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_huffman_all_bytes() {
+        let data: Vec<u8> = (0..20000).map(|i| ((i * 7919) % 256) as u8).collect();
+        let encoded = encode(&data);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_huffman_malformed() {
+        assert!(decode(&[1, 0, 0, 0], 1).is_err());
+
+        let mut encoded = encode(b"abcabcabcabd");
+        encoded.truncate(encoded.len() - 1);
+        assert!(decode(&encoded, 12).is_err());
+
+        // A corrupted length is rejected before anything is allocated
+        let mut encoded = encode(b"abcabcabcabd");
+        encoded[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode(&encoded, usize::MAX).is_err());
+        assert!(decode(&encode(b"abcabcabcabd"), 11).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Structure-aware layout for JSON and NDJSON
+//! The text is tokenized and split into a key table, a structure stream, a
+//! number stream and a text stream. Object keys are replaced by their index in
+//! the key table, so records repeating the same keys leave an almost constant
+//! structure stream. Numbers that format back exactly become zigzag varint
+//! deltas against the previous number under the same key; strings, other
+//! numbers, unusual whitespace and anything the tokenizer does not recognise
+//! are kept as raw bytes in the text stream. Any input round-trips byte for
+//! byte, valid JSON or not
+//!
+//! Format: [KEYS varint][LEN varint, BYTES]...[STRUCTURE LEN varint][OP...]
+//! [NUMBERS LEN varint][DELTA varint...][LEN varint, BYTES]..., where the ops
+//! are the punctuation bytes themselves, `k` with a key index, `"` a string,
+//! `d` with a decimals byte a number, `r` a number kept as text, `t`/`f`/`n`
+//! the literals, ` ` and `\n` single whitespace, `w` other whitespace and `x`
+//! unrecognised bytes
+
+use crate::error::HlcError;
+use crate::transforms::csv::{format_fixed, parse_fixed, MAX_DIGITS};
+use crate::transforms::varint::{unzigzag, write_varint, zigzag, Cursor};
+use std::collections::HashMap;
+
+/// Lines `is_ndjson` looks at
+const DETECT_LINES: usize = 32;
+
+const KEY: u8 = b'k';
+const STRING: u8 = b'"';
+const NUMBER: u8 = b'd';
+const NUMBER_TEXT: u8 = b'r';
+const TRUE: u8 = b't';
+const FALSE: u8 = b'f';
+const NULL: u8 = b'n';
+const SPACE: u8 = b' ';
+const NEWLINE: u8 = b'\n';
+const WHITESPACE: u8 = b'w';
+const RAW: u8 = b'x';
+
+const LITERALS: [(u8, &[u8]); 3] = [(TRUE, b"true"), (FALSE, b"false"), (NULL, b"null")];
+
+enum Token {
+    Punctuation(u8),
+    Literal(u8),
+    Whitespace,
+    /// A string, and whether it is an object key
+    String(bool),
+    Number,
+}
+
+/// Whether nearly every one of the first lines of `data` holds one JSON object
+///
+/// The last line looked at may be cut off, and so is left out; at least two
+/// non-empty lines are needed
+pub fn is_ndjson(data: &[u8]) -> bool {
+    let mut lines: Vec<&[u8]> = data.split(|&b| b == b'\n').take(DETECT_LINES + 1).collect();
+    lines.pop();
+    let lines: Vec<&[u8]> = lines.into_iter().map(|line| line.trim_ascii()).filter(|line| !line.is_empty()).collect();
+    if lines.len() < 2 {
+        return false;
+    }
+
+    let objects = lines.iter().filter(|line| line.starts_with(b"{") && line.ends_with(b"}")).count();
+    objects * 5 >= lines.len() * 4
+}
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let mut keys: Vec<&[u8]> = Vec::new();
+    let mut key_index: HashMap<&[u8], usize> = HashMap::new();
+    let mut structure = Vec::with_capacity(data.len() / 4);
+    let mut numbers = Vec::new();
+    let mut text = Vec::with_capacity(data.len() / 2);
+    // Previous number under each key, after one slot for numbers outside any
+    let mut previous = vec![0i64];
+    let mut context = 0;
+    let mut open_until = 0;
+
+    let mut pos = 0;
+    let mut raw_start = None;
+    while pos < data.len() {
+        let Some((token, end)) = next_token(data, pos, &mut open_until) else {
+            raw_start.get_or_insert(pos);
+            pos += 1;
+            continue;
+        };
+        if let Some(start) = raw_start.take() {
+            push_text(&mut structure, &mut text, RAW, &data[start..pos]);
+        }
+
+        let bytes = &data[pos..end];
+        match token {
+            Token::Punctuation(b) => structure.push(b),
+            Token::Literal(op) => structure.push(op),
+            Token::Whitespace => match bytes {
+                b" " => structure.push(SPACE),
+                b"\n" => structure.push(NEWLINE),
+                _ => push_text(&mut structure, &mut text, WHITESPACE, bytes),
+            },
+            Token::String(true) => {
+                let key = &bytes[1..bytes.len() - 1];
+                let index = *key_index.entry(key).or_insert_with(|| {
+                    keys.push(key);
+                    previous.push(0);
+                    keys.len() - 1
+                });
+                structure.push(KEY);
+                write_varint(&mut structure, index as u64);
+                context = index + 1;
+            }
+            Token::String(false) => push_text(&mut structure, &mut text, STRING, &bytes[1..bytes.len() - 1]),
+            Token::Number => match parse_fixed(bytes) {
+                Some((value, decimals)) => {
+                    structure.push(NUMBER);
+                    structure.push(decimals);
+                    write_varint(&mut numbers, zigzag(value.wrapping_sub(previous[context])));
+                    previous[context] = value;
+                }
+                None => push_text(&mut structure, &mut text, NUMBER_TEXT, bytes),
+            },
+        }
+        pos = end;
+    }
+    if let Some(start) = raw_start {
+        push_text(&mut structure, &mut text, RAW, &data[start..]);
+    }
+
+    let mut encoded = Vec::with_capacity(structure.len() + numbers.len() + text.len() + 16);
+    write_varint(&mut encoded, keys.len() as u64);
+    for key in &keys {
+        write_varint(&mut encoded, key.len() as u64);
+        encoded.extend_from_slice(key);
+    }
+    for stream in [&structure, &numbers] {
+        write_varint(&mut encoded, stream.len() as u64);
+        encoded.extend_from_slice(stream);
+    }
+    encoded.extend_from_slice(&text);
+    encoded
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut text = Cursor::new(data, "JSON");
+    let keys = (0..text.len()?)
+        .map(|_| {
+            let len = text.len()?;
+            text.bytes(len)
+        })
+        .collect::<Result<Vec<_>, HlcError>>()?;
+    let len = text.len()?;
+    let mut structure = Cursor::new(text.bytes(len)?, "JSON");
+    let len = text.len()?;
+    let mut numbers = Cursor::new(text.bytes(len)?, "JSON");
+
+    let mut decoded = Vec::with_capacity(data.len() * 3);
+    let mut previous = vec![0i64; keys.len() + 1];
+    let mut context = 0;
+    while !structure.is_empty() {
+        match structure.byte()? {
+            b @ (b'{' | b'}' | b'[' | b']' | b',' | b':') => decoded.push(b),
+            SPACE => decoded.push(b' '),
+            NEWLINE => decoded.push(b'\n'),
+            KEY => {
+                let index = structure.varint()? as usize;
+                let key = keys.get(index).ok_or_else(|| {
+                    HlcError::DecompressionError("Malformed JSON data: key index out of range".to_string())
+                })?;
+                decoded.push(b'"');
+                decoded.extend_from_slice(key);
+                decoded.push(b'"');
+                context = index + 1;
+            }
+            STRING => {
+                let len = text.len()?;
+                decoded.push(b'"');
+                decoded.extend_from_slice(text.bytes(len)?);
+                decoded.push(b'"');
+            }
+            NUMBER => {
+                let decimals = structure.byte()?;
+                if decimals as usize > MAX_DIGITS {
+                    return Err(HlcError::DecompressionError("Malformed JSON data: too many decimals".to_string()));
+                }
+                let value = previous[context].wrapping_add(unzigzag(numbers.varint()?));
+                previous[context] = value;
+                decoded.extend_from_slice(format_fixed(value, decimals).as_bytes());
+            }
+            NUMBER_TEXT | WHITESPACE | RAW => {
+                let len = text.len()?;
+                decoded.extend_from_slice(text.bytes(len)?);
+            }
+            op => {
+                if let Some(&(_, literal)) = LITERALS.iter().find(|&&(literal_op, _)| literal_op == op) {
+                    decoded.extend_from_slice(literal);
+                } else {
+                    return Err(HlcError::DecompressionError(format!("Malformed JSON data: unknown op {}", op)));
+                }
+            }
+        }
+    }
+    if !numbers.is_empty() || !text.is_empty() {
+        return Err(HlcError::DecompressionError("Malformed JSON data: trailing bytes".to_string()));
+    }
+    Ok(decoded)
+}
+
+fn push_text(structure: &mut Vec<u8>, text: &mut Vec<u8>, op: u8, bytes: &[u8]) {
+    structure.push(op);
+    write_varint(text, bytes.len() as u64);
+    text.extend_from_slice(bytes);
+}
+
+/// The token starting at `pos` and where it ends, or `None` for a byte that
+/// starts no token
+///
+/// JSON strings cannot hold a raw line break, so a quote not closed on its
+/// line starts no string. This also puts data starting inside a string, such
+/// as a chunk of NDJSON, back in step at the next line. Quotes before where an
+/// unclosed string ran to are not scanned again (`open_until`)
+fn next_token(data: &[u8], pos: usize, open_until: &mut usize) -> Option<(Token, usize)> {
+    let b = data[pos];
+    match b {
+        b'{' | b'}' | b'[' | b']' | b',' | b':' => Some((Token::Punctuation(b), pos + 1)),
+        b' ' | b'\t' | b'\r' | b'\n' => {
+            let len = data[pos..].iter().take_while(|b| matches!(b, b' ' | b'\t' | b'\r' | b'\n')).count();
+            Some((Token::Whitespace, pos + len))
+        }
+        b'"' if pos >= *open_until => {
+            let mut end = pos + 1;
+            while end < data.len() && data[end] != b'"' && data[end] != b'\n' {
+                end += if data[end] == b'\\' { 2 } else { 1 };
+            }
+            if end >= data.len() || data[end] != b'"' {
+                *open_until = end;
+                return None;
+            }
+            let end = end + 1;
+            let after = data[end..].iter().position(|b| !b.is_ascii_whitespace()).map(|skip| end + skip);
+            let is_key = after.is_some_and(|after| data[after] == b':');
+            Some((Token::String(is_key), end))
+        }
+        b'-' | b'0'..=b'9' => {
+            let len = data[pos..].iter().take_while(|b| matches!(b, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')).count();
+            Some((Token::Number, pos + len))
+        }
+        _ => LITERALS
+            .iter()
+            .find(|(_, literal)| data[pos..].starts_with(literal))
+            .map(|&(op, literal)| (Token::Literal(op), pos + literal.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let encoded = encode(data);
+        assert_eq!(decode(&encoded).unwrap(), data);
+        encoded
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let mut events = Vec::new();
+        for i in 0..500 {
+            let line = format!(
+                "{{\"ts\":{},\"user\":\"u{}\",\"score\":{}.{:02},\"ok\":{},\"tags\":[\"a\",\"b\"],\"ref\":null}}\n",
+                1_700_000_000 + i * 3, i % 17, i % 40, i % 100, i % 3 == 0
+            );
+            events.extend_from_slice(line.as_bytes());
+        }
+        let encoded = roundtrip(&events);
+        assert!(encoded.len() < events.len());
+        // Six keys, each stored once
+        assert_eq!(encoded[0], 6);
+
+        // Pretty-printed, with escapes, exponents, odd number formatting and
+        // whitespace, and text that is not JSON at all
+        roundtrip(b"{\n  \"a\\\"b\" : [1.5e3, -0, 007, -0.50, 12345678901234567890],\r\n\t\"c\": \"\\u00e9\\\\\"\n}");
+        roundtrip(b"not json: {\"unterminated\n\"x\", tru, nul, -, 1-2, }}");
+        roundtrip(b"[\"open");
+        roundtrip(b"\"\\\n\"");
+        // Starting inside a string only costs the rest of that line
+        let cut = &events[30..];
+        assert!(roundtrip(cut).len() < encode(&events).len() + 64);
+        roundtrip(&(0u8..=255).collect::<Vec<u8>>());
+        assert!(encode(b"").is_empty());
+    }
+
+    #[test]
+    fn test_is_ndjson() {
+        assert!(is_ndjson(b"{\"a\":1}\n{\"a\":2}\n\n  {\"a\":3}  \n{\"a\""));
+        assert!(!is_ndjson(b"{\"a\":1}\n"));
+        assert!(!is_ndjson(b"{\n  \"a\": 1,\n  \"b\": 2\n}\n"));
+        assert!(!is_ndjson(b"a,b,c\n1,2,3\n4,5,6\n"));
+
+        assert!(decode(&[0, 1, KEY]).is_err());
+        assert!(decode(&[0, 2, KEY, 0, 0]).is_err());
+        assert!(decode(&[0, 1, b'?', 0]).is_err());
+    }
+}
@@ -1,13 +1,31 @@
 pub mod analyzer;
+mod bitio;
+pub mod bwt;
+pub mod csv;
 pub mod delta;
 pub mod dictionary;
 pub mod entropy;
+pub mod float_xor;
+pub mod huffman;
+pub mod json;
+pub mod precomp;
+pub mod rans;
+pub mod reflate;
+pub mod registry;
 pub mod rle;
+pub mod shuffle;
+mod varint;
 
-pub use analyzer::{analyze_chunk, CompressionStrategy};
-pub use entropy::{encode_fast, encode_balanced, encode_max, estimate_compression_ratio};
+pub use analyzer::{analyze_chunk, detect_compressed, Analysis, Candidate, CompressedFormat, ContextEntropy, DataKind, Features, Thresholds};
+pub use registry::{Transform, TransformRegistry, FIRST_USER_TRANSFORM_ID};
+pub use entropy::{Codec, ZstdParams, encode_fast, encode_balanced, encode_max, estimate_compression_ratio};
 
 // Re-export specific functions to avoid naming conflicts
 pub use delta::{encode as delta_encode, decode as delta_decode, encode_advanced as delta_encode_advanced, decode_advanced as delta_decode_advanced};
 pub use rle::{encode as rle_encode, decode as rle_decode};
+pub use float_xor::{encode as float_xor_encode, decode as float_xor_decode};
+pub use huffman::{encode as huffman_encode, decode as huffman_decode};
+pub use rans::{encode as rans_encode, decode as rans_decode};
+pub use bwt::{encode as bwt_encode, decode as bwt_decode};
+pub use shuffle::{encode as shuffle_encode, decode as shuffle_decode, ShuffleMode};
 pub use dictionary::{encode as dict_encode, decode as dict_decode, train_dictionary, Dictionary};
\ No newline at end of file
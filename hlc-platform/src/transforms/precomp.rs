@@ -0,0 +1,538 @@
+//! Precompression of embedded deflate streams
+//! Finds zlib (PDF, most formats), gzip, ZIP/JAR/DOCX entry and PNG image
+//! streams, inflates them and keeps the inflated content instead when
+//! `reflate` rebuilds the original bytes from it exactly; everything else
+//! stays literal. The pipeline then compresses the raw content, which zstd
+//! cannot reach inside the deflate stream. Only streams wholly inside the
+//! data are found, so a stream split across chunks is left as it is
+//! Finding streams needs the `deflate` feature; rebuilding them does not
+//!
+//! Format: [SEGMENTS: u32 LE] then per segment
+//!   [LITERAL LEN: u32 LE][KIND: u8]
+//!   kind 1 (deflate stream) and 2 (PNG IDAT run) add
+//!   [LEVEL: u8][MEM LEVEL: u8][WINDOW BITS: u8][INFLATED LEN: u32 LE]
+//!   kind 2 then adds [ZLIB HEADER: 2][CHUNKS: u32 LE][CHUNK LEN: u32 LE]...
+//! followed by each segment's literal bytes and inflated content in order
+//! A PNG IDAT run is rebuilt with its zlib header and Adler-32 trailer and
+//! split back into chunks with their CRCs; for the other kinds the stream's
+//! wrapper stays in the literal bytes
+
+use crate::error::HlcError;
+use crate::transforms::reflate::{self, DeflateParams};
+
+const KIND_LITERAL: u8 = 0;
+const KIND_DEFLATE: u8 = 1;
+const KIND_PNG: u8 = 2;
+
+#[cfg(feature = "deflate")]
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Streams shorter than this are not worth a segment
+#[cfg(feature = "deflate")]
+const MIN_STREAM_LEN: usize = 64;
+
+/// Largest inflated stream expanded, against decompression bombs
+#[cfg(feature = "deflate")]
+const MAX_INFLATED_LEN: usize = 64 << 20;
+
+enum Stream {
+    Deflate,
+    Png { header: [u8; 2], chunk_lens: Vec<u32> },
+}
+
+struct Segment<'a> {
+    literal: &'a [u8],
+    expanded: Option<Expanded>,
+}
+
+struct Expanded {
+    stream: Stream,
+    params: DeflateParams,
+    inflated: Vec<u8>,
+}
+
+/// Replaces the reproducible deflate streams in `data` with their inflated
+/// content; `None` when there are none (or the `deflate` feature is off)
+pub fn expand(data: &[u8]) -> Option<Vec<u8>> {
+    let segments = scan(data);
+    if segments.len() < 2 {
+        return None;
+    }
+    Some(write(&segments))
+}
+
+/// `expand`, falling back to a single literal segment
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    expand(data).unwrap_or_else(|| write(&[Segment { literal: data, expanded: None }]))
+}
+
+/// Rebuilds the original data, re-deflating every expanded stream
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+    let mut header = Reader { data, pos: 0 };
+    let count = header.u32()? as usize;
+
+    let mut layout = Vec::with_capacity(count.min(data.len()));
+    for _ in 0..count {
+        let literal_len = header.u32()? as usize;
+        let kind = header.u8()?;
+        let expanded = match kind {
+            KIND_LITERAL => None,
+            KIND_DEFLATE | KIND_PNG => {
+                let params = DeflateParams {
+                    level: header.u8()?,
+                    mem_level: header.u8()?,
+                    window_bits: header.u8()?,
+                };
+                if !params.is_valid() {
+                    return Err(malformed("invalid deflate parameters"));
+                }
+                let inflated_len = header.u32()? as usize;
+                let stream = if kind == KIND_PNG {
+                    let zlib_header = [header.u8()?, header.u8()?];
+                    let chunks = header.u32()? as usize;
+                    let chunk_lens = (0..chunks).map(|_| header.u32()).collect::<Result<Vec<_>, _>>()?;
+                    Stream::Png { header: zlib_header, chunk_lens }
+                } else {
+                    Stream::Deflate
+                };
+                Some((stream, params, inflated_len))
+            }
+            kind => return Err(malformed(&format!("unknown segment kind {}", kind))),
+        };
+        layout.push((literal_len, expanded));
+    }
+
+    let mut payload = Reader { data, pos: header.pos };
+    let mut out = Vec::with_capacity(data.len() * 2);
+    for (literal_len, expanded) in layout {
+        out.extend_from_slice(payload.take(literal_len)?);
+        if let Some((stream, params, inflated_len)) = expanded {
+            let inflated = payload.take(inflated_len)?;
+            match stream {
+                Stream::Deflate => out.extend_from_slice(&reflate::deflate(inflated, params)),
+                Stream::Png { header, chunk_lens } => {
+                    let stream = zlib_stream(header, inflated, params);
+                    write_idat_chunks(&mut out, &stream, &chunk_lens)?;
+                }
+            }
+        }
+    }
+    if payload.pos != data.len() {
+        return Err(malformed("trailing bytes"));
+    }
+
+    Ok(out)
+}
+
+fn write(segments: &[Segment]) -> Vec<u8> {
+    let payload_len: usize = segments
+        .iter()
+        .map(|s| s.literal.len() + s.expanded.as_ref().map_or(0, |e| e.inflated.len()))
+        .sum();
+    let mut out = Vec::with_capacity(payload_len + 4 + segments.len() * 16);
+
+    out.extend_from_slice(&(segments.len() as u32).to_le_bytes());
+    for segment in segments {
+        out.extend_from_slice(&(segment.literal.len() as u32).to_le_bytes());
+        let Some(expanded) = &segment.expanded else {
+            out.push(KIND_LITERAL);
+            continue;
+        };
+
+        let params = expanded.params;
+        out.push(match expanded.stream {
+            Stream::Deflate => KIND_DEFLATE,
+            Stream::Png { .. } => KIND_PNG,
+        });
+        out.extend_from_slice(&[params.level, params.mem_level, params.window_bits]);
+        out.extend_from_slice(&(expanded.inflated.len() as u32).to_le_bytes());
+        if let Stream::Png { header, chunk_lens } = &expanded.stream {
+            out.extend_from_slice(header);
+            out.extend_from_slice(&(chunk_lens.len() as u32).to_le_bytes());
+            for len in chunk_lens {
+                out.extend_from_slice(&len.to_le_bytes());
+            }
+        }
+    }
+
+    for segment in segments {
+        out.extend_from_slice(segment.literal);
+        if let Some(expanded) = &segment.expanded {
+            out.extend_from_slice(&expanded.inflated);
+        }
+    }
+    out
+}
+
+/// Splits `data` into literal runs and expanded streams; the last segment is
+/// always a literal one
+#[cfg(feature = "deflate")]
+fn scan(data: &[u8]) -> Vec<Segment<'_>> {
+    let mut scanner = Scanner {
+        inflater: flate2::Decompress::new(false),
+    };
+    let mut segments = Vec::new();
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    while pos < data.len() {
+        match scanner.stream_at(data, pos) {
+            Some((start, end, expanded)) => {
+                segments.push(Segment {
+                    literal: &data[literal_start..start],
+                    expanded: Some(expanded),
+                });
+                literal_start = end;
+                pos = end;
+            }
+            None => pos += 1,
+        }
+    }
+
+    segments.push(Segment {
+        literal: &data[literal_start..],
+        expanded: None,
+    });
+    segments
+}
+
+#[cfg(not(feature = "deflate"))]
+fn scan(data: &[u8]) -> Vec<Segment<'_>> {
+    vec![Segment { literal: data, expanded: None }]
+}
+
+#[cfg(feature = "deflate")]
+struct Scanner {
+    inflater: flate2::Decompress,
+}
+
+#[cfg(feature = "deflate")]
+impl Scanner {
+    /// A reproducible stream starting at `pos` as (start, end, content);
+    /// `start` is past any wrapper header, which stays literal
+    fn stream_at(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize, Expanded)> {
+        let rest = &data[pos..];
+        match rest[0] {
+            b'P' if rest.starts_with(b"PK\x03\x04") => self.zip_entry(data, pos),
+            0x1F if rest.starts_with(&[0x1F, 0x8B, 0x08]) => self.gzip_member(data, pos),
+            0x89 if rest.starts_with(&PNG_SIGNATURE) => self.png_image(data, pos),
+            _ => self.zlib_stream(data, pos),
+        }
+    }
+
+    fn zip_entry(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize, Expanded)> {
+        let header = data.get(pos..pos + 30)?;
+        if u16::from_le_bytes([header[8], header[9]]) != 8 {
+            return None;
+        }
+        let name_len = u16::from_le_bytes([header[26], header[27]]) as usize;
+        let extra_len = u16::from_le_bytes([header[28], header[29]]) as usize;
+        self.raw_stream(data, pos + 30 + name_len + extra_len, 15)
+    }
+
+    fn gzip_member(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize, Expanded)> {
+        let flags = *data.get(pos + 3)?;
+        if flags & 0xE0 != 0 {
+            return None;
+        }
+
+        let mut at = pos + 10;
+        if flags & 0x04 != 0 {
+            let extra = data.get(at..at + 2)?;
+            at += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        // File name and comment are zero-terminated
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                at += data.get(at..)?.iter().position(|&b| b == 0)? + 1;
+            }
+        }
+        if flags & 0x02 != 0 {
+            at += 2;
+        }
+        self.raw_stream(data, at, 15)
+    }
+
+    /// A zlib stream; the Adler-32 trailer must match so that random bytes
+    /// that happen to look like a zlib header are rejected cheaply
+    fn zlib_stream(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize, Expanded)> {
+        let window_bits = zlib_window_bits(data.get(pos..pos + 2)?)?;
+        let (start, end, expanded) = self.raw_stream(data, pos + 2, window_bits)?;
+        let trailer = data.get(end..end + 4)?;
+        (trailer == adler32(&expanded.inflated).to_be_bytes()).then_some((start, end, expanded))
+    }
+
+    /// The IDAT chunks of a PNG image, which together hold one zlib stream
+    fn png_image(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize, Expanded)> {
+        let chunk_at = |at: usize| -> Option<(usize, &[u8])> {
+            let header = data.get(at..at + 8)?;
+            let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            data.get(at..at + 12 + len)?;
+            Some((len, &header[4..8]))
+        };
+
+        let mut at = pos + PNG_SIGNATURE.len();
+        loop {
+            let (len, kind) = chunk_at(at)?;
+            match kind {
+                b"IDAT" => break,
+                b"IEND" => return None,
+                _ => at += 12 + len,
+            }
+        }
+
+        let start = at;
+        let mut stream = Vec::new();
+        let mut chunk_lens = Vec::new();
+        while let Some((len, b"IDAT")) = chunk_at(at) {
+            stream.extend_from_slice(&data[at + 8..at + 8 + len]);
+            chunk_lens.push(len as u32);
+            at += 12 + len;
+        }
+        let end = at;
+
+        let window_bits = zlib_window_bits(stream.get(..2)?)?;
+        let (consumed, inflated) = self.inflate(&stream[2..])?;
+        let deflated = &stream[2..2 + consumed];
+        if stream.len() != 2 + consumed + 4 || stream[2 + consumed..] != adler32(&inflated).to_be_bytes() {
+            return None;
+        }
+        let params = reflate::find_params(&inflated, deflated, window_bits)?;
+
+        // Chunk CRCs are recomputed on decode, so bad ones must stay literal
+        let header = [stream[0], stream[1]];
+        let mut rebuilt = Vec::with_capacity(end - start);
+        write_idat_chunks(&mut rebuilt, &stream, &chunk_lens).ok()?;
+        if rebuilt != data[start..end] {
+            return None;
+        }
+
+        let stream = Stream::Png { header, chunk_lens };
+        Some((start, end, Expanded { stream, params, inflated }))
+    }
+
+    fn raw_stream(&mut self, data: &[u8], start: usize, window_bits: u8) -> Option<(usize, usize, Expanded)> {
+        let (consumed, inflated) = self.inflate(data.get(start..)?)?;
+        if consumed < MIN_STREAM_LEN {
+            return None;
+        }
+        let end = start + consumed;
+        let params = reflate::find_params(&inflated, &data[start..end], window_bits)?;
+        Some((start, end, Expanded { stream: Stream::Deflate, params, inflated }))
+    }
+
+    /// Inflates a raw deflate stream, returning the bytes it spans and its
+    /// content; `None` if it is invalid, truncated or too large
+    fn inflate(&mut self, data: &[u8]) -> Option<(usize, Vec<u8>)> {
+        use flate2::{FlushDecompress, Status};
+
+        self.inflater.reset(false);
+        let mut out = Vec::with_capacity((data.len() * 4).clamp(1024, MAX_INFLATED_LEN));
+        loop {
+            if out.len() == out.capacity() {
+                if out.len() >= MAX_INFLATED_LEN {
+                    return None;
+                }
+                out.reserve(out.len());
+            }
+
+            let (read, written) = (self.inflater.total_in(), out.len());
+            let input = &data[read as usize..];
+            match self.inflater.decompress_vec(input, &mut out, FlushDecompress::None).ok()? {
+                Status::StreamEnd => return Some((self.inflater.total_in() as usize, out)),
+                _ if self.inflater.total_in() == read && out.len() == written => return None,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Window bits from a zlib header, or `None` if the two bytes are not one
+#[cfg(feature = "deflate")]
+fn zlib_window_bits(header: &[u8]) -> Option<u8> {
+    let (cmf, flg) = (header[0], header[1]);
+    let check = u16::from_be_bytes([cmf, flg]);
+    // Deflate, a valid window size, no preset dictionary
+    if cmf & 0x0F != 8 || cmf >> 4 > 7 || flg & 0x20 != 0 || check % 31 != 0 {
+        return None;
+    }
+    Some(((cmf >> 4) + 8).max(9))
+}
+
+fn zlib_stream(header: [u8; 2], inflated: &[u8], params: DeflateParams) -> Vec<u8> {
+    let mut stream = header.to_vec();
+    stream.extend_from_slice(&reflate::deflate(inflated, params));
+    stream.extend_from_slice(&adler32(inflated).to_be_bytes());
+    stream
+}
+
+/// Writes `stream` as consecutive PNG IDAT chunks of the given lengths
+fn write_idat_chunks(out: &mut Vec<u8>, stream: &[u8], chunk_lens: &[u32]) -> Result<(), HlcError> {
+    let mut rest = stream;
+    for &len in chunk_lens {
+        if rest.len() < len as usize {
+            return Err(malformed("IDAT chunks longer than the stream"));
+        }
+        let (body, tail) = rest.split_at(len as usize);
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(b"IDAT");
+        crc.update(body);
+
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(b"IDAT");
+        out.extend_from_slice(body);
+        out.extend_from_slice(&crc.finalize().to_be_bytes());
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err(malformed("IDAT chunks shorter than the stream"));
+    }
+    Ok(())
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // Largest run of bytes before the sums can overflow a u32
+    const NMAX: usize = 5552;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for block in data.chunks(NMAX) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], HlcError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| malformed("truncated data"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, HlcError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, HlcError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+fn malformed(reason: &str) -> HlcError {
+    HlcError::DecompressionError(format!("Malformed precompression data: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "deflate")]
+    fn content(seed: u32) -> Vec<u8> {
+        (0..300)
+            .flat_map(|i| format!("<entry id=\"{}\" value=\"{}\"/>\n", i, (i * seed) % 1009).into_bytes())
+            .collect()
+    }
+
+    #[cfg(feature = "deflate")]
+    fn zlib_params(level: u8) -> DeflateParams {
+        DeflateParams { level, mem_level: 8, window_bits: 15 }
+    }
+
+    /// A blob embedding a zlib stream, a ZIP entry, a gzip member and a PNG
+    /// image split over two IDAT chunks, all made the way zlib makes them
+    #[cfg(feature = "deflate")]
+    fn container() -> Vec<u8> {
+        let mut blob = b"%PDF-1.4 header text ".to_vec();
+
+        let text = content(7);
+        blob.extend_from_slice(&zlib_stream([0x78, 0x9C], &text, zlib_params(6)));
+        blob.extend_from_slice(b" endstream ");
+
+        let entry = content(11);
+        let deflated = reflate::deflate(&entry, zlib_params(9));
+        blob.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00\x08\x00");
+        blob.extend_from_slice(&[0; 16]);
+        blob.extend_from_slice(&9u16.to_le_bytes());
+        blob.extend_from_slice(&0u16.to_le_bytes());
+        blob.extend_from_slice(b"entry.xml");
+        blob.extend_from_slice(&deflated);
+
+        let member = content(13);
+        blob.extend_from_slice(&[0x1F, 0x8B, 0x08, 0x08, 0, 0, 0, 0, 0, 0x03]);
+        blob.extend_from_slice(b"member.txt\0");
+        blob.extend_from_slice(&reflate::deflate(&member, zlib_params(1)));
+        blob.extend_from_slice(&crc32fast::hash(&member).to_le_bytes());
+        blob.extend_from_slice(&(member.len() as u32).to_le_bytes());
+
+        let pixels = content(17);
+        let stream = zlib_stream([0x78, 0xDA], &pixels, zlib_params(9));
+        let split = stream.len() / 3;
+        blob.extend_from_slice(&PNG_SIGNATURE);
+        blob.extend_from_slice(&[0, 0, 0, 0]);
+        blob.extend_from_slice(b"IHDR");
+        blob.extend_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        write_idat_chunks(&mut blob, &stream, &[split as u32, (stream.len() - split) as u32]).unwrap();
+        blob.extend_from_slice(&[0, 0, 0, 0]);
+        blob.extend_from_slice(b"IEND");
+        blob.extend_from_slice(&[0xAE, 0x42, 0x60, 0x82]);
+
+        blob
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_expands_embedded_streams() {
+        let blob = container();
+        let expanded = expand(&blob).unwrap();
+
+        // Four streams plus the trailing literal
+        assert_eq!(u32::from_le_bytes([expanded[0], expanded[1], expanded[2], expanded[3]]), 5);
+        assert!(expanded.windows(16).any(|w| w == &content(11)[..16]));
+        assert_eq!(decode(&expanded).unwrap(), blob);
+
+        // A damaged IDAT checksum keeps the image literal, but nothing is lost
+        let mut damaged = blob.clone();
+        let iend = damaged.len() - 12;
+        damaged[iend - 1] ^= 0xFF;
+        let expanded = expand(&damaged).unwrap();
+        assert_eq!(u32::from_le_bytes([expanded[0], expanded[1], expanded[2], expanded[3]]), 4);
+        assert_eq!(decode(&expanded).unwrap(), damaged);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_unreproducible_streams_stay_literal() {
+        use std::io::Write;
+
+        // Stored blocks are never what zlib emits at levels 1-9
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::none());
+        encoder.write_all(&content(3)).unwrap();
+        let stream = encoder.finish().unwrap();
+
+        assert!(expand(&stream).is_none());
+        assert_eq!(decode(&encode(&stream)).unwrap(), stream);
+    }
+
+    #[test]
+    fn test_malformed_data() {
+        assert_eq!(decode(&encode(b"plain")).unwrap(), b"plain");
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[1, 0, 0, 0, 5, 0, 0, 0, 0]).is_err());
+        assert!(decode(&[1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 8, 15, 0, 0, 0, 0]).is_err());
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
@@ -0,0 +1,220 @@
+//! Order-0 rANS (range asymmetric numeral systems) coder
+//! Reaches fractional bits per symbol, so it beats Huffman on skewed
+//! distributions such as BWT + MTF output where one symbol dominates
+//! Frequencies are normalised to 14 bits and stored with the stream
+//!
+//! Format: [ORIGINAL LEN: u32 LE][SYMBOL COUNT][(SYMBOL, FREQ: u16 LE)...][STATE + STREAM]
+
+use crate::error::HlcError;
+
+const PROB_BITS: u32 = 14;
+const PROB_SCALE: u32 = 1 << PROB_BITS;
+
+/// Lower bound of the normalised state interval
+const RANS_L: u32 = 1 << 23;
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let freqs = normalized_frequencies(data);
+    let mut cumulative = [0u32; 257];
+    for s in 0..256 {
+        cumulative[s + 1] = cumulative[s] + freqs[s];
+    }
+
+    let present: Vec<usize> = (0..256).filter(|&s| freqs[s] > 0).collect();
+    let mut encoded = Vec::with_capacity(data.len() / 2 + 8 + present.len() * 3);
+    encoded.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    // 256 distinct symbols are stored as 0 since an empty table never occurs
+    encoded.push(present.len() as u8);
+    for &s in &present {
+        encoded.push(s as u8);
+        encoded.extend_from_slice(&(freqs[s] as u16).to_le_bytes());
+    }
+
+    // rANS encodes back to front; bytes are collected in reverse and flipped
+    let mut stream = Vec::with_capacity(data.len() / 2);
+    let mut state = RANS_L;
+    for &b in data.iter().rev() {
+        let freq = freqs[b as usize];
+        let limit = ((RANS_L >> PROB_BITS) << 8) * freq;
+        while state >= limit {
+            stream.push(state as u8);
+            state >>= 8;
+        }
+        state = ((state / freq) << PROB_BITS) + (state % freq) + cumulative[b as usize];
+    }
+    stream.extend_from_slice(&state.to_be_bytes());
+    stream.reverse();
+
+    encoded.extend_from_slice(&stream);
+    encoded
+}
+
+/// Decodes a stream from `encode`, rejecting one that claims more than
+/// `max_len` bytes
+///
+/// The length needs an outside bound: a dominant symbol costs next to no
+/// bits, so the stream itself says little about how much it decodes to
+pub fn decode(data: &[u8], max_len: usize) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if data.len() < 5 {
+        return Err(malformed("truncated header"));
+    }
+
+    let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    if len > max_len {
+        return Err(malformed("length exceeds the chunk size"));
+    }
+    let symbol_count = match data[4] {
+        0 => 256,
+        n => n as usize,
+    };
+
+    let table_end = 5 + symbol_count * 3;
+    if data.len() < table_end + 4 {
+        return Err(malformed("truncated frequency table"));
+    }
+
+    let mut freqs = [0u32; 256];
+    for entry in data[5..table_end].chunks_exact(3) {
+        freqs[entry[0] as usize] = u16::from_le_bytes([entry[1], entry[2]]) as u32;
+    }
+
+    let mut cumulative = [0u32; 257];
+    for s in 0..256 {
+        cumulative[s + 1] = cumulative[s] + freqs[s];
+    }
+    if cumulative[256] != PROB_SCALE {
+        return Err(malformed("frequencies do not sum to the probability scale"));
+    }
+
+    let mut slot_symbol = vec![0u8; PROB_SCALE as usize];
+    for s in 0..256 {
+        for slot in cumulative[s]..cumulative[s + 1] {
+            slot_symbol[slot as usize] = s as u8;
+        }
+    }
+
+    let stream = &data[table_end..];
+    let mut state = u32::from_le_bytes([stream[0], stream[1], stream[2], stream[3]]);
+    let mut pos = 4;
+    let mut decoded = Vec::with_capacity(len);
+
+    for _ in 0..len {
+        let slot = state & (PROB_SCALE - 1);
+        let s = slot_symbol[slot as usize] as usize;
+        state = freqs[s] * (state >> PROB_BITS) + slot - cumulative[s];
+        while state < RANS_L {
+            let byte = *stream.get(pos).ok_or_else(|| malformed("truncated stream"))?;
+            state = (state << 8) | byte as u32;
+            pos += 1;
+        }
+        decoded.push(s as u8);
+    }
+
+    Ok(decoded)
+}
+
+/// Scales byte counts to sum to `PROB_SCALE`, keeping every present symbol >= 1
+fn normalized_frequencies(data: &[u8]) -> [u32; 256] {
+    let mut counts = [0u64; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+
+    let total = data.len() as u64;
+    let mut freqs = [0u32; 256];
+    for s in 0..256 {
+        if counts[s] > 0 {
+            freqs[s] = ((counts[s] * PROB_SCALE as u64 / total) as u32).max(1);
+        }
+    }
+
+    // Rounding leaves the sum slightly off; settle the difference on the
+    // largest frequencies, where it costs the least
+    let mut sum: u32 = freqs.iter().sum();
+    while sum != PROB_SCALE {
+        let largest = (0..256).max_by_key(|&s| freqs[s]).unwrap();
+        if sum > PROB_SCALE {
+            let excess = (sum - PROB_SCALE).min(freqs[largest] - 1).max(1);
+            freqs[largest] -= excess;
+            sum -= excess;
+        } else {
+            freqs[largest] += PROB_SCALE - sum;
+            sum = PROB_SCALE;
+        }
+    }
+
+    freqs
+}
+
+fn malformed(reason: &str) -> HlcError {
+    HlcError::DecompressionError(format!("Malformed rANS data: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rans_empty() {
+        let data = vec![];
+        let encoded = encode(&data);
+        let decoded = decode(&encoded, data.len()).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_rans_roundtrip() {
+        let data = b"Hello, world! This is a test string for rANS coding.".repeat(50);
+        let encoded = encode(&data);
+        let decoded = decode(&encoded, data.len()).unwrap();
+        assert_eq!(data, decoded);
+        assert!(encoded.len() < data.len());
+    }
+
+    #[test]
+    fn test_rans_skewed_distribution() {
+        // 98% zeros costs well under one bit per symbol, which Huffman cannot do
+        let data: Vec<u8> = (0..50000).map(|i| if i % 50 == 0 { 7 } else { 0 }).collect();
+        let encoded = encode(&data);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+        assert!(encoded.len() < data.len() / 16);
+    }
+
+    #[test]
+    fn test_rans_all_bytes() {
+        let data: Vec<u8> = (0..20000).map(|i| ((i * 7919) % 256) as u8).collect();
+        let encoded = encode(&data);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rans_many_rare_symbols() {
+        // More distinct symbols than the scale can give proportional slots to
+        let mut data = vec![0u8; 100000];
+        data.extend(0..=255u8);
+        let encoded = encode(&data);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rans_malformed() {
+        assert!(decode(&[5, 0, 0, 0], 5).is_err());
+
+        let mut encoded = encode(b"abcabcabcabd");
+        encoded[7] ^= 0x40; // corrupt a frequency
+        assert!(decode(&encoded, 12).is_err());
+
+        // A corrupted length is rejected before anything is allocated
+        let mut encoded = encode(b"aaaaaaaaaaaa");
+        encoded[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode(&encoded, 12).is_err());
+    }
+}
@@ -0,0 +1,1142 @@
+//! Bit-exact re-creation of zlib deflate streams
+//! A port of the compressor in zlib 1.2.x (deflate.c and trees.c) for the
+//! default strategy at levels 1-9. Precompression inflates deflate streams
+//! found in a chunk and relies on this module to rebuild the original bytes
+//! from the inflated content and the (level, memLevel, windowBits) they were
+//! made with. Any deviation from zlib's match finder or block splitting
+//! changes the output, so the code follows the C closely rather than
+//! idiomatically
+
+use std::sync::OnceLock;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+/// Matches of length 3 are discarded if their distance exceeds this
+const TOO_FAR: usize = 4096;
+const NIL: usize = 0;
+
+const LENGTH_CODES: usize = 29;
+const LITERALS: usize = 256;
+const L_CODES: usize = LITERALS + 1 + LENGTH_CODES;
+const D_CODES: usize = 30;
+const BL_CODES: usize = 19;
+const HEAP_SIZE: usize = 2 * L_CODES + 1;
+const MAX_BITS: usize = 15;
+const MAX_BL_BITS: usize = 7;
+const END_BLOCK: usize = 256;
+const REP_3_6: usize = 16;
+const REPZ_3_10: usize = 17;
+const REPZ_11_138: usize = 18;
+
+const EXTRA_LBITS: [u8; LENGTH_CODES] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const EXTRA_DBITS: [u8; D_CODES] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const EXTRA_BLBITS: [u8; BL_CODES] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 7];
+const BL_ORDER: [usize; BL_CODES] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// zlib's configuration_table: good_length, max_lazy, nice_length, max_chain
+/// and whether the level uses deflate_fast
+const CONFIGS: [(usize, usize, usize, usize, bool); 10] = [
+    (0, 0, 0, 0, false),
+    (4, 4, 8, 4, true),
+    (4, 5, 16, 8, true),
+    (4, 6, 32, 32, true),
+    (4, 4, 16, 16, false),
+    (8, 16, 32, 32, false),
+    (8, 16, 128, 128, false),
+    (8, 32, 128, 256, false),
+    (32, 128, 258, 1024, false),
+    (32, 258, 258, 4096, false),
+];
+
+/// Parameters a zlib deflate stream was produced with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateParams {
+    /// Compression level, 1-9
+    pub level: u8,
+    /// Memory level, 1-9; sets the hash table and symbol buffer sizes
+    pub mem_level: u8,
+    /// Base-two logarithm of the window size, 9-15
+    pub window_bits: u8,
+}
+
+impl DeflateParams {
+    pub fn is_valid(&self) -> bool {
+        (1..=9).contains(&self.level) && (1..=9).contains(&self.mem_level) && (9..=15).contains(&self.window_bits)
+    }
+}
+
+/// Compresses `data` as a raw deflate stream exactly like zlib would with
+/// `params`, the default strategy and a single `Z_FINISH` call
+pub fn deflate(data: &[u8], params: DeflateParams) -> Vec<u8> {
+    let mut deflater = Deflater::new(data, params, None);
+    deflater.run();
+    deflater.out.finish()
+}
+
+/// Whether deflating `data` with `params` reproduces `expected` exactly
+///
+/// Stops at the first block that differs, so wrong guesses are cheap
+pub fn reproduces(data: &[u8], params: DeflateParams, expected: &[u8]) -> bool {
+    let mut deflater = Deflater::new(data, params, Some(expected));
+    deflater.run() && deflater.out.finish() == expected
+}
+
+/// Searches common zlib settings for the ones that reproduce `expected` from
+/// `data`, trying `window_bits` only
+pub fn find_params(data: &[u8], expected: &[u8], window_bits: u8) -> Option<DeflateParams> {
+    const LEVELS: [u8; 9] = [6, 9, 1, 5, 4, 3, 2, 7, 8];
+    const MEM_LEVELS: [u8; 2] = [8, 9];
+
+    MEM_LEVELS.iter().find_map(|&mem_level| {
+        LEVELS.iter().find_map(|&level| {
+            let params = DeflateParams { level, mem_level, window_bits };
+            reproduces(data, params, expected).then_some(params)
+        })
+    })
+}
+
+struct Tables {
+    length_code: [u8; MAX_MATCH - MIN_MATCH + 1],
+    base_length: [u8; LENGTH_CODES],
+    dist_code: [u8; 512],
+    base_dist: [u16; D_CODES],
+    static_ltree: StaticTree<{ L_CODES + 2 }>,
+    static_dtree: StaticTree<D_CODES>,
+}
+
+struct StaticTree<const N: usize> {
+    code: [u16; N],
+    len: [u16; N],
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut length_code = [0u8; MAX_MATCH - MIN_MATCH + 1];
+        let mut base_length = [0u8; LENGTH_CODES];
+        let mut length = 0usize;
+        for code in 0..LENGTH_CODES - 1 {
+            base_length[code] = length as u8;
+            for _ in 0..1 << EXTRA_LBITS[code] {
+                length_code[length] = code as u8;
+                length += 1;
+            }
+        }
+        // Length 258 has its own code even though 257 would fit code 27
+        length_code[length - 1] = (LENGTH_CODES - 1) as u8;
+
+        let mut dist_code = [0u8; 512];
+        let mut base_dist = [0u16; D_CODES];
+        let mut dist = 0usize;
+        for code in 0..16 {
+            base_dist[code] = dist as u16;
+            for _ in 0..1 << EXTRA_DBITS[code] {
+                dist_code[dist] = code as u8;
+                dist += 1;
+            }
+        }
+        dist >>= 7;
+        for code in 16..D_CODES {
+            base_dist[code] = (dist << 7) as u16;
+            for _ in 0..1 << (EXTRA_DBITS[code] - 7) {
+                dist_code[256 + dist] = code as u8;
+                dist += 1;
+            }
+        }
+
+        let mut bl_count = [0u16; MAX_BITS + 1];
+        let mut ltree_len = [0u16; L_CODES + 2];
+        for (n, len) in ltree_len.iter_mut().enumerate() {
+            *len = match n {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            };
+            bl_count[*len as usize] += 1;
+        }
+        let mut ltree_code = [0u16; L_CODES + 2];
+        gen_codes(&mut ltree_code, &ltree_len, L_CODES + 1, &bl_count);
+
+        let mut dtree_code = [0u16; D_CODES];
+        for (n, code) in dtree_code.iter_mut().enumerate() {
+            *code = bi_reverse(n as u32, 5) as u16;
+        }
+
+        Tables {
+            length_code,
+            base_length,
+            dist_code,
+            base_dist,
+            static_ltree: StaticTree { code: ltree_code, len: ltree_len },
+            static_dtree: StaticTree { code: dtree_code, len: [5; D_CODES] },
+        }
+    })
+}
+
+fn d_code(dist: usize) -> usize {
+    let tables = tables();
+    if dist < 256 {
+        tables.dist_code[dist] as usize
+    } else {
+        tables.dist_code[256 + (dist >> 7)] as usize
+    }
+}
+
+fn bi_reverse(mut code: u32, len: usize) -> u32 {
+    let mut res = 0;
+    for _ in 0..len {
+        res = (res << 1) | (code & 1);
+        code >>= 1;
+    }
+    res
+}
+
+/// Assigns canonical codes to the lengths in `len[..=max_code]`
+fn gen_codes(code: &mut [u16], len: &[u16], max_code: usize, bl_count: &[u16; MAX_BITS + 1]) {
+    let mut next_code = [0u16; MAX_BITS + 1];
+    let mut value = 0u32;
+    for bits in 1..=MAX_BITS {
+        value = (value + bl_count[bits - 1] as u32) << 1;
+        next_code[bits] = value as u16;
+    }
+    for n in 0..=max_code {
+        let bits = len[n] as usize;
+        if bits == 0 {
+            continue;
+        }
+        code[n] = bi_reverse(next_code[bits] as u32, bits) as u16;
+        next_code[bits] = next_code[bits].wrapping_add(1);
+    }
+}
+
+/// A dynamic Huffman tree; `dl` holds the parent ("dad") of a node while the
+/// tree is built and its code length afterwards, as the union does in zlib
+struct Tree {
+    freq: Vec<u32>,
+    dl: Vec<u16>,
+    code: Vec<u16>,
+}
+
+impl Tree {
+    fn new(size: usize) -> Self {
+        Self {
+            freq: vec![0; size],
+            dl: vec![0; size],
+            code: vec![0; size],
+        }
+    }
+}
+
+struct TreeDesc {
+    static_len: Option<&'static [u16]>,
+    extra: &'static [u8],
+    extra_base: usize,
+    elems: usize,
+    max_length: usize,
+}
+
+/// Heap and bit-length counters shared by the three trees of a block
+struct Huffman {
+    heap: [usize; HEAP_SIZE],
+    heap_len: usize,
+    heap_max: usize,
+    depth: [u8; HEAP_SIZE],
+    bl_count: [u16; MAX_BITS + 1],
+    opt_len: i64,
+    static_len: i64,
+}
+
+impl Huffman {
+    fn smaller(&self, tree: &Tree, n: usize, m: usize) -> bool {
+        tree.freq[n] < tree.freq[m] || (tree.freq[n] == tree.freq[m] && self.depth[n] <= self.depth[m])
+    }
+
+    fn pqdownheap(&mut self, tree: &Tree, mut k: usize) {
+        let v = self.heap[k];
+        let mut j = k << 1;
+        while j <= self.heap_len {
+            if j < self.heap_len && self.smaller(tree, self.heap[j + 1], self.heap[j]) {
+                j += 1;
+            }
+            if self.smaller(tree, v, self.heap[j]) {
+                break;
+            }
+            self.heap[k] = self.heap[j];
+            k = j;
+            j <<= 1;
+        }
+        self.heap[k] = v;
+    }
+
+    /// Builds the Huffman tree for the frequencies in `tree`, sets its code
+    /// lengths and codes, and returns the largest code with a nonzero frequency
+    fn build_tree(&mut self, tree: &mut Tree, desc: &TreeDesc) -> usize {
+        let elems = desc.elems;
+        let mut max_code: isize = -1;
+
+        self.heap_len = 0;
+        self.heap_max = HEAP_SIZE;
+        for n in 0..elems {
+            if tree.freq[n] != 0 {
+                self.heap_len += 1;
+                self.heap[self.heap_len] = n;
+                max_code = n as isize;
+                self.depth[n] = 0;
+            } else {
+                tree.dl[n] = 0;
+            }
+        }
+
+        // The format needs at least two codes of nonzero length
+        while self.heap_len < 2 {
+            let node = if max_code < 2 {
+                max_code += 1;
+                max_code as usize
+            } else {
+                0
+            };
+            self.heap_len += 1;
+            self.heap[self.heap_len] = node;
+            tree.freq[node] = 1;
+            self.depth[node] = 0;
+            self.opt_len -= 1;
+            if let Some(stree) = desc.static_len {
+                self.static_len -= stree[node] as i64;
+            }
+        }
+        let max_code = max_code as usize;
+
+        for n in (1..=self.heap_len / 2).rev() {
+            self.pqdownheap(tree, n);
+        }
+
+        let mut node = elems;
+        loop {
+            let n = self.heap[1];
+            self.heap[1] = self.heap[self.heap_len];
+            self.heap_len -= 1;
+            self.pqdownheap(tree, 1);
+            let m = self.heap[1];
+
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = n;
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = m;
+
+            tree.freq[node] = tree.freq[n] + tree.freq[m];
+            self.depth[node] = self.depth[n].max(self.depth[m]) + 1;
+            tree.dl[n] = node as u16;
+            tree.dl[m] = node as u16;
+
+            self.heap[1] = node;
+            node += 1;
+            self.pqdownheap(tree, 1);
+            if self.heap_len < 2 {
+                break;
+            }
+        }
+        self.heap_max -= 1;
+        self.heap[self.heap_max] = self.heap[1];
+
+        self.gen_bitlen(tree, desc, max_code);
+        let bl_count = self.bl_count;
+        gen_codes(&mut tree.code, &tree.dl, max_code, &bl_count);
+        max_code
+    }
+
+    /// Computes code lengths from the tree shape, limiting them to the
+    /// descriptor's maximum the way zlib does, and accumulates block sizes
+    fn gen_bitlen(&mut self, tree: &mut Tree, desc: &TreeDesc, max_code: usize) {
+        let max_length = desc.max_length;
+        self.bl_count = [0; MAX_BITS + 1];
+
+        tree.dl[self.heap[self.heap_max]] = 0;
+        let mut overflow = 0i32;
+
+        for h in self.heap_max + 1..HEAP_SIZE {
+            let n = self.heap[h];
+            let mut bits = tree.dl[tree.dl[n] as usize] as usize + 1;
+            if bits > max_length {
+                bits = max_length;
+                overflow += 1;
+            }
+            tree.dl[n] = bits as u16;
+
+            if n > max_code {
+                continue;
+            }
+            self.bl_count[bits] += 1;
+            let xbits = if n >= desc.extra_base {
+                desc.extra[n - desc.extra_base] as i64
+            } else {
+                0
+            };
+            let f = tree.freq[n] as i64;
+            self.opt_len += f * (bits as i64 + xbits);
+            if let Some(stree) = desc.static_len {
+                self.static_len += f * (stree[n] as i64 + xbits);
+            }
+        }
+        if overflow == 0 {
+            return;
+        }
+
+        loop {
+            let mut bits = max_length - 1;
+            while self.bl_count[bits] == 0 {
+                bits -= 1;
+            }
+            self.bl_count[bits] -= 1;
+            self.bl_count[bits + 1] += 2;
+            self.bl_count[max_length] -= 1;
+            overflow -= 2;
+            if overflow <= 0 {
+                break;
+            }
+        }
+
+        let mut h = HEAP_SIZE;
+        for bits in (1..=max_length).rev() {
+            let mut n = self.bl_count[bits];
+            while n != 0 {
+                h -= 1;
+                let m = self.heap[h];
+                if m > max_code {
+                    continue;
+                }
+                if tree.dl[m] as usize != bits {
+                    self.opt_len += (bits as i64 - tree.dl[m] as i64) * tree.freq[m] as i64;
+                    tree.dl[m] = bits as u16;
+                }
+                n -= 1;
+            }
+        }
+    }
+}
+
+/// LSB-first bit writer as used by the deflate format
+struct BitWriter {
+    bytes: Vec<u8>,
+    buf: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn send_bits(&mut self, value: u32, length: u32) {
+        self.buf |= (value as u64) << self.bits;
+        self.bits += length;
+        while self.bits >= 8 {
+            self.bytes.push(self.buf as u8);
+            self.buf >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn windup(&mut self) {
+        if self.bits > 0 {
+            self.bytes.push(self.buf as u8);
+        }
+        self.buf = 0;
+        self.bits = 0;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.windup();
+        self.bytes
+    }
+}
+
+struct Deflater<'a> {
+    input: &'a [u8],
+    next_in: usize,
+
+    w_size: usize,
+    w_mask: usize,
+    window_size: usize,
+    window: Vec<u8>,
+    prev: Vec<u16>,
+    head: Vec<u16>,
+    ins_h: usize,
+    hash_mask: usize,
+    hash_shift: usize,
+
+    block_start: isize,
+    match_length: usize,
+    prev_match: usize,
+    match_available: bool,
+    strstart: usize,
+    match_start: usize,
+    lookahead: usize,
+    prev_length: usize,
+    insert: usize,
+
+    max_chain: usize,
+    max_lazy: usize,
+    good_match: usize,
+    nice_match: usize,
+    fast: bool,
+
+    dyn_ltree: Tree,
+    dyn_dtree: Tree,
+    bl_tree: Tree,
+    huffman: Huffman,
+    /// Symbols of the current block as (distance, literal or length - 3)
+    syms: Vec<(u16, u8)>,
+    sym_limit: usize,
+
+    out: BitWriter,
+    expected: Option<&'a [u8]>,
+}
+
+impl<'a> Deflater<'a> {
+    fn new(input: &'a [u8], params: DeflateParams, expected: Option<&'a [u8]>) -> Self {
+        let level = params.level.clamp(1, 9) as usize;
+        let mem_level = params.mem_level.clamp(1, 9) as usize;
+        let w_bits = params.window_bits.clamp(9, 15) as usize;
+        let (good_match, max_lazy, nice_match, max_chain, fast) = CONFIGS[level];
+
+        let w_size = 1 << w_bits;
+        let hash_bits = mem_level + 7;
+        let lit_bufsize = 1 << (mem_level + 6);
+
+        let mut deflater = Self {
+            input,
+            next_in: 0,
+            w_size,
+            w_mask: w_size - 1,
+            window_size: 2 * w_size,
+            // Matches never read past the window; the padding only keeps a
+            // porting slip from turning into a panic
+            window: vec![0; 2 * w_size + MAX_MATCH],
+            prev: vec![0; w_size],
+            head: vec![0; 1 << hash_bits],
+            ins_h: 0,
+            hash_mask: (1 << hash_bits) - 1,
+            hash_shift: hash_bits.div_ceil(MIN_MATCH),
+            block_start: 0,
+            match_length: MIN_MATCH - 1,
+            prev_match: 0,
+            match_available: false,
+            strstart: 0,
+            match_start: 0,
+            lookahead: 0,
+            prev_length: MIN_MATCH - 1,
+            insert: 0,
+            max_chain,
+            max_lazy,
+            good_match,
+            nice_match,
+            fast,
+            dyn_ltree: Tree::new(HEAP_SIZE),
+            dyn_dtree: Tree::new(2 * D_CODES + 1),
+            bl_tree: Tree::new(2 * BL_CODES + 1),
+            huffman: Huffman {
+                heap: [0; HEAP_SIZE],
+                heap_len: 0,
+                heap_max: 0,
+                depth: [0; HEAP_SIZE],
+                bl_count: [0; MAX_BITS + 1],
+                opt_len: 0,
+                static_len: 0,
+            },
+            syms: Vec::with_capacity(lit_bufsize),
+            sym_limit: lit_bufsize - 1,
+            out: BitWriter {
+                bytes: Vec::with_capacity(input.len() / 2 + 64),
+                buf: 0,
+                bits: 0,
+            },
+            expected,
+        };
+        deflater.init_block();
+        deflater
+    }
+
+    /// Compresses the whole input; false if the output diverged from the
+    /// expected stream
+    fn run(&mut self) -> bool {
+        if self.fast {
+            self.deflate_fast()
+        } else {
+            self.deflate_slow()
+        }
+    }
+
+    fn max_dist(&self) -> usize {
+        self.w_size - MIN_LOOKAHEAD
+    }
+
+    fn update_hash(&mut self, c: u8) {
+        self.ins_h = ((self.ins_h << self.hash_shift) ^ c as usize) & self.hash_mask;
+    }
+
+    /// Inserts the string at `pos` into the hash chains and returns the
+    /// previous head of its chain
+    fn insert_string(&mut self, pos: usize) -> usize {
+        self.update_hash(self.window[pos + MIN_MATCH - 1]);
+        let head = self.head[self.ins_h] as usize;
+        self.prev[pos & self.w_mask] = head as u16;
+        self.head[self.ins_h] = pos as u16;
+        head
+    }
+
+    fn slide_hash(&mut self) {
+        let w_size = self.w_size;
+        for entry in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            let m = *entry as usize;
+            *entry = if m >= w_size { (m - w_size) as u16 } else { NIL as u16 };
+        }
+    }
+
+    fn fill_window(&mut self) {
+        let w_size = self.w_size;
+        loop {
+            let mut more = self.window_size - self.lookahead - self.strstart;
+
+            if self.strstart >= w_size + self.max_dist() {
+                self.window.copy_within(w_size..2 * w_size - more, 0);
+                self.match_start = self.match_start.wrapping_sub(w_size);
+                self.strstart -= w_size;
+                self.block_start -= w_size as isize;
+                if self.insert > self.strstart {
+                    self.insert = self.strstart;
+                }
+                self.slide_hash();
+                more += w_size;
+            }
+            if self.next_in == self.input.len() {
+                break;
+            }
+
+            let n = more.min(self.input.len() - self.next_in);
+            let at = self.strstart + self.lookahead;
+            self.window[at..at + n].copy_from_slice(&self.input[self.next_in..self.next_in + n]);
+            self.next_in += n;
+            self.lookahead += n;
+
+            if self.lookahead + self.insert >= MIN_MATCH {
+                let mut pos = self.strstart - self.insert;
+                self.ins_h = self.window[pos] as usize;
+                self.update_hash(self.window[pos + 1]);
+                while self.insert > 0 {
+                    self.update_hash(self.window[pos + MIN_MATCH - 1]);
+                    self.prev[pos & self.w_mask] = self.head[self.ins_h];
+                    self.head[self.ins_h] = pos as u16;
+                    pos += 1;
+                    self.insert -= 1;
+                    if self.lookahead + self.insert < MIN_MATCH {
+                        break;
+                    }
+                }
+            }
+
+            if self.lookahead >= MIN_LOOKAHEAD || self.next_in == self.input.len() {
+                break;
+            }
+        }
+    }
+
+    fn longest_match(&mut self, mut cur_match: usize) -> usize {
+        let mut chain_length = self.max_chain;
+        let scan = self.strstart;
+        let mut best_len = self.prev_length;
+        let mut nice_match = self.nice_match;
+        let limit = if self.strstart > self.max_dist() {
+            self.strstart - self.max_dist()
+        } else {
+            NIL
+        };
+
+        let w = &self.window;
+        let mut scan_end1 = w[scan + best_len - 1];
+        let mut scan_end = w[scan + best_len];
+
+        if self.prev_length >= self.good_match {
+            chain_length >>= 2;
+        }
+        if nice_match > self.lookahead {
+            nice_match = self.lookahead;
+        }
+
+        loop {
+            let m = cur_match;
+            if w[m + best_len] == scan_end && w[m + best_len - 1] == scan_end1 && w[m] == w[scan] && w[m + 1] == w[scan + 1] {
+                // The hash guarantees the third byte matches, so zlib never
+                // compares it
+                let mut len = MIN_MATCH;
+                while len < MAX_MATCH && w[scan + len] == w[m + len] {
+                    len += 1;
+                }
+
+                if len > best_len {
+                    self.match_start = cur_match;
+                    best_len = len;
+                    if len >= nice_match {
+                        break;
+                    }
+                    scan_end1 = w[scan + best_len - 1];
+                    scan_end = w[scan + best_len];
+                }
+            }
+
+            cur_match = self.prev[cur_match & self.w_mask] as usize;
+            if cur_match <= limit {
+                break;
+            }
+            chain_length -= 1;
+            if chain_length == 0 {
+                break;
+            }
+        }
+
+        best_len.min(self.lookahead)
+    }
+
+    fn deflate_fast(&mut self) -> bool {
+        loop {
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window();
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = NIL;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+
+            if hash_head != NIL && self.strstart - hash_head <= self.max_dist() {
+                self.match_length = self.longest_match(hash_head);
+            }
+
+            let bflush;
+            if self.match_length >= MIN_MATCH {
+                bflush = self.tally_dist(self.strstart.wrapping_sub(self.match_start), self.match_length - MIN_MATCH);
+                self.lookahead -= self.match_length;
+
+                if self.match_length <= self.max_lazy && self.lookahead >= MIN_MATCH {
+                    // max_lazy doubles as max_insert_length at the fast levels
+                    self.match_length -= 1;
+                    loop {
+                        self.strstart += 1;
+                        self.insert_string(self.strstart);
+                        self.match_length -= 1;
+                        if self.match_length == 0 {
+                            break;
+                        }
+                    }
+                    self.strstart += 1;
+                } else {
+                    self.strstart += self.match_length;
+                    self.match_length = 0;
+                    self.ins_h = self.window[self.strstart] as usize;
+                    self.update_hash(self.window[self.strstart + 1]);
+                }
+            } else {
+                bflush = self.tally_lit(self.window[self.strstart]);
+                self.lookahead -= 1;
+                self.strstart += 1;
+            }
+
+            if bflush && !self.flush_block(false) {
+                return false;
+            }
+        }
+
+        self.insert = self.strstart.min(MIN_MATCH - 1);
+        self.flush_block(true)
+    }
+
+    fn deflate_slow(&mut self) -> bool {
+        loop {
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window();
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = NIL;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+
+            self.prev_length = self.match_length;
+            self.prev_match = self.match_start;
+            self.match_length = MIN_MATCH - 1;
+
+            if hash_head != NIL && self.prev_length < self.max_lazy && self.strstart - hash_head <= self.max_dist() {
+                self.match_length = self.longest_match(hash_head);
+                if self.match_length <= 5 && self.match_length == MIN_MATCH && self.strstart - self.match_start > TOO_FAR {
+                    self.match_length = MIN_MATCH - 1;
+                }
+            }
+
+            if self.prev_length >= MIN_MATCH && self.match_length <= self.prev_length {
+                let max_insert = self.strstart + self.lookahead - MIN_MATCH;
+                let dist = self.strstart.wrapping_sub(1).wrapping_sub(self.prev_match);
+                let bflush = self.tally_dist(dist, self.prev_length - MIN_MATCH);
+
+                self.lookahead -= self.prev_length - 1;
+                self.prev_length -= 2;
+                loop {
+                    self.strstart += 1;
+                    if self.strstart <= max_insert {
+                        self.insert_string(self.strstart);
+                    }
+                    self.prev_length -= 1;
+                    if self.prev_length == 0 {
+                        break;
+                    }
+                }
+                self.match_available = false;
+                self.match_length = MIN_MATCH - 1;
+                self.strstart += 1;
+
+                if bflush && !self.flush_block(false) {
+                    return false;
+                }
+            } else if self.match_available {
+                let bflush = self.tally_lit(self.window[self.strstart - 1]);
+                if bflush && !self.flush_block(false) {
+                    return false;
+                }
+                self.strstart += 1;
+                self.lookahead -= 1;
+            } else {
+                self.match_available = true;
+                self.strstart += 1;
+                self.lookahead -= 1;
+            }
+        }
+
+        if self.match_available {
+            self.tally_lit(self.window[self.strstart - 1]);
+            self.match_available = false;
+        }
+        self.insert = self.strstart.min(MIN_MATCH - 1);
+        self.flush_block(true)
+    }
+
+    fn tally_lit(&mut self, c: u8) -> bool {
+        self.syms.push((0, c));
+        self.dyn_ltree.freq[c as usize] += 1;
+        self.syms.len() == self.sym_limit
+    }
+
+    fn tally_dist(&mut self, dist: usize, len: usize) -> bool {
+        let tables = tables();
+        self.syms.push((dist as u16, len as u8));
+        self.dyn_ltree.freq[tables.length_code[len] as usize + LITERALS + 1] += 1;
+        self.dyn_dtree.freq[d_code(dist - 1)] += 1;
+        self.syms.len() == self.sym_limit
+    }
+
+    fn init_block(&mut self) {
+        self.dyn_ltree.freq[..L_CODES].fill(0);
+        self.dyn_dtree.freq[..D_CODES].fill(0);
+        self.bl_tree.freq[..BL_CODES].fill(0);
+        self.dyn_ltree.freq[END_BLOCK] = 1;
+        self.huffman.opt_len = 0;
+        self.huffman.static_len = 0;
+        self.syms.clear();
+    }
+
+    /// Emits the current block and checks the output against the expected
+    /// stream so far
+    fn flush_block(&mut self, last: bool) -> bool {
+        let stored = (self.block_start >= 0).then_some(self.block_start as usize);
+        let stored_len = (self.strstart as isize - self.block_start) as usize;
+        self.tr_flush_block(stored, stored_len, last);
+        self.block_start = self.strstart as isize;
+
+        match self.expected {
+            Some(expected) => {
+                let written = &self.out.bytes;
+                written.len() <= expected.len() && expected[..written.len()] == written[..]
+            }
+            None => true,
+        }
+    }
+
+    fn tr_flush_block(&mut self, stored: Option<usize>, stored_len: usize, last: bool) {
+        let tables = tables();
+        let l_desc = TreeDesc {
+            static_len: Some(&tables.static_ltree.len),
+            extra: &EXTRA_LBITS,
+            extra_base: LITERALS + 1,
+            elems: L_CODES,
+            max_length: MAX_BITS,
+        };
+        let d_desc = TreeDesc {
+            static_len: Some(&tables.static_dtree.len),
+            extra: &EXTRA_DBITS,
+            extra_base: 0,
+            elems: D_CODES,
+            max_length: MAX_BITS,
+        };
+
+        let l_max = self.huffman.build_tree(&mut self.dyn_ltree, &l_desc);
+        let d_max = self.huffman.build_tree(&mut self.dyn_dtree, &d_desc);
+        let max_blindex = self.build_bl_tree(l_max, d_max);
+
+        let mut opt_lenb = (self.huffman.opt_len + 3 + 7) >> 3;
+        let static_lenb = (self.huffman.static_len + 3 + 7) >> 3;
+        if static_lenb <= opt_lenb {
+            opt_lenb = static_lenb;
+        }
+
+        let last_bit = last as u32;
+        match stored {
+            Some(start) if stored_len as i64 + 4 <= opt_lenb => {
+                self.out.send_bits(last_bit, 3);
+                self.out.windup();
+                self.out.bytes.extend_from_slice(&(stored_len as u16).to_le_bytes());
+                self.out.bytes.extend_from_slice(&(!(stored_len as u16)).to_le_bytes());
+                self.out.bytes.extend_from_slice(&self.window[start..start + stored_len]);
+            }
+            _ if static_lenb == opt_lenb => {
+                self.out.send_bits((1 << 1) + last_bit, 3);
+                let (lcode, llen) = (&tables.static_ltree.code[..], &tables.static_ltree.len[..]);
+                let (dcode, dlen) = (&tables.static_dtree.code[..], &tables.static_dtree.len[..]);
+                compress_block(&mut self.out, &self.syms, lcode, llen, dcode, dlen);
+            }
+            _ => {
+                self.out.send_bits((2 << 1) + last_bit, 3);
+                self.send_all_trees(l_max + 1, d_max + 1, max_blindex + 1);
+                compress_block(
+                    &mut self.out,
+                    &self.syms,
+                    &self.dyn_ltree.code,
+                    &self.dyn_ltree.dl,
+                    &self.dyn_dtree.code,
+                    &self.dyn_dtree.dl,
+                );
+            }
+        }
+
+        self.init_block();
+        if last {
+            self.out.windup();
+        }
+    }
+
+    /// Counts the code length codes needed to send `tree` into the bit
+    /// length tree's frequencies
+    fn scan_tree(tree: &mut Tree, bl_tree: &mut Tree, max_code: usize) {
+        let mut prevlen: isize = -1;
+        let mut nextlen = tree.dl[0] as isize;
+        let mut count = 0u32;
+        let (mut max_count, mut min_count) = if nextlen == 0 { (138, 3) } else { (7, 4) };
+        tree.dl[max_code + 1] = 0xFFFF;
+
+        for n in 0..=max_code {
+            let curlen = nextlen;
+            nextlen = tree.dl[n + 1] as isize;
+            count += 1;
+            if count < max_count && curlen == nextlen {
+                continue;
+            } else if count < min_count {
+                bl_tree.freq[curlen as usize] += count;
+            } else if curlen != 0 {
+                if curlen != prevlen {
+                    bl_tree.freq[curlen as usize] += 1;
+                }
+                bl_tree.freq[REP_3_6] += 1;
+            } else if count <= 10 {
+                bl_tree.freq[REPZ_3_10] += 1;
+            } else {
+                bl_tree.freq[REPZ_11_138] += 1;
+            }
+            count = 0;
+            prevlen = curlen;
+            (max_count, min_count) = if nextlen == 0 {
+                (138, 3)
+            } else if curlen == nextlen {
+                (6, 3)
+            } else {
+                (7, 4)
+            };
+        }
+    }
+
+    fn send_tree(out: &mut BitWriter, tree: &Tree, bl_tree: &Tree, max_code: usize) {
+        let send_code = |out: &mut BitWriter, c: usize| out.send_bits(bl_tree.code[c] as u32, bl_tree.dl[c] as u32);
+        let mut prevlen: isize = -1;
+        let mut nextlen = tree.dl[0] as isize;
+        let mut count = 0u32;
+        let (mut max_count, mut min_count) = if nextlen == 0 { (138, 3) } else { (7, 4) };
+
+        for n in 0..=max_code {
+            let curlen = nextlen;
+            nextlen = tree.dl[n + 1] as isize;
+            count += 1;
+            if count < max_count && curlen == nextlen {
+                continue;
+            } else if count < min_count {
+                for _ in 0..count {
+                    send_code(out, curlen as usize);
+                }
+            } else if curlen != 0 {
+                if curlen != prevlen {
+                    send_code(out, curlen as usize);
+                    count -= 1;
+                }
+                send_code(out, REP_3_6);
+                out.send_bits(count - 3, 2);
+            } else if count <= 10 {
+                send_code(out, REPZ_3_10);
+                out.send_bits(count - 3, 3);
+            } else {
+                send_code(out, REPZ_11_138);
+                out.send_bits(count - 11, 7);
+            }
+            count = 0;
+            prevlen = curlen;
+            (max_count, min_count) = if nextlen == 0 {
+                (138, 3)
+            } else if curlen == nextlen {
+                (6, 3)
+            } else {
+                (7, 4)
+            };
+        }
+    }
+
+    /// Builds the bit length tree and returns the index in `BL_ORDER` of the
+    /// last code length code to send
+    fn build_bl_tree(&mut self, l_max: usize, d_max: usize) -> usize {
+        Self::scan_tree(&mut self.dyn_ltree, &mut self.bl_tree, l_max);
+        Self::scan_tree(&mut self.dyn_dtree, &mut self.bl_tree, d_max);
+
+        let bl_desc = TreeDesc {
+            static_len: None,
+            extra: &EXTRA_BLBITS,
+            extra_base: 0,
+            elems: BL_CODES,
+            max_length: MAX_BL_BITS,
+        };
+        self.huffman.build_tree(&mut self.bl_tree, &bl_desc);
+
+        let mut max_blindex = BL_CODES - 1;
+        while max_blindex >= 3 {
+            if self.bl_tree.dl[BL_ORDER[max_blindex]] != 0 {
+                break;
+            }
+            max_blindex -= 1;
+        }
+        self.huffman.opt_len += 3 * (max_blindex as i64 + 1) + 5 + 5 + 4;
+        max_blindex
+    }
+
+    fn send_all_trees(&mut self, lcodes: usize, dcodes: usize, blcodes: usize) {
+        self.out.send_bits(lcodes as u32 - 257, 5);
+        self.out.send_bits(dcodes as u32 - 1, 5);
+        self.out.send_bits(blcodes as u32 - 4, 4);
+        for &rank in &BL_ORDER[..blcodes] {
+            self.out.send_bits(self.bl_tree.dl[rank] as u32, 3);
+        }
+        Self::send_tree(&mut self.out, &self.dyn_ltree, &self.bl_tree, lcodes - 1);
+        Self::send_tree(&mut self.out, &self.dyn_dtree, &self.bl_tree, dcodes - 1);
+    }
+}
+
+fn compress_block(out: &mut BitWriter, syms: &[(u16, u8)], lcode: &[u16], llen: &[u16], dcode: &[u16], dlen: &[u16]) {
+    let tables = tables();
+    for &(dist, lc) in syms {
+        if dist == 0 {
+            out.send_bits(lcode[lc as usize] as u32, llen[lc as usize] as u32);
+            continue;
+        }
+
+        let lc = lc as usize;
+        let code = tables.length_code[lc] as usize;
+        out.send_bits(lcode[code + LITERALS + 1] as u32, llen[code + LITERALS + 1] as u32);
+        let extra = EXTRA_LBITS[code] as u32;
+        if extra != 0 {
+            out.send_bits((lc - tables.base_length[code] as usize) as u32, extra);
+        }
+
+        let dist = dist as usize - 1;
+        let code = d_code(dist);
+        out.send_bits(dcode[code] as u32, dlen[code] as u32);
+        let extra = EXTRA_DBITS[code] as u32;
+        if extra != 0 {
+            out.send_bits((dist - tables.base_dist[code] as usize) as u32, extra);
+        }
+    }
+    out.send_bits(lcode[END_BLOCK] as u32, llen[END_BLOCK] as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_text() -> Vec<u8> {
+        (0..40)
+            .flat_map(|i| format!("{}: the quick brown fox jumps over the lazy dog\n", i * i % 97).into_bytes())
+            .collect()
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_zlib_output() {
+        // Raw streams from zlib 1.2.13 (windowBits -15, memLevel 8)
+        let level1 = from_hex(
+            "95945b12c2200c45ff5d4596500284e06e7cd4b7a2ad55ebea9d7107e79b3399cbc9856e29cf432f8fe9b839cb7a68ef9b\
+             ecda474ed3f53e4a7bf5c3fff8b2faceb26dfb4560786278657830c66b667c84f313cc6f508f43fb915d57619c54d8fca2\
+             8c877884760cceafb43d507fe9989e00f36798a7d2f6c3fa54b8af44f3c33c19fa77b62e83f551f8ba1ce6c9508fc2ba39\
+             fd0c611d22f419e07c87f537c8ff00",
+        );
+        let level6 = from_hex(
+            "9594591282301005ff3dc51c01429689b75159058d6c0a9cde2a6ed0df7935d5e97a33d95596b69271ed1ebddca7f47b4b\
+             9d3679aeafcf2ce95b4de7f3703b76295373c959dcb27884309ee58d63f902ceb790df433d0aed17500fc4b181e583813c\
+             f0b7d08e87f3236d0fd41f32d87ec8ef204fa4eda7cb4e6f09e5873c0efa575837581f03b74b218f837a0cac9bd263488f\
+             1bf499c3f90aebef61fe0f",
+        );
+        let text = sample_text();
+
+        let params = |level| DeflateParams { level, mem_level: 8, window_bits: 15 };
+        assert_eq!(deflate(&text, params(1)), level1);
+        assert_eq!(deflate(&text, params(6)), level6);
+        assert!(reproduces(&text, params(9), &level6));
+        assert!(!reproduces(&text, params(1), &level6));
+        assert_eq!(find_params(&text, &level1, 15), Some(params(1)));
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_round_trip_through_inflate() {
+        use std::io::Read;
+
+        // Long enough to slide the window and fill several blocks
+        let mut state = 0x2545_F491u32;
+        let mut data = sample_text().repeat(20);
+        for i in 0..200_000u32 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            data.push(if state & 3 == 0 { (state >> 24) as u8 } else { b"abcde"[(i % 5) as usize] });
+        }
+
+        for (level, mem_level, window_bits) in [(1, 8, 15), (3, 1, 15), (6, 9, 12), (9, 8, 9)] {
+            let params = DeflateParams { level, mem_level, window_bits };
+            let compressed = deflate(&data, params);
+            let mut inflated = Vec::new();
+            flate2::read::DeflateDecoder::new(&compressed[..]).read_to_end(&mut inflated).unwrap();
+            assert_eq!(inflated, data, "{:?}", params);
+            assert!(reproduces(&data, params, &compressed));
+        }
+
+        assert_eq!(deflate(&[], DeflateParams { level: 6, mem_level: 8, window_bits: 15 }), [0x03, 0x00]);
+    }
+}
@@ -0,0 +1,599 @@
+//! Transform trait and registry
+//! Every transform has a stable numeric id that is written to the container,
+//! so a decoder can look up exactly the transforms an archive was built with
+//! Ids below `FIRST_USER_TRANSFORM_ID` are reserved for the built-in transforms;
+//! domain-specific preprocessors register themselves above it
+
+use crate::error::HlcError;
+use crate::transforms::{bwt, csv, delta, dictionary, entropy, float_xor, json, precomp, rle, shuffle};
+use crate::transforms::shuffle::ShuffleMode;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// First id available to user-defined transforms
+pub const FIRST_USER_TRANSFORM_ID: u16 = 256;
+
+// Built-in transform ids; 0 is the entropy stage of a chunk recipe
+pub const RLE_ID: u16 = 1;
+pub const DELTA_ID: u16 = 2;
+pub const DICTIONARY_ID: u16 = 3;
+pub const FLOAT_XOR_ID: u16 = 4;
+pub const SHUFFLE_ID: u16 = 5;
+pub const BWT_ID: u16 = 6;
+pub const PRECOMP_ID: u16 = 7;
+pub const CSV_ID: u16 = 8;
+pub const JSON_ID: u16 = 9;
+
+/// Number of bytes the default `Transform::estimate` trial-compresses
+const ESTIMATE_SAMPLE_SIZE: usize = 16 * 1024;
+
+/// A reversible pre-processing step applied to a chunk before entropy coding
+///
+/// `params` are recorded in the chunk recipe next to the transform id and
+/// handed back to `decode`, so `decode` must invert `encode` exactly from its
+/// input and the same params. Empty params select the transform's defaults
+pub trait Transform: Send + Sync {
+    /// Stable id recorded in the container; never reuse an id for a different format
+    fn id(&self) -> u16;
+
+    fn name(&self) -> &str;
+
+    fn encode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError>;
+
+    fn decode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError>;
+
+    /// Checks `params` when a pipeline pins them, so a bad `--pipeline` is
+    /// rejected up front instead of failing on the first chunk
+    ///
+    /// The default accepts any params
+    fn check_params(&self, _params: &[u8]) -> Result<(), HlcError> {
+        Ok(())
+    }
+
+    /// Expected benefit of applying the transform with default params to
+    /// `data`, as the fraction of the entropy-coded size it saves; 0.0 means
+    /// the transform should be skipped
+    ///
+    /// The default trial-compresses a prefix of the data with and without the
+    /// transform at zstd level 1
+    fn estimate(&self, data: &[u8]) -> f32 {
+        let sample = &data[..data.len().min(ESTIMATE_SAMPLE_SIZE)];
+        if sample.is_empty() {
+            return 0.0;
+        }
+
+        let encoded = match self.encode(sample, &[]) {
+            Ok(encoded) => encoded,
+            Err(_) => return 0.0,
+        };
+        let plain = entropy::encode(sample, 1).map(|c| c.len()).unwrap_or(sample.len());
+        let transformed = entropy::encode(&encoded, 1).map(|c| c.len()).unwrap_or(encoded.len());
+
+        (1.0 - transformed as f32 / plain as f32).max(0.0)
+    }
+}
+
+/// Transforms available to the pipeline, keyed by id
+#[derive(Clone)]
+pub struct TransformRegistry {
+    transforms: BTreeMap<u16, Arc<dyn Transform>>,
+}
+
+impl TransformRegistry {
+    /// Creates a registry holding the built-in transforms
+    pub fn new() -> Self {
+        let mut registry = Self {
+            transforms: BTreeMap::new(),
+        };
+
+        let builtins: [Arc<dyn Transform>; 9] = [
+            Arc::new(RleTransform),
+            Arc::new(DeltaTransform),
+            Arc::new(DictionaryTransform),
+            Arc::new(FloatXorTransform),
+            Arc::new(ShuffleTransform),
+            Arc::new(BwtTransform),
+            Arc::new(PrecompTransform),
+            Arc::new(CsvTransform),
+            Arc::new(JsonTransform),
+        ];
+        for transform in builtins {
+            registry.transforms.insert(transform.id(), transform);
+        }
+
+        registry
+    }
+
+    /// Registers a user-defined transform
+    ///
+    /// Fails if the id is in the reserved built-in range or already taken, or
+    /// if the name is already taken by a transform or names a codec, so that
+    /// pipeline stages always resolve to one thing
+    pub fn register(&mut self, transform: Arc<dyn Transform>) -> Result<(), HlcError> {
+        let id = transform.id();
+        if id < FIRST_USER_TRANSFORM_ID {
+            return Err(HlcError::ConfigError(format!(
+                "Transform id {} is reserved for built-in transforms (user ids start at {})",
+                id, FIRST_USER_TRANSFORM_ID
+            )));
+        }
+        if let Some(existing) = self.transforms.get(&id) {
+            return Err(HlcError::ConfigError(format!(
+                "Transform id {} is already registered to '{}'",
+                id,
+                existing.name()
+            )));
+        }
+        if let Some(existing) = self.find_by_name(transform.name()) {
+            return Err(HlcError::ConfigError(format!(
+                "Transform name '{}' is already registered to id {}",
+                transform.name(),
+                existing.id()
+            )));
+        }
+        if transform.name().parse::<entropy::Codec>().is_ok() {
+            return Err(HlcError::ConfigError(format!(
+                "Transform name '{}' is the name of a codec",
+                transform.name()
+            )));
+        }
+
+        self.transforms.insert(id, transform);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u16) -> Option<&Arc<dyn Transform>> {
+        self.transforms.get(&id)
+    }
+
+    /// Looks up a transform an archive needs, failing clearly when it is missing
+    pub fn require(&self, id: u16) -> Result<&Arc<dyn Transform>, HlcError> {
+        self.get(id).ok_or(HlcError::UnregisteredTransform(id))
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&Arc<dyn Transform>> {
+        self.transforms.values().find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+
+    /// User-defined transforms in id order
+    pub fn user_transforms(&self) -> impl Iterator<Item = &Arc<dyn Transform>> {
+        self.transforms.range(FIRST_USER_TRANSFORM_ID..).map(|(_, t)| t)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = u16> + '_ {
+        self.transforms.keys().copied()
+    }
+}
+
+impl Default for TransformRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TransformRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.transforms.iter().map(|(id, t)| (id, t.name())))
+            .finish()
+    }
+}
+
+pub struct RleTransform;
+
+impl Transform for RleTransform {
+    fn id(&self) -> u16 {
+        RLE_ID
+    }
+
+    fn name(&self) -> &str {
+        "rle"
+    }
+
+    fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(rle::encode(data))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(rle::decode(data))
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 0)
+    }
+}
+
+/// Delta coding; params: [STRIDE] in bytes (1, 2 or 4), default 1
+pub struct DeltaTransform;
+
+impl DeltaTransform {
+    fn stride(params: &[u8]) -> Result<usize, HlcError> {
+        match params.first().copied().unwrap_or(1) {
+            stride @ (1 | 2 | 4) => Ok(stride as usize),
+            stride => Err(HlcError::TransformError(format!("Unsupported delta stride {}", stride))),
+        }
+    }
+}
+
+impl Transform for DeltaTransform {
+    fn id(&self) -> u16 {
+        DELTA_ID
+    }
+
+    fn name(&self) -> &str {
+        "delta"
+    }
+
+    fn encode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(delta::encode_advanced(data, Self::stride(params)?))
+    }
+
+    fn decode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(delta::decode_advanced(data, Self::stride(params)?))
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 1)?;
+        Self::stride(params).map(|_| ())
+    }
+}
+
+pub struct DictionaryTransform;
+
+impl Transform for DictionaryTransform {
+    fn id(&self) -> u16 {
+        DICTIONARY_ID
+    }
+
+    fn name(&self) -> &str {
+        "dictionary"
+    }
+
+    fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(dictionary::encode(data))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(dictionary::decode(data))
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 0)
+    }
+}
+
+/// XOR float coding; params: [WIDTH] (4 or 8), default 8
+/// The width is also stored in the stream, so decoding ignores the params
+pub struct FloatXorTransform;
+
+impl Transform for FloatXorTransform {
+    fn id(&self) -> u16 {
+        FLOAT_XOR_ID
+    }
+
+    fn name(&self) -> &str {
+        "float_xor"
+    }
+
+    fn encode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(float_xor::encode(data, params.first().copied().unwrap_or(8) as usize))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        float_xor::decode(data)
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 1)?;
+        match params.first() {
+            None | Some(4 | 8) => Ok(()),
+            Some(width) => Err(HlcError::TransformError(format!("Unsupported float width {}", width))),
+        }
+    }
+}
+
+/// Byte/bit shuffle; params: [WIDTH, MODE], default 4-byte byte shuffle
+/// Width and mode are also stored in the stream, so decoding ignores the params
+pub struct ShuffleTransform;
+
+impl Transform for ShuffleTransform {
+    fn id(&self) -> u16 {
+        SHUFFLE_ID
+    }
+
+    fn name(&self) -> &str {
+        "shuffle"
+    }
+
+    fn encode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        let width = params.first().copied().unwrap_or(4) as usize;
+        let mode = match params.get(1) {
+            Some(&id) => ShuffleMode::from_id(id).ok_or_else(|| {
+                HlcError::TransformError(format!("Unknown shuffle mode {}", id))
+            })?,
+            None => ShuffleMode::Byte,
+        };
+        Ok(shuffle::encode(data, width, mode))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        shuffle::decode(data)
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 2)?;
+        if params.first() == Some(&0) {
+            return Err(HlcError::TransformError("Shuffle width must be at least 1".to_string()));
+        }
+        match params.get(1) {
+            Some(&id) if ShuffleMode::from_id(id).is_none() => {
+                Err(HlcError::TransformError(format!("Unknown shuffle mode {}", id)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct BwtTransform;
+
+impl Transform for BwtTransform {
+    fn id(&self) -> u16 {
+        BWT_ID
+    }
+
+    fn name(&self) -> &str {
+        "bwt"
+    }
+
+    fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(bwt::encode(data))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        bwt::decode(data)
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 0)
+    }
+}
+
+/// Expands embedded deflate streams (see `precomp`); decoding re-deflates
+/// them bit-exactly
+pub struct PrecompTransform;
+
+impl Transform for PrecompTransform {
+    fn id(&self) -> u16 {
+        PRECOMP_ID
+    }
+
+    fn name(&self) -> &str {
+        "precomp"
+    }
+
+    fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(precomp::encode(data))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        precomp::decode(data)
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 0)
+    }
+}
+
+pub struct CsvTransform;
+
+impl Transform for CsvTransform {
+    fn id(&self) -> u16 {
+        CSV_ID
+    }
+
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    /// params: [DELIMITER]; without one the delimiter is detected, falling
+    /// back to a comma
+    fn encode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        let delimiter = match params.first() {
+            Some(&delimiter) => delimiter,
+            None => csv::detect_delimiter(data).unwrap_or(b','),
+        };
+        Ok(csv::encode(data, delimiter))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        csv::decode(data)
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 1)
+    }
+}
+
+pub struct JsonTransform;
+
+impl Transform for JsonTransform {
+    fn id(&self) -> u16 {
+        JSON_ID
+    }
+
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        Ok(json::encode(data))
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        json::decode(data)
+    }
+
+    fn check_params(&self, params: &[u8]) -> Result<(), HlcError> {
+        no_more_params(params, 0)
+    }
+}
+
+/// Rejects params beyond the first `max` a built-in transform takes
+fn no_more_params(params: &[u8], max: usize) -> Result<(), HlcError> {
+    if params.len() > max {
+        return Err(HlcError::TransformError(format!("Takes at most {} params, got {}", max, params.len())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct XorMask;
+
+    impl Transform for XorMask {
+        fn id(&self) -> u16 {
+            300
+        }
+
+        fn name(&self) -> &str {
+            "xor_mask"
+        }
+
+        fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+            Ok(data.iter().map(|b| b ^ 0x5A).collect())
+        }
+
+        fn decode(&self, data: &[u8], params: &[u8]) -> Result<Vec<u8>, HlcError> {
+            self.encode(data, params)
+        }
+    }
+
+    #[test]
+    fn test_builtin_transforms_roundtrip() {
+        let registry = TransformRegistry::new();
+        let data: Vec<u8> = (0u32..2000).flat_map(|i| (i / 3).to_le_bytes()).collect();
+
+        for id in registry.ids() {
+            let transform = registry.require(id).unwrap();
+            let encoded = transform.encode(&data, &[]).unwrap();
+            assert_eq!(transform.decode(&encoded, &[]).unwrap(), data, "{}", transform.name());
+        }
+        assert_eq!(registry.user_transforms().count(), 0);
+    }
+
+    #[test]
+    fn test_builtin_params() {
+        let data: Vec<u8> = (0u32..1000).flat_map(|i| (i * 5).to_le_bytes()).collect();
+
+        for stride in [1u8, 2, 4] {
+            let encoded = DeltaTransform.encode(&data, &[stride]).unwrap();
+            assert_eq!(DeltaTransform.decode(&encoded, &[stride]).unwrap(), data);
+        }
+        assert!(DeltaTransform.encode(&data, &[3]).is_err());
+
+        let encoded = ShuffleTransform.encode(&data, &[8, 1]).unwrap();
+        assert_eq!(ShuffleTransform.decode(&encoded, &[8, 1]).unwrap(), data);
+        assert!(ShuffleTransform.encode(&data, &[4, 9]).is_err());
+    }
+
+    #[test]
+    fn test_register_user_transform() {
+        let mut registry = TransformRegistry::new();
+        registry.register(Arc::new(XorMask)).unwrap();
+
+        assert_eq!(registry.require(300).unwrap().name(), "xor_mask");
+        assert_eq!(registry.find_by_name("XOR_MASK").unwrap().id(), 300);
+        assert_eq!(registry.user_transforms().count(), 1);
+
+        // Duplicate ids are rejected
+        assert!(registry.register(Arc::new(XorMask)).is_err());
+    }
+
+    #[test]
+    fn test_reserved_ids_rejected() {
+        let mut registry = TransformRegistry::new();
+        assert!(registry.register(Arc::new(RleTransform)).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_names_rejected() {
+        struct Named(&'static str);
+
+        impl Transform for Named {
+            fn id(&self) -> u16 {
+                400
+            }
+
+            fn name(&self) -> &str {
+                self.0
+            }
+
+            fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+                Ok(data.to_vec())
+            }
+
+            fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+                Ok(data.to_vec())
+            }
+        }
+
+        let mut registry = TransformRegistry::new();
+        registry.register(Arc::new(XorMask)).unwrap();
+        for name in ["delta", "Xor_Mask", "zstd", "LZMA"] {
+            assert!(matches!(registry.register(Arc::new(Named(name))), Err(HlcError::ConfigError(_))), "{}", name);
+        }
+        registry.register(Arc::new(Named("columns"))).unwrap();
+    }
+
+    #[test]
+    fn test_missing_transform_error() {
+        let registry = TransformRegistry::new();
+        match registry.require(999) {
+            Err(HlcError::UnregisteredTransform(999)) => {}
+            other => panic!("unexpected result: {:?}", other.map(|t| t.name().to_string())),
+        }
+    }
+
+    /// Appends incompressible noise, so it can only make things worse
+    struct AppendNoise;
+
+    impl Transform for AppendNoise {
+        fn id(&self) -> u16 {
+            301
+        }
+
+        fn name(&self) -> &str {
+            "append_noise"
+        }
+
+        fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+            let mut out = data.to_vec();
+            let mut state = 0x1234_5678u32;
+            out.extend((0..1024).map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            }));
+            Ok(out)
+        }
+
+        fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+            Ok(data[..data.len().saturating_sub(1024)].to_vec())
+        }
+    }
+
+    #[test]
+    fn test_default_estimate() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i * i / 7) as u8).collect();
+        assert!(RleTransform.estimate(&[]) == 0.0);
+        assert_eq!(AppendNoise.estimate(&data), 0.0);
+
+        let text = b"ordinary text ".repeat(100);
+        let estimate = BwtTransform.estimate(&text);
+        assert!((0.0..=1.0).contains(&estimate));
+    }
+}
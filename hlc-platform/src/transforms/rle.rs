@@ -1,6 +1,6 @@
-/// Run-Length Encoding implementation optimized for zero sequences
-/// Format: For zero runs: [0x00][COUNT], for non-zero bytes: [BYTE]
-/// This is particularly effective for sparse data with many zero sequences
+//! Run-Length Encoding implementation optimized for zero sequences
+//! Format: For zero runs: [0x00][COUNT], for non-zero bytes: [BYTE]
+//! This is particularly effective for sparse data with many zero sequences
 
 pub fn encode(data: &[u8]) -> Vec<u8> {
     if data.is_empty() {
@@ -46,9 +46,7 @@ pub fn decode(data: &[u8]) -> Vec<u8> {
             if i + 1 < data.len() {
                 let count = data[i + 1] as usize;
                 // Add 'count' zeros
-                for _ in 0..count {
-                    decoded.push(0);
-                }
+                decoded.resize(decoded.len() + count, 0);
                 i += 2;
             } else {
                 // Malformed data - treat as single zero
@@ -0,0 +1,203 @@
+//! Byte and bit shuffling for typed arrays (Blosc style)
+//! Byte shuffle transposes N-byte elements so all byte-0s come first, then all
+//! byte-1s, and so on; bit shuffle does the same at the level of bit planes
+//! Neither changes the size of the data, they only group similar bytes so the
+//! entropy stage can find them
+//!
+//! Format: [MODE][WIDTH][SHUFFLED ELEMENTS][UNSHUFFLED REMAINDER]
+
+use crate::error::HlcError;
+
+/// Size of the fixed header: mode byte + element width byte
+const HEADER_SIZE: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShuffleMode {
+    #[default]
+    Byte,
+    Bit,
+}
+
+impl ShuffleMode {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            ShuffleMode::Byte => 0,
+            ShuffleMode::Bit => 1,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(ShuffleMode::Byte),
+            1 => Some(ShuffleMode::Bit),
+            _ => None,
+        }
+    }
+}
+
+pub fn encode(data: &[u8], width: usize, mode: ShuffleMode) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let width = width.clamp(1, u8::MAX as usize);
+    let mut encoded = Vec::with_capacity(data.len() + HEADER_SIZE);
+    encoded.push(mode.id());
+    encoded.push(width as u8);
+
+    match mode {
+        ShuffleMode::Byte => byte_shuffle(data, width, &mut encoded),
+        ShuffleMode::Bit => bit_shuffle(data, width, &mut encoded),
+    }
+
+    encoded
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, HlcError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if data.len() < HEADER_SIZE {
+        return Err(HlcError::DecompressionError(
+            "Malformed shuffle data: truncated header".to_string(),
+        ));
+    }
+
+    let mode = ShuffleMode::from_id(data[0]).ok_or_else(|| {
+        HlcError::DecompressionError(format!("Malformed shuffle data: unknown mode {}", data[0]))
+    })?;
+    let width = data[1] as usize;
+    if width == 0 {
+        return Err(HlcError::DecompressionError(
+            "Malformed shuffle data: zero element width".to_string(),
+        ));
+    }
+
+    let payload = &data[HEADER_SIZE..];
+    let mut decoded = Vec::with_capacity(payload.len());
+    match mode {
+        ShuffleMode::Byte => byte_unshuffle(payload, width, &mut decoded),
+        ShuffleMode::Bit => bit_unshuffle(payload, width, &mut decoded),
+    }
+
+    Ok(decoded)
+}
+
+fn byte_shuffle(data: &[u8], width: usize, out: &mut Vec<u8>) {
+    let count = data.len() / width;
+
+    for lane in 0..width {
+        out.extend((0..count).map(|i| data[i * width + lane]));
+    }
+    out.extend_from_slice(&data[count * width..]);
+}
+
+fn byte_unshuffle(data: &[u8], width: usize, out: &mut Vec<u8>) {
+    let count = data.len() / width;
+    let start = out.len();
+    out.resize(start + count * width, 0);
+
+    for lane in 0..width {
+        for i in 0..count {
+            out[start + i * width + lane] = data[lane * count + i];
+        }
+    }
+    out.extend_from_slice(&data[count * width..]);
+}
+
+/// Bit shuffle works on groups of 8 elements so every bit plane fills whole bytes;
+/// leftover elements and trailing bytes are copied through unchanged
+fn bit_shuffle(data: &[u8], width: usize, out: &mut Vec<u8>) {
+    let count = (data.len() / width) / 8 * 8;
+    let plane_len = count / 8;
+    let start = out.len();
+    out.resize(start + count * width, 0);
+
+    for i in 0..count {
+        for byte in 0..width {
+            let value = data[i * width + byte];
+            for bit in 0..8 {
+                let plane = byte * 8 + bit;
+                out[start + plane * plane_len + i / 8] |= ((value >> bit) & 1) << (i % 8);
+            }
+        }
+    }
+    out.extend_from_slice(&data[count * width..]);
+}
+
+fn bit_unshuffle(data: &[u8], width: usize, out: &mut Vec<u8>) {
+    let count = (data.len() / width) / 8 * 8;
+    let plane_len = count / 8;
+    let start = out.len();
+    out.resize(start + count * width, 0);
+
+    for plane in 0..width * 8 {
+        let (byte, bit) = (plane / 8, plane % 8);
+        for i in 0..count {
+            let value = (data[plane * plane_len + i / 8] >> (i % 8)) & 1;
+            out[start + i * width + byte] |= value << bit;
+        }
+    }
+    out.extend_from_slice(&data[count * width..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffle_empty() {
+        let data = vec![];
+        let encoded = encode(&data, 4, ShuffleMode::Byte);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_byte_shuffle_layout() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let encoded = encode(&data, 4, ShuffleMode::Byte);
+
+        // Header, then byte-0s, byte-1s, byte-2s, byte-3s, then the leftover byte
+        assert_eq!(encoded, vec![0, 4, 1, 5, 2, 6, 3, 7, 4, 8, 9]);
+        assert_eq!(decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_bit_shuffle_roundtrip() {
+        let data: Vec<u8> = (0u32..1001).flat_map(|i| (i * 3).to_le_bytes()).collect();
+        let encoded = encode(&data, 4, ShuffleMode::Bit);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(data, decoded);
+        assert_eq!(encoded.len(), data.len() + HEADER_SIZE);
+    }
+
+    #[test]
+    fn test_shuffle_odd_lengths() {
+        for len in [1usize, 7, 15, 63, 65, 129] {
+            let data: Vec<u8> = (0..len).map(|i| (i * 31) as u8).collect();
+            for mode in [ShuffleMode::Byte, ShuffleMode::Bit] {
+                for width in [2, 4, 8] {
+                    let encoded = encode(&data, width, mode);
+                    assert_eq!(decode(&encoded).unwrap(), data);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_shuffle_helps_entropy_stage() {
+        let data: Vec<u8> = (0u32..20000).flat_map(|i| (i * 7).to_le_bytes()).collect();
+        let plain = zstd::encode_all(&data[..], 5).unwrap();
+        let shuffled = zstd::encode_all(&encode(&data, 4, ShuffleMode::Byte)[..], 5).unwrap();
+        assert!(shuffled.len() < plain.len());
+    }
+
+    #[test]
+    fn test_shuffle_malformed() {
+        assert!(decode(&[0]).is_err());
+        assert!(decode(&[7, 4, 1, 2]).is_err());
+        assert!(decode(&[0, 0, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! LEB128 varints and a byte cursor shared by the structure-aware transforms
+
+use crate::error::HlcError;
+
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Maps signed values to unsigned ones with small magnitudes staying small
+pub(crate) fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub(crate) fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Reads varints and byte runs from encoded data, failing with `what` in the
+/// error when the data runs out
+pub(crate) struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+    what: &'static str,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(data: &'a [u8], what: &'static str) -> Self {
+        Self { data, pos: 0, what }
+    }
+
+    fn truncated(&self) -> HlcError {
+        HlcError::DecompressionError(format!("Malformed {} data: truncated", self.what))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, HlcError> {
+        let byte = *self.data.get(self.pos).ok_or_else(|| self.truncated())?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, HlcError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(HlcError::DecompressionError(format!("Malformed {} data: varint too long", self.what)))
+    }
+
+    /// A varint used as a length or count, bounded by the bytes left so
+    /// corrupt input cannot demand huge allocations
+    pub(crate) fn len(&mut self) -> Result<usize, HlcError> {
+        let value = self.varint()?;
+        if value > (self.data.len() - self.pos) as u64 {
+            return Err(self.truncated());
+        }
+        Ok(value as usize)
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], HlcError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or_else(|| self.truncated())?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_roundtrip() {
+        let values = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX];
+        let mut out = Vec::new();
+        for &value in &values {
+            write_varint(&mut out, value);
+        }
+        let mut cursor = Cursor::new(&out, "varint");
+        for &value in &values {
+            assert_eq!(cursor.varint().unwrap(), value);
+        }
+        assert!(cursor.is_empty());
+        assert!(cursor.byte().is_err());
+
+        for value in [0, 1, -1, 63, -64, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert_eq!(zigzag(-1), 1);
+    }
+}
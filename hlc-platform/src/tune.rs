@@ -0,0 +1,257 @@
+//! Fitting the analyzer's thresholds to a corpus
+//! Every chunk of the corpus is encoded in full with the candidate chains of
+//! each kind the analyzer could name for it, so how any setting of the
+//! thresholds would have done is known exactly. The thresholds are then fitted
+//! one at a time over a grid, repeatedly, keeping a change only when it makes
+//! the corpus smaller, so a fitted model never does worse than the defaults
+//! on the corpus it was fitted to
+
+use crate::chunk::split_for_config;
+use crate::config::HlcConfig;
+use crate::error::HlcError;
+use crate::recipe::{Recipe, RecipeStep};
+use crate::transforms::analyzer::{self, ContextEntropy, DataKind, Features, Thresholds};
+use crate::transforms::entropy::{self, Codec};
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Most passes over all thresholds before the fit stops
+const MAX_ROUNDS: usize = 4;
+
+/// Kinds the analyzer can name without a hint; binary data is further split
+/// by delta stride
+const KINDS: [DataKind; 7] = [
+    DataKind::Text,
+    DataKind::FloatArray { width: 4 },
+    DataKind::FloatArray { width: 8 },
+    DataKind::IntArray { width: 2 },
+    DataKind::IntArray { width: 4 },
+    DataKind::IntArray { width: 8 },
+    DataKind::Random,
+];
+
+/// Outcome of fitting thresholds to a corpus
+#[derive(Debug, Clone)]
+pub struct TuneReport {
+    pub thresholds: Thresholds,
+    pub files: usize,
+    pub chunks: usize,
+    pub original_size: u64,
+    /// Corpus size with the default thresholds
+    pub default_size: u64,
+    /// Corpus size with the fitted thresholds
+    pub tuned_size: u64,
+    /// Corpus size if every chunk got its best kind; no thresholds do better
+    pub best_size: u64,
+}
+
+/// What one chunk measured, and its size under every decision
+struct Sample {
+    features: Features,
+    entropy: ContextEntropy,
+    record_width: Option<usize>,
+    /// Encoded size for each kind, with the delta stride for binary data
+    outcomes: Vec<(DataKind, usize, u64)>,
+}
+
+impl Sample {
+    fn size(&self, thresholds: &Thresholds) -> u64 {
+        let kind = thresholds.kind(&self.features, &self.entropy);
+        let stride = match kind {
+            DataKind::Binary => analyzer::delta_stride(&self.features, self.record_width, thresholds),
+            _ => 0,
+        };
+        self.outcomes
+            .iter()
+            .find(|&&(k, s, _)| k == kind && s == stride)
+            .map_or(0, |&(_, _, size)| size)
+    }
+
+    fn best_size(&self) -> u64 {
+        self.outcomes.iter().map(|&(_, _, size)| size).min().unwrap_or(0)
+    }
+}
+
+/// Fits the analyzer's thresholds to every file under `corpus`, chunked and
+/// encoded as `config` says
+pub fn tune(corpus: &Path, config: &HlcConfig) -> Result<TuneReport, HlcError> {
+    let files = corpus_files(corpus)?;
+    if files.is_empty() {
+        return Err(HlcError::ConfigError(format!("No files found in corpus '{}'", corpus.display())));
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads)
+        .build()
+        .map_err(|e| HlcError::ThreadPoolError(e.to_string()))?;
+
+    let mut samples = Vec::new();
+    let mut original_size = 0;
+    for path in &files {
+        let data = fs::read(path)?;
+        if data.is_empty() {
+            continue;
+        }
+        original_size += data.len() as u64;
+        let chunks = split_for_config(&data, config);
+        let measured: Result<Vec<Sample>, HlcError> =
+            pool.install(|| chunks.par_iter().map(|chunk| measure(&chunk.data, config)).collect());
+        samples.extend(measured?);
+    }
+
+    let total = |thresholds: &Thresholds| samples.iter().map(|s| s.size(thresholds)).sum::<u64>();
+    let default_size = total(&Thresholds::default());
+
+    let mut thresholds = Thresholds::default();
+    let mut tuned_size = default_size;
+    for _ in 0..MAX_ROUNDS {
+        let mut improved = false;
+        for field in 0..thresholds.fields_mut().len() {
+            let name = thresholds.fields_mut()[field].0;
+            for value in grid(name) {
+                let mut candidate = thresholds;
+                *candidate.fields_mut()[field].1 = value;
+                let size = total(&candidate);
+                if size < tuned_size {
+                    thresholds = candidate;
+                    tuned_size = size;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    Ok(TuneReport {
+        thresholds,
+        files: files.len(),
+        chunks: samples.len(),
+        original_size,
+        default_size,
+        tuned_size,
+        best_size: samples.iter().map(Sample::best_size).sum(),
+    })
+}
+
+/// Values tried for each threshold, in hundredths to keep model files tidy
+fn grid(name: &str) -> impl Iterator<Item = f32> {
+    let (low, high, step) = match name {
+        "text_fraction" => (50, 99, 1),
+        "float_fraction" => (50, 100, 1),
+        "lane_entropy_ratio" => (30, 100, 1),
+        "random_entropy" => (600, 800, 5),
+        _ => (50, 100, 1),
+    };
+    (low..=high).step_by(step).map(|hundredths| hundredths as f32 / 100.0)
+}
+
+/// Every regular file under `dir`, in path order
+///
+/// Symlinks to files are followed, symlinks to directories are not, so a link
+/// loop cannot make the walk recurse forever
+fn corpus_files(dir: &Path) -> Result<Vec<PathBuf>, HlcError> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() || (file_type.is_symlink() && path.is_file()) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Measures a chunk and encodes it in full with the candidate chains of every
+/// kind, each chain once
+fn measure(data: &[u8], config: &HlcConfig) -> Result<Sample, HlcError> {
+    let windows = analyzer::sample_windows(data);
+    let entropy = analyzer::context_entropy(&windows);
+    let features = Features::measure(data);
+    let record_width = analyzer::detect_record_width(windows[0]);
+    let text_kind = analyzer::text_kind(windows[0]);
+
+    let mut decisions: Vec<(DataKind, usize)> = KINDS.iter().map(|&kind| (kind, 0)).collect();
+    decisions.extend([1, 2, 4].map(|stride| (DataKind::Binary, stride)));
+
+    let mut encoded: Vec<(Vec<RecipeStep>, u64)> = Vec::new();
+    let mut outcomes = Vec::with_capacity(decisions.len());
+    for (kind, stride) in decisions {
+        let record_width = if kind == DataKind::Random || kind.is_text() { None } else { record_width };
+        // Text is refined further before its chains are proposed
+        let chains_kind = if kind == DataKind::Text { text_kind } else { kind };
+        let mut best = data.len() as u64;
+        for steps in analyzer::candidate_chains(&features, chains_kind, record_width, stride.max(1), config.mode) {
+            let size = match encoded.iter().find(|(known, _)| *known == steps) {
+                Some(&(_, size)) => size,
+                None => {
+                    let size = encoded_size(data, &steps, config)?;
+                    encoded.push((steps, size));
+                    size
+                }
+            };
+            best = best.min(size);
+        }
+        outcomes.push((kind, stride, best));
+    }
+
+    Ok(Sample { features, entropy, record_width, outcomes })
+}
+
+/// Size of `data` after `steps` and the smaller of zstd and rANS; chains that
+/// do not fit the chunk count as storing it
+fn encoded_size(data: &[u8], steps: &[RecipeStep], config: &HlcConfig) -> Result<u64, HlcError> {
+    let recipe = Recipe { steps: steps.to_vec() };
+    let transformed = match recipe.encode(data, &config.registry) {
+        Ok(transformed) => transformed,
+        Err(HlcError::TransformError(_)) => return Ok(data.len() as u64),
+        Err(e) => return Err(e),
+    };
+    let zstd = entropy::encode(&transformed, config.entropy_level)?.len();
+    let rans = entropy::encode_with(Codec::Rans, &transformed, config.entropy_level)?.len();
+    Ok(zstd.min(rans).min(transformed.len()) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tune_never_loses_to_defaults() {
+        let dir = tempfile::TempDir::new().unwrap();
+        // A text file and, in a subdirectory, a column of counters
+        let text = b"id,name,value\n1,alpha,0.5\n2,beta,0.25\n".repeat(2000);
+        let counters: Vec<u8> = (0u32..20_000).flat_map(|i| (i * 5).to_le_bytes()).collect();
+        fs::write(dir.path().join("rows.csv"), &text).unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("nested").join("counters.bin"), &counters).unwrap();
+
+        let config = HlcConfig::default().with_chunk_size(32 * 1024);
+        let report = tune(dir.path(), &config).unwrap();
+        assert_eq!(report.files, 2);
+        assert!(report.chunks >= 2);
+        assert!(report.best_size <= report.tuned_size);
+        assert!(report.tuned_size <= report.default_size);
+        assert!(report.default_size <= report.original_size);
+        report.thresholds.validate().unwrap();
+
+        // A link back to the corpus root is skipped rather than walked forever
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path(), dir.path().join("nested").join("loop")).unwrap();
+            assert_eq!(corpus_files(dir.path()).unwrap().len(), 2);
+        }
+
+        let empty = tempfile::TempDir::new().unwrap();
+        assert!(matches!(tune(empty.path(), &config), Err(HlcError::ConfigError(_))));
+    }
+}
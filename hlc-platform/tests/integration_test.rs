@@ -2,11 +2,12 @@
 //! 
 //! These tests verify the complete end-to-end functionality of the compression system.
 
-use hlc::{HlcConfig, HlcMode, ChecksumType};
+use hlc::{HlcConfig, HlcMode, ChecksumType, Codec, DataKind, HlcError, Transform, TransformRegistry};
 use hlc::{compress_data, decompress_data, validate_data, get_compression_info, estimate_compression_ratio};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
-use tempfile::{NamedTempFile, TempDir};
+use std::io::{BufReader, BufWriter, Cursor};
+use std::sync::Arc;
+use tempfile::TempDir;
 
 #[test]
 fn test_basic_compression_roundtrip() {
@@ -187,7 +188,8 @@ fn test_data_corruption_detection() {
     
     // Corrupt the data by flipping some bits
     if compressed.len() > 10 {
-        compressed[compressed.len() - 5] ^= 0xFF;
+        let len = compressed.len();
+        compressed[len - 5] ^= 0xFF;
         
         // Should detect corruption during decompression
         assert!(decompress_data(&compressed).is_err());
@@ -222,7 +224,7 @@ fn test_compression_estimation() {
 #[test]
 fn test_various_data_types() {
     let test_cases = vec![
-        ("Text data", b"The quick brown fox jumps over the lazy dog. ".repeat(100).into_bytes()),
+        ("Text data", b"The quick brown fox jumps over the lazy dog. ".repeat(100)),
         ("Binary data", (0..1000u16).flat_map(|i| i.to_le_bytes()).collect()),
         ("Sparse data", {
             let mut data = vec![0u8; 1000];
@@ -359,4 +361,632 @@ fn test_version_info() {
     
     println!("HLC Version: {}", version_info.version);
     println!("Description: {}", version_info.description);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_float_telemetry_compression() {
+    // Slowly varying sensor readings stored as raw f64 values
+    let telemetry: Vec<u8> = (0..20000)
+        .map(|i| 21.5 + (i as f64 / 500.0).sin() * 0.75)
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+
+    let config = HlcConfig::default();
+    let compressed = compress_data(&telemetry, &config).unwrap();
+    let decompressed = decompress_data(&compressed).unwrap();
+    assert_eq!(telemetry, decompressed);
+
+    // Float XOR and byte shuffling are both trialled; either keeps the values
+    // aligned, and the analyzer takes whichever samples smaller
+    let info = get_compression_info(&compressed).unwrap();
+    assert!(info.chunk_stats.float_xor_chunks + info.chunk_stats.shuffle_chunks > 0);
+
+    let plain_zstd = zstd::encode_all(&telemetry[..], config.entropy_level).unwrap();
+    assert!(compressed.len() < plain_zstd.len());
+}
+
+#[test]
+fn test_integer_array_shuffle() {
+    // Counter-like u32 column with small irregular increments
+    let mut value = 1_000_000u32;
+    let column: Vec<u8> = (0..50000u32)
+        .flat_map(|i| {
+            value += (i * 7919) % 13;
+            value.to_le_bytes()
+        })
+        .collect();
+
+    for mode in [HlcMode::Balanced, HlcMode::Max] {
+        let config = HlcConfig::default().with_mode(mode);
+        let compressed = compress_data(&column, &config).unwrap();
+        assert_eq!(decompress_data(&compressed).unwrap(), column);
+
+        let info = get_compression_info(&compressed).unwrap();
+        assert!(info.chunk_stats.shuffle_chunks > 0);
+
+        let plain_zstd = zstd::encode_all(&column[..], config.entropy_level).unwrap();
+        println!("{:?} shuffle: {} bytes, plain zstd: {} bytes", mode, compressed.len(), plain_zstd.len());
+        assert!(compressed.len() < plain_zstd.len());
+    }
+}
+
+#[test]
+fn test_text_bwt_in_max_mode() {
+    let mut log = Vec::new();
+    for i in 0..3000 {
+        log.extend_from_slice(
+            format!("2024-01-15 10:{:02}:{:02} INFO request id={} path=/api/v1/items status=200\n",
+                    (i / 60) % 60, i % 60, i * 7).as_bytes(),
+        );
+    }
+
+    let max_config = HlcConfig::default().with_mode(HlcMode::Max);
+    let compressed = compress_data(&log, &max_config).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), log);
+
+    let info = get_compression_info(&compressed).unwrap();
+    assert!(info.chunk_stats.bwt_chunks > 0);
+
+    // Balanced mode keeps text on the plain LZ path
+    let balanced = compress_data(&log, &HlcConfig::default()).unwrap();
+    assert_eq!(get_compression_info(&balanced).unwrap().chunk_stats.bwt_chunks, 0);
+}
+
+#[test]
+fn test_native_entropy_coder_after_bwt() {
+    // Real source text: once the BWT has removed the context redundancy there
+    // is little left for LZ matching, so a native coder finishes the chunk
+    let mut source = Vec::new();
+    source.extend_from_slice(include_bytes!("../src/pipeline.rs"));
+    source.extend_from_slice(include_bytes!("../src/container.rs"));
+    source.extend_from_slice(include_bytes!("../src/chunk.rs"));
+    source.extend_from_slice(include_bytes!("../src/cli.rs"));
+    source.extend_from_slice(include_bytes!("../src/lib.rs"));
+
+    let config = HlcConfig::default().with_mode(HlcMode::Max);
+    let compressed = compress_data(&source, &config).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), source);
+
+    let info = get_compression_info(&compressed).unwrap();
+    assert!(info.chunk_stats.bwt_chunks > 0);
+    assert!(info.chunk_stats.huffman_chunks + info.chunk_stats.rans_chunks > 0);
+}
+
+#[test]
+fn test_general_purpose_codecs() {
+    let mut data = Vec::new();
+    for i in 0..5000 {
+        data.extend_from_slice(format!("record {} value={} status=ok\n", i, i % 97).as_bytes());
+    }
+
+    for codec in [Codec::Zstd, Codec::Lz4, Codec::Brotli, Codec::Xz, Codec::Deflate] {
+        let config = HlcConfig::default().with_codec(codec);
+        if !codec.is_available() {
+            assert!(compress_data(&data, &config).is_err());
+            continue;
+        }
+
+        let compressed = compress_data(&data, &config).unwrap();
+        assert_eq!(decompress_data(&compressed).unwrap(), data);
+        assert!(validate_data(&compressed).unwrap());
+        println!("{}: {} bytes", codec.name(), compressed.len());
+        assert!(compressed.len() < data.len() / 2);
+    }
+}
+
+/// Stand-in for a proprietary record format: transposes fixed 12-byte records
+/// into three columns
+struct RecordColumns;
+
+impl Transform for RecordColumns {
+    fn id(&self) -> u16 {
+        1000
+    }
+
+    fn name(&self) -> &str {
+        "record_columns"
+    }
+
+    fn encode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        let records = data.len() / 12;
+        let mut out = Vec::with_capacity(data.len());
+        for field in 0..3 {
+            for record in 0..records {
+                out.extend_from_slice(&data[record * 12 + field * 4..record * 12 + field * 4 + 4]);
+            }
+        }
+        out.extend_from_slice(&data[records * 12..]);
+        Ok(out)
+    }
+
+    fn decode(&self, data: &[u8], _params: &[u8]) -> Result<Vec<u8>, HlcError> {
+        let records = data.len() / 12;
+        let mut out = vec![0u8; records * 12];
+        for field in 0..3 {
+            for record in 0..records {
+                let src = (field * records + record) * 4;
+                out[record * 12 + field * 4..record * 12 + field * 4 + 4].copy_from_slice(&data[src..src + 4]);
+            }
+        }
+        out.extend_from_slice(&data[records * 12..]);
+        Ok(out)
+    }
+
+    fn estimate(&self, data: &[u8]) -> f32 {
+        // The owner knows when the layout applies, so no trial run is needed
+        if data.len() >= 12 { 0.5 } else { 0.0 }
+    }
+}
+
+#[test]
+fn test_user_defined_transform() {
+    let mut data = Vec::new();
+    for i in 0u32..20000 {
+        data.extend_from_slice(&i.to_le_bytes());
+        data.extend_from_slice(&(1_700_000_000 + i * 5).to_le_bytes());
+        data.extend_from_slice(&((i * 37) % 1000).to_le_bytes());
+    }
+
+    let mut registry = TransformRegistry::new();
+    registry.register(Arc::new(RecordColumns)).unwrap();
+    let registry = Arc::new(registry);
+
+    let config = HlcConfig::default().with_registry(registry.clone());
+    let compressed = compress_data(&data, &config).unwrap();
+
+    let (chunks, _) = hlc::container::read_hlc_container(&mut Cursor::new(&compressed)).unwrap();
+    assert!(chunks.iter().any(|c| c.recipe.steps.first().map(|s| s.id) == Some(1000)));
+
+    assert_eq!(hlc::decompress_data_with_registry(&compressed, registry).unwrap(), data);
+
+    // A decoder without the transform registered fails clearly
+    match decompress_data(&compressed) {
+        Err(HlcError::UnregisteredTransform(1000)) => {}
+        other => panic!("expected an unregistered transform error, got {:?}", other.map(|d| d.len())),
+    }
+}
+
+#[test]
+fn test_chunk_recipes() {
+    // Slowly stepping values with long plateaus: both RLE and delta apply
+    let data: Vec<u8> = (0..200_000u32).map(|i| ((i / 16) % 251) as u8).collect();
+    let config = HlcConfig::default().with_mode(HlcMode::Max);
+    let compressed = compress_data(&data, &config).unwrap();
+
+    let (chunks, _) = hlc::container::read_hlc_container(&mut Cursor::new(&compressed)).unwrap();
+    for chunk in chunks.iter().filter(|c| !c.recipe.is_stored()) {
+        // The entropy stage, when used, is always the last step
+        if let Some(pos) = chunk.recipe.steps.iter().position(|s| s.is_entropy()) {
+            assert_eq!(pos, chunk.recipe.steps.len() - 1);
+        }
+
+        for step in chunk.recipe.steps.iter().filter(|s| s.id == hlc::transforms::registry::DELTA_ID) {
+            assert!(matches!(step.params.as_slice(), [1] | [2] | [4]));
+        }
+    }
+
+    assert_eq!(decompress_data(&compressed).unwrap(), data);
+    assert_eq!(get_compression_info(&compressed).unwrap().version, 6);
+}
+
+#[test]
+fn test_pinned_pipeline() {
+    let data: Vec<u8> = (0u32..100_000).flat_map(|i| (i * 3 + i % 7).to_le_bytes()).collect();
+    let pipeline: hlc::Pipeline = "delta:4,shuffle:4,zstd:9".parse().unwrap();
+    let config = HlcConfig::default().with_chunk_size(64 * 1024).with_pipeline(pipeline);
+
+    let compressed = compress_data(&data, &config).unwrap();
+    assert_eq!(compress_data(&data, &config).unwrap(), compressed);
+
+    // Every chunk carries exactly the requested chain
+    let expected = vec![
+        hlc::RecipeStep::new(hlc::transforms::registry::DELTA_ID, vec![4]),
+        hlc::RecipeStep::new(hlc::transforms::registry::SHUFFLE_ID, vec![4]),
+        hlc::RecipeStep::entropy(Codec::Zstd, 9),
+    ];
+    let (chunks, _) = hlc::container::read_hlc_container(&mut Cursor::new(&compressed)).unwrap();
+    assert!(chunks.iter().all(|c| c.recipe.steps == expected));
+    assert_eq!(decompress_data(&compressed).unwrap(), data);
+
+    let bad = HlcConfig::default().with_pipeline("delta:4,nonesuch".parse().unwrap());
+    assert!(matches!(compress_data(&data, &bad), Err(HlcError::ConfigError(_))));
+    let bad_stride = HlcConfig::default().with_pipeline("delta:3".parse().unwrap());
+    assert!(matches!(compress_data(&data, &bad_stride), Err(HlcError::ConfigError(_))));
+}
+
+#[test]
+fn test_exhaustive_search() {
+    let mut data = create_test_data(40_000);
+    data.extend((0u32..8_000).flat_map(|i| (i * i).to_le_bytes()));
+
+    let base = HlcConfig::default().with_chunk_size(32 * 1024);
+    let searched = base.clone().with_search(hlc::SearchBudget::default().with_max_candidates(16));
+
+    let plain = compress_data(&data, &base).unwrap();
+    let compressed = compress_data(&data, &searched).unwrap();
+    assert!(compressed.len() <= plain.len());
+    assert_eq!(decompress_data(&compressed).unwrap(), data);
+
+    // Without a time limit the search is deterministic
+    assert_eq!(compress_data(&data, &searched).unwrap(), compressed);
+}
+
+#[test]
+fn test_content_defined_chunking() {
+    // Aperiodic records, as in a log or database dump
+    let original: Vec<u8> = (0u64..12_000)
+        .flat_map(|i| format!("{},{},{}\n", i, i.wrapping_mul(0x9E37_79B9) % 9973, i * i % 101).into_bytes())
+        .collect();
+    let mut edited = original.clone();
+    edited.splice(5_000..5_000, b"a few inserted bytes".iter().copied());
+
+    let config = HlcConfig::default().with_chunking(hlc::Chunking::content_defined(16 * 1024));
+    let first = compress_data(&original, &config).unwrap();
+    let second = compress_data(&edited, &config).unwrap();
+    assert_eq!(decompress_data(&first).unwrap(), original);
+    assert_eq!(decompress_data(&second).unwrap(), edited);
+
+    // Boundaries resynchronise after the edit, so most chunks are unchanged
+    let checksums = |data: &[u8]| -> Vec<u64> {
+        let (chunks, _) = hlc::container::read_hlc_container(&mut Cursor::new(data)).unwrap();
+        chunks.iter().map(|c| c.original_checksum).collect()
+    };
+    let (before, after) = (checksums(&first), checksums(&second));
+    let shared = after.iter().filter(|c| before.contains(c)).count();
+    assert!(before.len() > 5);
+    assert!(shared + 2 >= before.len());
+}
+
+#[test]
+fn test_chunk_deduplication() {
+    // A disk-image-like layout: the same blocks recur throughout
+    let block_a: Vec<u8> = (0u32..4096).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+    let block_b: Vec<u8> = (0u32..4096).map(|i| (i.wrapping_mul(40_503) >> 7) as u8).collect();
+    let mut data = Vec::new();
+    for i in 0..24 {
+        data.extend_from_slice(if i % 3 == 0 { &block_b } else { &block_a });
+    }
+
+    let config = HlcConfig::default().with_chunk_size(4096).with_dedup(true);
+    let mut compressed = Vec::new();
+    let stats = hlc::compress(&mut Cursor::new(&data), &mut compressed, &config).unwrap();
+    assert_eq!(stats.chunk_stats.reference_chunks, 22);
+    assert_eq!(stats.chunk_stats.dedup_saved_bytes, 22 * 4096);
+    assert_eq!(decompress_data(&compressed).unwrap(), data);
+    assert!(validate_data(&compressed).unwrap());
+
+    let info = get_compression_info(&compressed).unwrap();
+    assert_eq!(info.chunk_stats.reference_chunks, 22);
+
+    // Off by default
+    let without = compress_data(&data, &HlcConfig::default().with_chunk_size(4096)).unwrap();
+    assert_eq!(get_compression_info(&without).unwrap().chunk_stats.reference_chunks, 0);
+    assert!(compressed.len() < without.len());
+    assert_eq!(decompress_data(&without).unwrap(), data);
+}
+
+#[test]
+fn test_chunk_store_snapshots() {
+    let dir = TempDir::new().unwrap();
+    let store = hlc::ChunkStore::open(dir.path().join("store")).unwrap();
+    let config = HlcConfig::default().with_chunking(hlc::Chunking::content_defined(4096));
+
+    let monday: Vec<u8> = (0u32..8_000)
+        .flat_map(|i| format!("{},{},{}\n", i, i % 97, i.wrapping_mul(2_654_435_761)).into_bytes())
+        .collect();
+    let mut tuesday = monday.clone();
+    tuesday.splice(50_000..50_000, b"inserted record\n".iter().copied());
+
+    let first = store.put("monday", &mut Cursor::new(&monday), &config).unwrap();
+    let second = store.put("tuesday", &mut Cursor::new(&tuesday), &config).unwrap();
+    assert_eq!(first.new_chunks, first.total_chunks);
+    assert!(second.new_chunks <= 3);
+
+    let mut restored = Vec::new();
+    store.get("monday", &mut restored, &config).unwrap();
+    assert_eq!(restored, monday);
+
+    store.remove("monday").unwrap();
+    assert!(store.gc().unwrap().removed_chunks <= 3);
+
+    let mut restored = Vec::new();
+    store.get("tuesday", &mut restored, &config).unwrap();
+    assert_eq!(restored, tuesday);
+    assert_eq!(store.manifests().unwrap(), vec!["tuesday".to_string()]);
+}
+
+#[test]
+fn test_context_groups() {
+    // Log-like records whose vocabulary repeats across small chunks
+    let data: Vec<u8> = (0u32..6_000)
+        .flat_map(|i| {
+            let level = ["INFO", "WARN", "DEBUG"][(i.wrapping_mul(2_654_435_761) >> 30) as usize % 3];
+            format!("2024-05-01T12:{:02}:{:02} {} request {} served in {}ms\n", i / 60 % 60, i % 60, level, i, i % 17)
+                .into_bytes()
+        })
+        .collect();
+
+    let cold = HlcConfig::default().with_chunk_size(4096);
+    let primed = cold.clone().with_context_group(8);
+
+    let mut cold_out = Vec::new();
+    hlc::compress(&mut Cursor::new(&data), &mut cold_out, &cold).unwrap();
+    let mut primed_out = Vec::new();
+    let stats = hlc::compress(&mut Cursor::new(&data), &mut primed_out, &primed).unwrap();
+
+    assert!(stats.chunk_stats.prefixed_chunks > 0);
+    assert!(primed_out.len() < cold_out.len());
+    assert_eq!(decompress_data(&primed_out).unwrap(), data);
+    assert!(validate_data(&primed_out).unwrap());
+
+    // Every eighth chunk starts a new group and decodes on its own
+    let (chunks, _) = hlc::container::read_hlc_container(&mut Cursor::new(&primed_out)).unwrap();
+    assert!(chunks.iter().step_by(8).all(|c| c.prefix_len == 0));
+    assert_eq!(hlc::container::context_groups(&chunks).len(), chunks.len().div_ceil(8));
+
+    // A group size set directly on the config is checked too
+    let mut empty_groups = cold.clone();
+    empty_groups.context_group = Some(0);
+    assert!(matches!(
+        hlc::compress(&mut Cursor::new(&data), &mut Vec::new(), &empty_groups),
+        Err(HlcError::ConfigError(_))
+    ));
+}
+
+#[test]
+fn test_zstd_long_distance_and_memory_limit() {
+    // A block repeated far apart, beyond the default window at level 1
+    let block: Vec<u8> = (0u32..65_536).map(|i| (i.wrapping_mul(2_654_435_761) >> 9) as u8).collect();
+    let filler: Vec<u8> = (0u32..4_500_000).map(|i| (i.wrapping_mul(40_503) >> 5) as u8).collect();
+    let data = [block.as_slice(), &filler, block.as_slice()].concat();
+
+    let mut base = HlcConfig::default().with_chunk_size(8 << 20).with_threads(1);
+    base.entropy_level = 1;
+    let long = base.clone().with_zstd_params(hlc::ZstdParams::default().with_long_distance(23));
+
+    let plain = compress_data(&data, &base).unwrap();
+    let compressed = compress_data(&data, &long).unwrap();
+    assert!(compressed.len() + 30_000 < plain.len());
+    assert_eq!(decompress_data(&compressed).unwrap(), data);
+
+    // A decoder capped below the 8 MiB window refuses the frames
+    let capped = HlcConfig::default().with_memory_limit(4 << 20);
+    assert!(matches!(
+        hlc::decompress_data_with_config(&compressed, &capped),
+        Err(HlcError::DecompressionError(_))
+    ));
+    let roomy = HlcConfig::default().with_memory_limit(8 << 20);
+    assert_eq!(hlc::decompress_data_with_config(&compressed, &roomy).unwrap(), data);
+
+    // Compressing with a window the configured limit cannot decode fails early
+    let too_wide = long.clone().with_memory_limit(1 << 20);
+    assert!(matches!(compress_data(&data, &too_wide), Err(HlcError::ConfigError(_))));
+}
+
+#[test]
+fn test_already_compressed_chunks_are_stored() {
+    // Text followed by an existing archive, as in a mixed backup set
+    let text: Vec<u8> = (0u32..40_000).flat_map(|i| format!("file {} size {}\n", i, i * 13 % 4099).into_bytes()).collect();
+    let archive = compress_data(&text, &HlcConfig::default().with_mode(HlcMode::Max)).unwrap();
+    let mut state = 0x1234_5678_9abc_def1u64;
+    let encrypted: Vec<u8> = (0..64 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let data = [text.as_slice(), &archive, &encrypted].concat();
+
+    let config = HlcConfig::default().with_chunk_size(64 * 1024);
+    let compressed = compress_data(&data, &config).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), data);
+
+    let (chunks, _) = hlc::container::read_hlc_container(&mut Cursor::new(&compressed)).unwrap();
+    let last = chunks.last().unwrap();
+    assert!(last.recipe.is_stored());
+    assert_eq!(last.compressed_size, last.original_size);
+    assert!(!chunks[0].recipe.is_stored());
+    assert_eq!(
+        hlc::transforms::analyzer::detect_compressed(&archive, &hlc::Thresholds::default()),
+        Some(hlc::transforms::analyzer::CompressedFormat::Hlc)
+    );
+}
+
+#[cfg(feature = "deflate")]
+#[test]
+fn test_precompression_expands_zip_entries() {
+    use hlc::transforms::reflate::{deflate, DeflateParams};
+
+    // A JAR-like archive: similar entries, each deflated on its own the way
+    // java.util.zip does (zlib level 6)
+    let params = DeflateParams { level: 6, mem_level: 8, window_bits: 15 };
+    let mut archive = Vec::new();
+    for entry in 0..40u32 {
+        let name = format!("com/example/Service{}.class", entry);
+        let body: Vec<u8> = (0..200u32)
+            .flat_map(|i| format!("method{} calls Service{}\n", i, (entry + i) % 40).into_bytes())
+            .collect();
+        let deflated = deflate(&body, params);
+
+        archive.extend_from_slice(b"PK\x03\x04\x14\x00\x08\x08\x08\x00");
+        archive.extend_from_slice(&[0; 16]);
+        archive.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&deflated);
+    }
+
+    let plain = compress_data(&archive, &HlcConfig::default()).unwrap();
+    let config = HlcConfig::default().with_precompression(true);
+    let expanded = compress_data(&archive, &config).unwrap();
+
+    assert_eq!(decompress_data(&expanded).unwrap(), archive);
+    assert!(expanded.len() * 2 < plain.len(), "{} vs {}", expanded.len(), plain.len());
+    assert!(get_compression_info(&expanded).unwrap().chunk_stats.precomp_chunks > 0);
+}
+
+#[test]
+fn test_data_kind_hint() {
+    let values: Vec<u8> = (0u32..100_000).flat_map(|i| (i * 3 + (i >> 4)).to_le_bytes()).collect();
+
+    let hinted = HlcConfig::default().with_hint(DataKind::IntArray { width: 4 });
+    let compressed = compress_data(&values, &hinted).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), values);
+    assert!(compressed.len() < values.len() / 4);
+
+    // A random hint stores chunks as they are, without trials
+    let stored = compress_data(&values, &HlcConfig::default().with_hint(DataKind::Random)).unwrap();
+    let info = get_compression_info(&stored).unwrap();
+    assert_eq!(info.chunk_stats.stored_chunks, info.total_chunks);
+    assert_eq!(decompress_data(&stored).unwrap(), values);
+
+    let invalid = HlcConfig::default().with_hint(DataKind::FloatArray { width: 2 });
+    assert!(matches!(compress_data(&values, &invalid), Err(HlcError::ConfigError(_))));
+}
+
+#[test]
+fn test_strategy_fn_chooses_per_chunk() {
+    use hlc::{ChunkContext, Strategy};
+    use std::sync::Mutex;
+
+    let page_size = 64 * 1024;
+    let data: Vec<u8> = (0u32..64 * 1024).flat_map(|i| (i * 7).to_le_bytes()).collect();
+
+    // Even pages hold integers the caller knows about, odd pages are left alone
+    let pipeline: hlc::Pipeline = "delta:4,zstd".parse().unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_by_fn = Arc::clone(&seen);
+    let config = HlcConfig::default()
+        .with_chunk_size(page_size)
+        .with_strategy_fn(Arc::new(move |ctx: &ChunkContext| {
+            seen_by_fn.lock().unwrap().push((ctx.id, ctx.offset, ctx.data.len()));
+            if ctx.id & 1 == 0 { Strategy::Pipeline(pipeline.clone()) } else { Strategy::Store }
+        }));
+
+    let compressed = compress_data(&data, &config).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), data);
+
+    let info = get_compression_info(&compressed).unwrap();
+    assert_eq!(info.chunk_stats.stored_chunks, info.total_chunks / 2);
+    assert_eq!(info.chunk_stats.delta_chunks, info.total_chunks / 2);
+
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort();
+    assert_eq!(seen.len(), info.total_chunks);
+    for (id, offset, len) in seen {
+        assert_eq!(offset, (id * page_size) as u64);
+        assert_eq!(len, page_size);
+    }
+}
+
+#[test]
+fn test_tuned_thresholds_roundtrip() {
+    let corpus = TempDir::new().unwrap();
+    let floats: Vec<u8> = (0..50_000).flat_map(|i| ((i as f32) * 0.01).cos().to_le_bytes()).collect();
+    let text = b"2024-01-01 12:00:00 INFO request served in 12ms\n".repeat(2000);
+    std::fs::write(corpus.path().join("samples.f32"), &floats).unwrap();
+    std::fs::write(corpus.path().join("service.log"), &text).unwrap();
+
+    let config = HlcConfig::default().with_chunk_size(64 * 1024).with_threads(2);
+    let report = hlc::tune::tune(corpus.path(), &config).unwrap();
+    assert_eq!(report.files, 2);
+    assert!(report.tuned_size <= report.default_size);
+
+    let model = corpus.path().join("model.txt");
+    report.thresholds.save(&model).unwrap();
+    let thresholds = hlc::Thresholds::load(&model).unwrap();
+    assert_eq!(thresholds, report.thresholds);
+
+    let tuned = config.with_thresholds(thresholds);
+    let compressed = compress_data(&floats, &tuned).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), floats);
+}
+
+#[test]
+fn test_explain_reports_each_chunk() {
+    let mut state = 0x9E37_79B9u32;
+    let mut data: Vec<u8> = (0..40_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    data.extend(b"log line: request served\n".repeat(1600));
+    let config = HlcConfig::default().with_chunk_size(40_000);
+
+    let reports = hlc::explain::explain(&data, &config).unwrap();
+    assert_eq!(reports.len(), 2);
+    assert!(reports[0].is_stored());
+    assert_eq!(reports[0].decision, hlc::chunk::Decision::Incompressible);
+    assert!(reports[0].analysis.is_none() && reports[0].tried.is_empty());
+    assert_eq!(reports[0].compressed_size, 40_000);
+    assert_eq!(reports[1].analysis.as_ref().unwrap().kind, DataKind::Text);
+    assert_eq!(reports[1].offset, 40_000);
+    assert!(reports[1].stages.iter().any(|s| s.name.starts_with("zstd")));
+
+    let total: usize = reports.iter().map(|r| r.compressed_size).sum();
+    let archive = compress_data(&data, &config).unwrap();
+    assert!(archive.len() > total);
+
+    let json = hlc::explain::to_json(&reports, &config);
+    assert!(json.contains("\"stored\": true"));
+    assert!(json.contains("\"kind\": \"text\""));
+    assert!(json.contains("\"decision\": \"incompressible\""));
+}
+
+#[test]
+fn test_csv_columnar_layout() {
+    let mut state = 0x2545_F491u32;
+    let mut csv = b"timestamp,sensor,reading,status\r\n".to_vec();
+    for i in 0..20_000u32 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let status = ["ok", "ok", "ok", "warn", "\"fault, retrying\""][(state % 5) as usize];
+        let row = format!("{},sensor-{},{}.{:02},{}\r\n",
+                          1_700_000_000 + i * 15, state % 8, 20 + state % 7, state % 100, status);
+        csv.extend_from_slice(row.as_bytes());
+    }
+
+    let plain = compress_data(&csv, &HlcConfig::default().with_pipeline("zstd".parse().unwrap())).unwrap();
+    let compressed = compress_data(&csv, &HlcConfig::default()).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), csv);
+    assert!(compressed.len() < plain.len(), "{} vs {}", compressed.len(), plain.len());
+    assert!(get_compression_info(&compressed).unwrap().chunk_stats.csv_chunks > 0);
+
+    // Tab-separated text under a hint, with an unterminated last line
+    let tsv = b"a\tb\tc\n1\t2\t3\n4\t5\t6".repeat(500);
+    let hinted = HlcConfig::default().with_hint(DataKind::Csv);
+    let compressed = compress_data(&tsv, &hinted).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), tsv);
+}
+
+#[test]
+fn test_ndjson_structure_split() {
+    // Event records repeating the same 40 keys, some pretty-printed JSON after
+    let mut events = Vec::new();
+    for i in 0..6000u64 {
+        let mut record = format!("{{\"ts\":{},\"event\":\"{}\"", 1_700_000_000_000 + i * 37, ["click", "view"][(i % 2) as usize]);
+        for key in 0..38 {
+            record.push_str(&format!(",\"field_{:02}\":{}", key, (i * (key + 1)) % 1000));
+        }
+        record.push_str("}\n");
+        events.extend_from_slice(record.as_bytes());
+    }
+
+    let plain = compress_data(&events, &HlcConfig::default().with_pipeline("zstd".parse().unwrap())).unwrap();
+    let compressed = compress_data(&events, &HlcConfig::default()).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), events);
+    assert!(compressed.len() < plain.len(), "{} vs {}", compressed.len(), plain.len());
+    let info = get_compression_info(&compressed).unwrap();
+    assert_eq!(info.chunk_stats.json_chunks, info.total_chunks);
+
+    let document = b"{\n  \"name\": \"hlc\",\n  \"sizes\": [1.50, -2e3, 0.1]\n}\n".repeat(300);
+    let hinted = HlcConfig::default().with_hint(DataKind::Json);
+    let compressed = compress_data(&document, &hinted).unwrap();
+    assert_eq!(decompress_data(&compressed).unwrap(), document);
+}